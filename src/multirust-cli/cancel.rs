@@ -0,0 +1,148 @@
+//! Cancelling toolchain changes with Ctrl-C.
+//!
+//! While a `CancelGuard` is alive SIGINT and SIGTERM (Ctrl-C and
+//! Ctrl-Break on Windows) don't kill the process. Instead they ask
+//! the running operation to stop at its next opportunity, which drops
+//! its transaction and rolls back whatever it had changed. Only the
+//! first signal is caught, so a second one kills the process if the
+//! operation doesn't stop.
+
+use multirust::{Error, Result};
+use multirust_utils::raw;
+
+pub struct CancelGuard(imp::Handlers);
+
+impl CancelGuard {
+    pub fn new() -> Self {
+        CancelGuard(imp::install())
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        imp::restore(&self.0);
+    }
+}
+
+/// Runs an operation that changes toolchains so that it can be
+/// cancelled. Whatever error the cancellation caused is reported as
+/// `Error::Cancelled`.
+pub fn cancellable<F: FnOnce() -> Result<()>>(f: F) -> Result<()> {
+    let _guard = CancelGuard::new();
+    match f() {
+        Err(_) if raw::is_cancelled() => Err(Error::Cancelled),
+        r => r,
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use libc;
+    use multirust_utils::raw;
+    use std::mem;
+    use std::ptr;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    // The dispositions that were replaced, to put back afterwards
+    pub struct Handlers(Box<[libc::sigaction; 2]>);
+
+    // Where the handler finds the replaced dispositions. The first
+    // signal puts them back, so a second one isn't caught and kills
+    // the process as usual.
+    static REPLACED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    extern "C" fn handle(_: libc::c_int) {
+        raw::cancel();
+        let replaced = REPLACED.swap(0, Ordering::SeqCst) as *const [libc::sigaction; 2];
+        if !replaced.is_null() {
+            put_back(unsafe { &*replaced });
+        }
+    }
+
+    fn put_back(old: &[libc::sigaction; 2]) {
+        for (signal, old) in SIGNALS.iter().zip(old.iter()) {
+            unsafe {
+                libc::sigaction(*signal, old, ptr::null_mut());
+            }
+        }
+    }
+
+    pub fn install() -> Handlers {
+        unsafe {
+            let mut old: Box<[libc::sigaction; 2]> = Box::new(mem::zeroed());
+            for (signal, old) in SIGNALS.iter().zip(old.iter_mut()) {
+                libc::sigaction(*signal, ptr::null(), old);
+            }
+            REPLACED.store(&*old as *const _ as usize, Ordering::SeqCst);
+
+            // Without SA_RESTART a blocking read is interrupted by the
+            // signal, so a stalled download notices it straight away
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = handle as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            for signal in &SIGNALS {
+                libc::sigaction(*signal, &action, ptr::null_mut());
+            }
+            Handlers(old)
+        }
+    }
+
+    pub fn restore(handlers: &Handlers) {
+        // Unless a signal already put them back
+        if REPLACED.swap(0, Ordering::SeqCst) != 0 {
+            put_back(&handlers.0);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use multirust_utils::raw;
+    use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+    type DWORD = u32;
+    type BOOL = i32;
+    type HandlerRoutine = unsafe extern "system" fn(DWORD) -> BOOL;
+    const CTRL_C_EVENT: DWORD = 0;
+    const CTRL_BREAK_EVENT: DWORD = 1;
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<HandlerRoutine>, add: BOOL) -> BOOL;
+    }
+
+    pub struct Handlers;
+
+    // Whether the handler is still installed. The first Ctrl-C
+    // removes it, so a second one kills the process as usual.
+    static INSTALLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+    unsafe extern "system" fn handle(ctrl_type: DWORD) -> BOOL {
+        match ctrl_type {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+                raw::cancel();
+                if INSTALLED.swap(false, Ordering::SeqCst) {
+                    SetConsoleCtrlHandler(Some(handle), 0);
+                }
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn install() -> Handlers {
+        INSTALLED.store(true, Ordering::SeqCst);
+        unsafe {
+            SetConsoleCtrlHandler(Some(handle), 1);
+        }
+        Handlers
+    }
+
+    pub fn restore(_: &Handlers) {
+        if INSTALLED.swap(false, Ordering::SeqCst) {
+            unsafe {
+                SetConsoleCtrlHandler(Some(handle), 0);
+            }
+        }
+    }
+}
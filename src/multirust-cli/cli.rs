@@ -29,6 +29,7 @@ reinstalled.
                 )
                 .arg(Arg::with_name("toolchain").required(true))
                 .args(install_args())
+                .args(dist_args())
                 .arg_group(install_group())
         )
         .subcommand(
@@ -49,6 +50,7 @@ To remove an existing override use `multirust remove-override`.
                 )
                 .arg(Arg::with_name("toolchain").required(true))
                 .args(install_args())
+                .args(dist_args())
                 .arg_group(install_group())
         )
         .subcommand(
@@ -62,6 +64,7 @@ channels, plus any other installed toolchains.
                 )
                 .arg(Arg::with_name("toolchain").required(false))
                 .args(install_args())
+                .args(dist_args())
                 .arg_group(install_group())
         )
         .subcommand(
@@ -79,6 +82,13 @@ channels, plus any other installed toolchains.
         .subcommand(
             SubCommand::with_name("list-toolchains")
                 .about("List all installed toolchains.")
+                .arg(Arg::with_name("verbose")
+                         .short("v")
+                         .long("verbose")
+                         .help("Show the channel, version, source and size of each toolchain"))
+                .arg(Arg::with_name("json")
+                         .long("json")
+                         .help("Print the verbose listing as JSON"))
         )
         .subcommand(
             SubCommand::with_name("remove-override")
@@ -99,11 +109,90 @@ r"Uninstalls an installed toolchain.
                 )
                 .arg(Arg::with_name("toolchain").required(true))
         )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("Uninstall toolchains that haven't been used recently.")
+                .after_help(
+r"Uninstalls the toolchains that haven't been run for the given time,
+e.g. '30d'. The time is a number followed by s, m, h, d or w. The
+default toolchain and toolchains used by overrides are kept.
+
+Toolchains are recorded as used when they are installed and when
+they are run, either directly or through `multirust run`. Toolchains
+installed by older versions of multirust are tracked from the first
+time this command sees them.
+
+Prompts for confirmation, unless disabled.
+"
+                )
+                .arg(Arg::with_name("older-than")
+                         .long("older-than")
+                         .help("Remove toolchains unused for this long")
+                         .takes_value(true)
+                         .value_name("time")
+                         .required(true))
+                .arg(Arg::with_name("no-prompt").short("y").help("Disable confirmation prompt."))
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check an installed toolchain for damaged files.")
+                .after_help(
+r"Compares the files of each installed component against the hashes
+recorded when it was installed, reporting missing, modified and
+extra files. Components installed by older versions of multirust
+only record their paths, so they are only checked for missing files.
+"
+                )
+                .arg(Arg::with_name("toolchain").required(true))
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about("Reinstall the damaged components of a toolchain.")
+                .after_help(
+r"Downloads and reinstalls the components of an installed toolchain
+that are missing or fail `multirust verify`, using the release the
+toolchain was installed from. The toolchain is not updated.
+"
+                )
+                .arg(Arg::with_name("toolchain").required(true))
+        )
+        .subcommand(
+            SubCommand::with_name("dedup")
+                .about("Share identical files between installed toolchains.")
+                .after_help(
+r"Replaces the files of every installed toolchain with hard links into
+a store of file contents under ~/.multirust, so that files which are
+the same in several toolchains only take up disk space once. Files
+no toolchain uses any more are removed from the store.
+
+Set MULTIRUST_DEDUP=1 to do this for each toolchain as it is
+installed or updated.
+"
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("du")
+                .about("Show the disk space used by toolchains.")
+                .after_help(
+r"Shows the size of each installed toolchain and of each of its
+components, and of the temporary, update hash and shared file
+directories under ~/.multirust. Linked toolchains live elsewhere,
+so they aren't measured. There is no download cache: downloads are
+kept in the temporary directory only until they are installed.
+
+Files shared between toolchains by `multirust dedup` count towards
+the size of each of them, but only once towards the total.
+"
+                )
+                .arg(Arg::with_name("json").long("json").help("Print the sizes as JSON"))
+        )
         .subcommand(
             SubCommand::with_name("list-targets")
                 .about("List targets available to install")
                 .after_help(
-r"List the targets available to an installed toolchain.
+r"List the targets available to a toolchain, marking those that are
+installed. If the toolchain is not installed then the targets offered
+by its release channel are listed, without installing anything.
 "
                 )
                 .arg(Arg::with_name("toolchain").required(true))
@@ -112,22 +201,69 @@ r"List the targets available to an installed toolchain.
             SubCommand::with_name("add-target")
                 .about("Add additional compilation targets to an existing toolchain")
                 .after_help(
-r"Adds the standard library for a given platform to an existing
-installation.
+r"Adds the standard library for the given platforms to an existing
+installation. All targets are added in one transaction, so if any of
+them is unavailable then none are added.
 "
                 )
                 .arg(Arg::with_name("toolchain").required(true))
-                .arg(Arg::with_name("target").required(true))
+                .arg(Arg::with_name("target").multiple(true))
+                .arg(Arg::with_name("all").long("all").help("Add all available targets"))
+                .arg_group(target_group())
         )
         .subcommand(
             SubCommand::with_name("remove-target")
                 .about("Removes compilation targets from an existing toolchain")
                 .after_help(
-r"Removes the standard library for a given platform.
+r"Removes the standard library for the given platforms.
 "
                 )
                 .arg(Arg::with_name("toolchain").required(true))
-                .arg(Arg::with_name("target").required(true))
+                .arg(Arg::with_name("target").multiple(true))
+                .arg(Arg::with_name("all").long("all").help("Remove all installed targets"))
+                .arg_group(target_group())
+        )
+        .subcommand(
+            SubCommand::with_name("component")
+                .about("Modify a toolchain's installed components.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the components available to an installed toolchain.")
+                        .arg(Arg::with_name("toolchain").required(true))
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add components to an existing toolchain.")
+                        .after_help(
+r"Installs optional components from the toolchain's release channel.
+Components are named either 'pkg' for the toolchain's own target,
+or 'pkg-target'. All components are installed in one transaction.
+
+With --installer, installs the components of a locally built
+installer instead. These are kept by later updates of the toolchain,
+and can be removed by name with `multirust component remove`.
+"
+                        )
+                        .arg(Arg::with_name("toolchain").required(true))
+                        .arg(Arg::with_name("component").multiple(true))
+                        .arg(Arg::with_name("installer")
+                             .long("installer")
+                             .help("Install from a local .tar.gz, .tar or directory installer")
+                             .takes_value(true)
+                             .value_name("installer-path")
+                             .number_of_values(1))
+                        .arg_group(ArgGroup::with_name("components")
+                                   .add("component")
+                                   .add("installer")
+                                   .required(true))
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove components from an existing toolchain.")
+                        .arg(Arg::with_name("toolchain").required(true))
+                        .arg(Arg::with_name("component").required(true).multiple(true))
+                )
         )
         .subcommand(
             SubCommand::with_name("run")
@@ -155,6 +291,17 @@ may be any program, not just rustc or cargo.
                 )
                 .arg(Arg::with_name("command").required(true).multiple(true))
         )
+        .subcommand(
+            SubCommand::with_name("clean-tmp")
+                .about("Remove temporary files left behind by interrupted runs.")
+                .after_help(
+r"Removes the temporary files and directories in ~/.multirust/tmp
+that belong to runs of multirust that have exited, or that are more
+than a day old. Runs that change the default toolchain or overrides
+also do this.
+"
+                )
+        )
         .subcommand(
             SubCommand::with_name("delete-data")
                 .about("Delete all user metadata.")
@@ -240,8 +387,12 @@ fn install_args() -> Vec<Arg<'static, 'static, 'static, 'static, 'static, 'stati
             .help(r"
              Allows arbitrary builds of rust to be installed
              from a custom-built installer, either from the
-             local filesystem or the network. Custom
-             installers are neither checksum nor
+             local filesystem or the network. An installer
+             may be a .tar.gz or .tar tarball, or an unpacked
+             installer directory. An installer downloaded
+             from a URL is checked against the checksum
+             published next to it as <url>.sha256, if there
+             is one. Custom installers are not
              signature-verified.
 
              If multiple installers are specified then they
@@ -254,9 +405,70 @@ fn install_args() -> Vec<Arg<'static, 'static, 'static, 'static, 'static, 'stati
             .takes_value(true)
             .value_name("toolchain-path")
             .min_values(1),
+        Arg::with_name("sha256")
+            .long("sha256")
+            .help(r"
+             The SHA-256 checksum of an installer URL, used
+             instead of the published one. When there are
+             several installer URLs, give one for each of
+             them, in the same order.
+             ")
+            .takes_value(true)
+            .value_name("hex")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("installer"),
+        Arg::with_name("require-checksum")
+            .long("require-checksum")
+            .help("Fail if an installer URL has no checksum to verify")
+            .requires("installer"),
+    ]
+}
+
+fn dist_args() -> Vec<Arg<'static, 'static, 'static, 'static, 'static, 'static>> {
+    vec![
+        Arg::with_name("profile")
+            .long("profile")
+            .help(r"
+             The set of components to install from the release
+             channel: 'minimal', 'default' or 'complete'. Once
+             chosen, the profile is kept by later updates of the
+             toolchain.
+             ")
+            .takes_value(true)
+            .value_name("profile")
+            .number_of_values(1),
+        Arg::with_name("with")
+            .long("with")
+            .help(r"
+             Install an additional component from the release
+             channel, given as 'pkg' for the toolchain's own
+             target or as 'pkg-target'. May be repeated.
+             ")
+            .takes_value(true)
+            .value_name("component")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("without")
+            .long("without")
+            .help(r"
+             Remove an installed optional component, given as
+             'pkg' or 'pkg-target'. May be repeated.
+             ")
+            .takes_value(true)
+            .value_name("component")
+            .multiple(true)
+            .number_of_values(1),
     ]
 }
 
+fn target_group() -> ArgGroup<'static, 'static> {
+    ArgGroup::with_name("targets")
+        .add("target")
+        .add("all")
+        .required(true)
+}
+
 fn install_group() -> ArgGroup<'static, 'static> {
     ArgGroup::with_name("toolchain-source")
         .add("copy-local")
@@ -113,30 +113,27 @@ pub fn show_tool_versions(toolchain: &Toolchain) -> Result<()> {
     println!("");
 
     if toolchain.exists() {
-        let rustc_path = toolchain.binary_file("rustc");
-        let cargo_path = toolchain.binary_file("cargo");
-
-        if utils::is_file(&rustc_path) {
-            let mut cmd = Command::new(&rustc_path);
-            cmd.arg("--version");
-            toolchain.set_ldpath(&mut cmd);
+        for &tool in &["rustc", "cargo"] {
+            let path = toolchain.binary_file(tool);
+            if !utils::is_file(&path) {
+                println!("(no {} command in toolchain?)", tool);
+                continue;
+            }
 
-            if utils::cmd_status("rustc", &mut cmd).is_err() {
-                println!("(failed to run rustc)");
+            // Prefer the installed metadata, and only run the tool
+            // when it doesn't say
+            if let Ok(Some(version)) = toolchain.tool_version(tool) {
+                println!("{} {}", tool, version);
+                continue;
             }
-        } else {
-            println!("(no rustc command in toolchain?)");
-        }
-        if utils::is_file(&cargo_path) {
-            let mut cmd = Command::new(&cargo_path);
+
+            let mut cmd = Command::new(&path);
             cmd.arg("--version");
             toolchain.set_ldpath(&mut cmd);
 
-            if utils::cmd_status("cargo", &mut cmd).is_err() {
-                println!("(failed to run cargo)");
+            if utils::cmd_status(tool, &mut cmd).is_err() {
+                println!("(failed to run {})", tool);
             }
-        } else {
-            println!("(no cargo command in toolchain?)");
         }
     } else {
         println!("(toolchain not installed)");
@@ -166,7 +166,7 @@ impl DownloadTracker {
 }
 
 /// Human readable representation of data size in bytes
-struct HumanReadable(u64);
+pub struct HumanReadable(pub u64);
 
 impl fmt::Display for HumanReadable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -16,6 +16,7 @@ extern crate rand;
 #[macro_use]
 extern crate scopeguard;
 extern crate tempdir;
+extern crate rustc_serialize;
 
 #[cfg(windows)]
 extern crate winapi;
@@ -29,6 +30,7 @@ extern crate libc;
 
 #[macro_use]
 mod log;
+mod cancel;
 mod cli;
 mod common;
 mod download_tracker;
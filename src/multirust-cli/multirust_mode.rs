@@ -1,16 +1,23 @@
+use cancel::cancellable;
 use clap::ArgMatches;
 use cli;
+use download_tracker::HumanReadable;
 use common::{confirm, show_channel_version,
              set_globals, run_inner,
              show_tool_versions};
 use multirust::*;
-use multirust_dist::manifest::Component;
+use multirust_dist::component::DedupStats;
+use multirust_dist::manifest::{Component, Profile};
+use multirust_dist::manifestation::{UpdateStatus, UpdateSummary};
+use rustc_serialize::json::{Json, ToJson};
 use self_update;
+use std::collections::BTreeMap;
 use std::env;
 use std::io::Write;
 use std::iter;
 use std::path::{Path, PathBuf};
 use term;
+use time;
 
 pub fn main() -> Result<()> {
     try!(::self_update::cleanup_self_updater());
@@ -26,22 +33,36 @@ pub fn main() -> Result<()> {
     let cfg = try!(set_globals(verbose));
 
     match app_matches.subcommand() {
-        ("update", Some(m)) => update(&cfg, m),
-        ("default", Some(m)) => default_(&cfg, m),
-        ("override", Some(m)) => override_(&cfg, m),
+        ("update", Some(m)) => cancellable(|| update(&cfg, m)),
+        ("default", Some(m)) => cancellable(|| default_(&cfg, m)),
+        ("override", Some(m)) => cancellable(|| override_(&cfg, m)),
         ("show-default", Some(_)) => show_default(&cfg),
         ("show-override", Some(_)) => show_override(&cfg),
         ("list-overrides", Some(_)) => list_overrides(&cfg),
-        ("list-toolchains", Some(_)) => list_toolchains(&cfg),
+        ("list-toolchains", Some(m)) => list_toolchains(&cfg, m),
         ("remove-override", Some(m)) => remove_override(&cfg, m),
         ("remove-toolchain", Some(m)) => remove_toolchain_args(&cfg, m),
+        ("gc", Some(m)) => gc(&cfg, m),
+        ("verify", Some(m)) => verify(&cfg, m),
+        ("repair", Some(m)) => cancellable(|| repair(&cfg, m)),
+        ("dedup", Some(_)) => dedup(&cfg),
+        ("du", Some(m)) => du(&cfg, m),
         ("list-targets", Some(m)) => list_targets(&cfg, m),
-        ("add-target", Some(m)) => add_target(&cfg, m),
-        ("remove-target", Some(m)) => remove_target(&cfg, m),
+        ("add-target", Some(m)) => cancellable(|| add_target(&cfg, m)),
+        ("remove-target", Some(m)) => cancellable(|| remove_target(&cfg, m)),
+        ("component", Some(c)) => {
+            match c.subcommand() {
+                ("list", Some(m)) => component_list(&cfg, m),
+                ("add", Some(m)) => cancellable(|| component_add(&cfg, m)),
+                ("remove", Some(m)) => cancellable(|| component_remove(&cfg, m)),
+                _ => unreachable!(),
+            }
+        }
         ("run", Some(m)) => run(&cfg, m),
         ("proxy", Some(m)) => proxy(&cfg, m),
         ("upgrade-data", Some(_)) => cfg.upgrade_data().map(|_| ()),
         ("delete-data", Some(m)) => delete_data(&cfg, m),
+        ("clean-tmp", Some(_)) => clean_tmp(&cfg),
         ("self", Some(c)) => {
             match c.subcommand() {
                 ("uninstall", Some(m)) => self_uninstall(m),
@@ -110,11 +131,66 @@ fn remove_toolchain_args(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     try!(get_toolchain(cfg, m, false)).remove()
 }
 
+fn gc(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let older_than = m.value_of("older-than").unwrap();
+    let secs = try!(parse_duration(older_than));
+    let unused = try!(cfg.find_unused_toolchains(secs));
+
+    if unused.is_empty() {
+        try!(cfg.track_unrecorded_toolchains());
+        info!("no toolchains have gone unused for {}", older_than);
+        return Ok(());
+    }
+
+    let now = time::get_time().sec;
+    let mut msg = format!("\nThese toolchains haven't been used for {} and will be removed:\n\n",
+                          older_than);
+    for &(ref name, last_used) in &unused {
+        msg.push_str(&format!("    {} (last used {} days ago)\n",
+                              name, (now - last_used) / (24 * 60 * 60)));
+    }
+    msg.push_str("\nContinue? (y/N)");
+
+    if !m.is_present("no-prompt") && !try!(confirm(&msg, false)) {
+        info!("aborting gc");
+        return Ok(());
+    }
+
+    for (name, _) in unused {
+        if !try!(try!(cfg.get_toolchain(&name, false)).remove_if_unused(secs)) {
+            info!("keeping toolchain '{}', which has been used since", name);
+        }
+    }
+    try!(cfg.track_unrecorded_toolchains());
+
+    Ok(())
+}
+
+// Parses a time like '30d' into seconds
+fn parse_duration(s: &str) -> Result<i64> {
+    let invalid = || Error::InvalidDuration(s.to_owned());
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let count: i64 = try!(s[..s.len() - 1].parse().map_err(|_| invalid()));
+    if count < 0 {
+        return Err(invalid());
+    }
+    count.checked_mul(unit).ok_or_else(invalid)
+}
+
 fn default_(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let toolchain = try!(get_toolchain(cfg, m, true));
     if !try!(common_install_args(&toolchain, m)) {
         if !toolchain.is_custom() {
-            try!(toolchain.install_from_dist_if_not_installed());
+            try!(toolchain.install_from_dist_if_not_installed(try!(profile_arg(m)),
+                                                              &component_args(m, "with"),
+                                                              &component_args(m, "without")));
         }
     }
 
@@ -129,7 +205,23 @@ fn update(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     if let Some(name) = m.value_of("toolchain") {
         let toolchain = try!(cfg.get_toolchain(name, true));
         if !try!(common_install_args(&toolchain, m)) {
-            try!(toolchain.install_from_dist())
+            if toolchain.is_custom() {
+                try!(toolchain.update_from_source())
+            } else {
+                let existed = toolchain.exists();
+                let status = try!(toolchain.install_from_dist(try!(profile_arg(m)),
+                                                              &component_args(m, "with"),
+                                                              &component_args(m, "without")));
+                if let UpdateStatus::Changed(ref summary) = status {
+                    if existed {
+                        println!("");
+                        println!("{} updated{}", name, update_summary_details(summary));
+                        for line in update_summary_lines(summary) {
+                            println!("    {}", line);
+                        }
+                    }
+                }
+            }
         }
         println!("");
         try!(show_channel_version(cfg, name));
@@ -143,7 +235,9 @@ fn override_(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let toolchain = try!(get_toolchain(cfg, m, true));
     if !try!(common_install_args(&toolchain, m)) {
         if !toolchain.is_custom() {
-            try!(toolchain.install_from_dist_if_not_installed());
+            try!(toolchain.install_from_dist_if_not_installed(try!(profile_arg(m)),
+                                                              &component_args(m, "with"),
+                                                              &component_args(m, "without")));
         }
     }
 
@@ -158,7 +252,8 @@ fn common_install_args(toolchain: &Toolchain, m: &ArgMatches) -> Result<bool> {
 
     if let Some(installers) = m.values_of("installer") {
         let is: Vec<_> = installers.iter().map(|i| i.as_ref()).collect();
-        try!(toolchain.install_from_installers(&*is));
+        let hashes = m.values_of("sha256").unwrap_or(Vec::new());
+        try!(toolchain.install_from_installers(&*is, &*hashes, m.is_present("require-checksum")));
     } else if let Some(path) = m.value_of("copy-local") {
         try!(toolchain.install_from_dir(Path::new(path), false));
     } else if let Some(path) = m.value_of("link-local") {
@@ -169,6 +264,20 @@ fn common_install_args(toolchain: &Toolchain, m: &ArgMatches) -> Result<bool> {
     Ok(true)
 }
 
+fn profile_arg(m: &ArgMatches) -> Result<Option<Profile>> {
+    if let Some(name) = m.value_of("profile") {
+        Ok(Some(try!(Profile::from_str(name))))
+    } else {
+        Ok(None)
+    }
+}
+
+fn component_args(m: &ArgMatches, name: &str) -> Vec<String> {
+    m.values_of(name)
+     .map(|names| names.iter().map(|n| n.to_string()).collect())
+     .unwrap_or(Vec::new())
+}
+
 fn doc_url(m: &ArgMatches) -> &'static str {
     if m.is_present("book") {
         "book/index.html"
@@ -195,6 +304,12 @@ fn which(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn clean_tmp(cfg: &Cfg) -> Result<()> {
+    let removed = try!(cfg.clean_tmp());
+    info!("removed {} temporary files and directories", removed);
+    Ok(())
+}
+
 fn delete_data(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let msg =
 r"
@@ -225,6 +340,7 @@ fn remove_override(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
         return Ok(());
     }
 
+    let _lock = try!(cfg.lock_metadata());
     try!(cfg.override_db.remove(path,
                                 &cfg.temp_cfg,
                                 cfg.notify_handler.as_ref()));
@@ -274,16 +390,118 @@ fn list_overrides(cfg: &Cfg) -> Result<()> {
     Ok(())
 }
 
-fn list_toolchains(cfg: &Cfg) -> Result<()> {
+fn list_toolchains(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let mut toolchains = try!(cfg.list_toolchains());
 
     toolchains.sort();
 
+    if m.is_present("verbose") || m.is_present("json") {
+        return list_toolchains_verbose(cfg, toolchains, m.is_present("json"));
+    }
+
     if toolchains.is_empty() {
         println!("no installed toolchains");
     } else {
-        for toolchain in toolchains {
-            println!("{}", &toolchain);
+        for name in toolchains {
+            if try!(cfg.get_toolchain(&name, false)).verify().is_err() {
+                println!("{} (broken)", name);
+            } else {
+                println!("{}", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_toolchains_verbose(cfg: &Cfg, toolchains: Vec<String>, json: bool) -> Result<()> {
+    let default = try!(cfg.default_name());
+    let override_ = try!(cfg.find_override_name(&try!(utils::current_dir())));
+
+    let mut du = utils::DiskUsage::new();
+    let mut infos = Vec::new();
+    for name in toolchains {
+        let info = try!(cfg.get_toolchain(&name, false)).info(&mut du);
+        let is_default = default.as_ref() == Some(&name);
+        let is_override = override_.as_ref() == Some(&name);
+        infos.push((name, is_default, is_override, info));
+    }
+
+    if json {
+        let toolchains = infos.iter().map(|&(ref name, is_default, is_override, ref info)| {
+            let source = info.source.as_ref().map(|source| {
+                let mut o = BTreeMap::new();
+                o.insert("kind".to_owned(), source.kind().to_json());
+                o.insert("locations".to_owned(), source.locations().to_json());
+                Json::Object(o)
+            });
+            let mut o = BTreeMap::new();
+            o.insert("name".to_owned(), name.to_json());
+            o.insert("default".to_owned(), is_default.to_json());
+            o.insert("override".to_owned(), is_override.to_json());
+            o.insert("kind".to_owned(), info.kind.name().to_json());
+            o.insert("broken".to_owned(), info.broken.to_json());
+            o.insert("source".to_owned(), source.unwrap_or(Json::Null));
+            o.insert("date".to_owned(), info.date.to_json());
+            o.insert("version".to_owned(), info.version.to_json());
+            o.insert("targets".to_owned(), info.targets.to_json());
+            o.insert("bytes".to_owned(), info.size.to_json());
+            o.insert("error".to_owned(), info.error.to_json());
+            Json::Object(o)
+        });
+        let mut root = BTreeMap::new();
+        root.insert("toolchains".to_owned(), Json::Array(toolchains.collect()));
+        println!("{}", Json::Object(root));
+        return Ok(());
+    }
+
+    if infos.is_empty() {
+        println!("no installed toolchains");
+    }
+    for (name, is_default, is_override, info) in infos {
+        let mut markers = Vec::new();
+        if is_default {
+            markers.push("default");
+        }
+        if is_override {
+            markers.push("override");
+        }
+        if info.broken {
+            markers.push("broken");
+        }
+        if markers.is_empty() {
+            println!("{}", name);
+        } else {
+            println!("{} ({})", name, markers.join(", "));
+        }
+
+        println!("    kind: {}", info.kind.name());
+        match info.source {
+            Some(ToolchainSource::Copy(ref path)) => {
+                println!("    source: copied from '{}'", path.display())
+            }
+            Some(ToolchainSource::Link(ref path)) => {
+                println!("    source: linked to '{}'", path.display())
+            }
+            Some(ToolchainSource::Installers { locations: ref installers, .. }) => {
+                let installers: Vec<_> = installers.iter().map(|i| format!("'{}'", i)).collect();
+                println!("    source: installed from {}", installers.join(", "))
+            }
+            None => {}
+        }
+        if let Some(ref date) = info.date {
+            println!("    date: {}", date);
+        }
+        if let Some(ref version) = info.version {
+            println!("    version: {}", version);
+        }
+        if !info.targets.is_empty() {
+            println!("    targets: {}", info.targets.join(", "));
+        }
+        if let Some(bytes) = info.size {
+            println!("    size: {}", HumanReadable(bytes));
+        }
+        if let Some(ref error) = info.error {
+            println!("    error: {}", error);
         }
     }
     Ok(())
@@ -306,14 +524,28 @@ fn update_all_channels(cfg: &Cfg) -> Result<()> {
                        name);
         let _ = t.reset();
         let _ = write!(t, " update ");
-        if result.is_ok() {
-            let _ = t.fg(term::color::BRIGHT_GREEN);
-            let _ = writeln!(t, "succeeded");
-            let _ = t.reset();
-        } else {
-            let _ = t.fg(term::color::BRIGHT_RED);
-            let _ = writeln!(t, "FAILED");
-            let _ = t.reset();
+        match *result {
+            Ok(ref status) => {
+                let _ = t.fg(term::color::BRIGHT_GREEN);
+                let _ = write!(t, "succeeded");
+                let _ = t.reset();
+                match *status {
+                    UpdateStatus::Unchanged => {
+                        let _ = writeln!(t, " (unchanged)");
+                    }
+                    UpdateStatus::Changed(ref summary) => {
+                        let _ = writeln!(t, "{}", update_summary_details(summary));
+                        for line in update_summary_lines(summary) {
+                            let _ = writeln!(t, "{}    {}", padding_str, line);
+                        }
+                    }
+                }
+            }
+            Err(_) => {
+                let _ = t.fg(term::color::BRIGHT_RED);
+                let _ = writeln!(t, "FAILED");
+                let _ = t.reset();
+            }
         }
     }
     println!("");
@@ -329,12 +561,206 @@ fn update_all_channels(cfg: &Cfg) -> Result<()> {
     Ok(())
 }
 
+// The dates, download size and duration of an update, like
+// " (2016-02-28 → 2016-03-05, 85.20 MiB downloaded, 12.3s)"
+fn update_summary_details(summary: &UpdateSummary) -> String {
+    let mut details = Vec::new();
+    match (&summary.old_date, &summary.new_date) {
+        (&Some(ref old), &Some(ref new)) if old != new => {
+            details.push(format!("{} → {}", old, new));
+        }
+        (_, &Some(ref new)) => details.push(new.clone()),
+        _ => {}
+    }
+    if summary.downloaded_bytes > 0 {
+        details.push(format!("{} downloaded", HumanReadable(summary.downloaded_bytes)));
+    }
+    if summary.elapsed > 0.0 {
+        details.push(format!("{:.1}s", summary.elapsed));
+    }
+
+    if details.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", details.join(", "))
+    }
+}
+
+// A line for each package whose version changed and each component
+// added or removed
+fn update_summary_lines(summary: &UpdateSummary) -> Vec<String> {
+    let mut lines = Vec::new();
+    for &(ref pkg, ref old, ref new) in &summary.packages {
+        lines.push(format!("{} {} → {}", pkg, old, new));
+    }
+    for component in &summary.added {
+        lines.push(format!("added {}", component.name()));
+    }
+    for component in &summary.removed {
+        lines.push(format!("removed {}", component.name()));
+    }
+    lines
+}
+
+fn verify(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = m.value_of("toolchain").unwrap();
+    let toolchain = try!(cfg.get_toolchain(toolchain, false));
+    let mut damaged = false;
+    for (name, verification) in try!(toolchain.verify_components()) {
+        if verification.is_intact() {
+            if verification.hashed {
+                println!("{}: ok", name);
+            } else {
+                println!("{}: ok (no recorded hashes)", name);
+            }
+            continue;
+        }
+
+        damaged = true;
+        println!("{}: damaged", name);
+        for path in &verification.missing {
+            println!("    missing: {}", path.display());
+        }
+        for path in &verification.modified {
+            println!("    modified: {}", path.display());
+        }
+        for path in &verification.extra {
+            println!("    extra: {}", path.display());
+        }
+    }
+
+    if damaged {
+        Err(Error::ToolchainDamaged(toolchain.name().to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+fn repair(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = m.value_of("toolchain").unwrap();
+    let toolchain = try!(cfg.get_toolchain(toolchain, false));
+    let repaired = try!(toolchain.repair());
+
+    if repaired.is_empty() {
+        info!("toolchain '{}' has no damaged components", toolchain.name());
+    }
+    for component in repaired {
+        info!("repaired component '{}'", component.name());
+    }
+
+    Ok(())
+}
+
+fn dedup(cfg: &Cfg) -> Result<()> {
+    let mut toolchains = try!(cfg.list_toolchains());
+    toolchains.sort();
+
+    let mut total = DedupStats::default();
+    for name in toolchains {
+        let toolchain = try!(cfg.get_toolchain(&name, false));
+        let stats = try!(toolchain.dedup());
+        if stats.linked > 0 {
+            info!("toolchain '{}': linked {} files, saving {}",
+                  name, stats.linked, HumanReadable(stats.bytes_saved));
+        }
+        total.files += stats.files;
+        total.linked += stats.linked;
+        total.bytes_saved += stats.bytes_saved;
+    }
+    let pruned = try!(cfg.prune_store());
+
+    println!("linked {} of {} files, saving {}",
+             total.linked, total.files, HumanReadable(total.bytes_saved));
+    if pruned > 0 {
+        println!("removed {} unused files from the store", pruned);
+    }
+
+    Ok(())
+}
+
+fn du(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let mut toolchains = try!(cfg.list_toolchains());
+    toolchains.sort();
+
+    // Toolchains are measured first, so that the store's unique size
+    // is what no toolchain uses any more
+    let mut du = utils::DiskUsage::new();
+    let mut toolchain_usage = Vec::new();
+    for name in toolchains {
+        let usage = try!(try!(cfg.get_toolchain(&name, false)).disk_usage(&mut du));
+        toolchain_usage.push((name, usage));
+    }
+    let dirs = [("tmp", cfg.temp_cfg.root_directory()),
+                ("update-hashes", &*cfg.update_hash_dir),
+                ("store", &*cfg.store_dir)];
+    let mut dir_usage = Vec::new();
+    for &(name, path) in &dirs {
+        dir_usage.push((name, path, try!(du.measure(path))));
+    }
+    let total = toolchain_usage.iter().map(|&(_, ref u)| u.size.unique_bytes)
+                               .chain(dir_usage.iter().map(|&(_, _, ref s)| s.unique_bytes))
+                               .fold(0, |a, b| a + b);
+
+    if m.is_present("json") {
+        let toolchains = toolchain_usage.iter().map(|&(ref name, ref usage)| {
+            let components = usage.components.iter().map(|&(ref name, bytes)| {
+                let mut o = BTreeMap::new();
+                o.insert("name".to_owned(), name.to_json());
+                o.insert("bytes".to_owned(), bytes.to_json());
+                Json::Object(o)
+            });
+            let mut o = BTreeMap::new();
+            o.insert("name".to_owned(), name.to_json());
+            o.insert("linked".to_owned(), usage.linked.to_json());
+            o.insert("bytes".to_owned(), usage.size.bytes.to_json());
+            o.insert("unique_bytes".to_owned(), usage.size.unique_bytes.to_json());
+            o.insert("components".to_owned(), Json::Array(components.collect()));
+            Json::Object(o)
+        });
+        let directories = dir_usage.iter().map(|&(name, path, ref size)| {
+            let mut o = BTreeMap::new();
+            o.insert("name".to_owned(), name.to_json());
+            o.insert("path".to_owned(), Json::String(path.to_string_lossy().into_owned()));
+            o.insert("bytes".to_owned(), size.bytes.to_json());
+            o.insert("unique_bytes".to_owned(), size.unique_bytes.to_json());
+            Json::Object(o)
+        });
+        let mut root = BTreeMap::new();
+        root.insert("toolchains".to_owned(), Json::Array(toolchains.collect()));
+        root.insert("directories".to_owned(), Json::Array(directories.collect()));
+        root.insert("total_bytes".to_owned(), total.to_json());
+        println!("{}", Json::Object(root));
+        return Ok(());
+    }
+
+    for (name, usage) in toolchain_usage {
+        if usage.linked {
+            println!("{}: linked", name);
+            continue;
+        }
+        println!("{}: {}", name, HumanReadable(usage.size.bytes));
+        for (component, bytes) in usage.components {
+            println!("    {}: {}", component, HumanReadable(bytes));
+        }
+    }
+    for (name, _, size) in dir_usage {
+        println!("{}: {}", name, HumanReadable(size.bytes));
+    }
+    println!("total: {}", HumanReadable(total));
+
+    Ok(())
+}
+
 fn list_targets(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let toolchain = m.value_of("toolchain").unwrap();
     let toolchain = try!(cfg.get_toolchain(toolchain, false));
-    for component in try!(toolchain.list_components()) {
-        if component.pkg == "rust-std" {
-            println!("{}", component.target);
+    for status in try!(toolchain.list_components()) {
+        if status.component.pkg == "rust-std" {
+            if status.installed {
+                println!("{} (installed)", status.component.target);
+            } else {
+                println!("{}", status.component.target);
+            }
         }
     }
 
@@ -343,26 +769,109 @@ fn list_targets(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
 
 fn add_target(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let toolchain = m.value_of("toolchain").unwrap();
-    let target = m.value_of("target").unwrap();
     let toolchain = try!(cfg.get_toolchain(toolchain, false));
-    let new_component = Component {
-        pkg: "rust-std".to_string(),
-        target: target.to_string(),
+    let new_components = if m.is_present("all") {
+        let mut new_components = Vec::new();
+        for status in try!(toolchain.list_components()) {
+            if status.component.pkg != "rust-std" || status.installed {
+                continue;
+            }
+            if status.available {
+                new_components.push(status.component);
+            } else {
+                info!("skipping target '{}', which is unavailable for download",
+                      status.component.target);
+            }
+        }
+        new_components
+    } else {
+        target_components(m)
     };
-    try!(toolchain.add_component(new_component));
+    try!(toolchain.add_components(new_components));
 
     Ok(())
 }
 
 fn remove_target(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
     let toolchain = m.value_of("toolchain").unwrap();
-    let target = m.value_of("target").unwrap();
     let toolchain = try!(cfg.get_toolchain(toolchain, false));
-    let new_component = Component {
-        pkg: "rust-std".to_string(),
-        target: target.to_string(),
+    let components = if m.is_present("all") {
+        try!(toolchain.list_components()).into_iter()
+            .filter(|s| s.component.pkg == "rust-std" && s.installed && !s.required)
+            .map(|s| s.component)
+            .collect()
+    } else {
+        target_components(m)
     };
-    try!(toolchain.remove_component(new_component));
+    try!(toolchain.remove_components(components, vec![]));
+
+    Ok(())
+}
+
+fn target_components(m: &ArgMatches) -> Vec<Component> {
+    m.values_of("target").unwrap().iter().map(|target| {
+        Component {
+            pkg: "rust-std".to_string(),
+            target: target.to_string(),
+        }
+    }).collect()
+}
+
+fn component_list(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = m.value_of("toolchain").unwrap();
+    let toolchain = try!(cfg.get_toolchain(toolchain, false));
+    for status in try!(toolchain.list_components()) {
+        let name = status.component.name();
+        if status.required {
+            println!("{} (required)", name);
+        } else if status.installed {
+            println!("{} (installed)", name);
+        } else {
+            println!("{}", name);
+        }
+    }
+    if toolchain.exists() {
+        for name in try!(toolchain.list_local_components()) {
+            println!("{} (local)", name);
+        }
+    }
+
+    Ok(())
+}
+
+fn component_add(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = m.value_of("toolchain").unwrap();
+    let toolchain = try!(cfg.get_toolchain(toolchain, false));
+    if let Some(installer) = m.value_of("installer") {
+        for name in try!(toolchain.add_local_components(Path::new(installer))) {
+            info!("installed local component '{}'", name);
+        }
+        return Ok(());
+    }
+
+    let mut components = Vec::new();
+    for name in m.values_of("component").unwrap() {
+        components.push(try!(toolchain.find_component(name)));
+    }
+    try!(toolchain.add_components(components));
+
+    Ok(())
+}
+
+fn component_remove(cfg: &Cfg, m: &ArgMatches) -> Result<()> {
+    let toolchain = m.value_of("toolchain").unwrap();
+    let toolchain = try!(cfg.get_toolchain(toolchain, false));
+    let local_components = try!(toolchain.list_local_components());
+    let mut components = Vec::new();
+    let mut local = Vec::new();
+    for name in m.values_of("component").unwrap() {
+        if local_components.iter().any(|n| n == name) {
+            local.push(name.to_owned());
+        } else {
+            components.push(try!(toolchain.find_component(name)));
+        }
+    }
+    try!(toolchain.remove_components(components, local));
 
     Ok(())
 }
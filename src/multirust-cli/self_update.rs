@@ -292,7 +292,7 @@ fn maybe_install_rust_stable(verbose: bool) -> Result<()> {
     // then install stable and make it the default.
     if try!(cfg.find_default()).is_none() {
         let stable = try!(cfg.get_toolchain("stable", false));
-        try!(stable.install_from_dist());
+        try!(stable.install_from_dist(None, &[], &[]));
         try!(cfg.set_default("stable"));
     } else {
         info!("updating existing installation");
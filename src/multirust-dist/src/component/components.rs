@@ -6,22 +6,44 @@ use multirust_utils::{self, utils};
 use prefix::InstallPrefix;
 use errors::*;
 
-use component::transaction::Transaction;
+use component::transaction::{self, Transaction};
 use component::package::{INSTALLER_VERSION, VERSION_FILE};
 
 use std::path::{Path, PathBuf};
-use std::fs::File;
+use std::collections::HashSet;
+use std::fs::{self, File};
 use std::io::Write;
 
+use itertools::Itertools;
+use openssl::crypto::hash::{Type, Hasher};
+
 const COMPONENTS_FILE: &'static str = "components";
 
+/// The version of the `hashes-<component>` files written next to
+/// component manifests by `ComponentBuilder`, which record the
+/// SHA-256 and mode of every installed file. They are kept out of the
+/// component manifests so those stay readable by rust-installer and
+/// older versions of multirust, which reject any line that isn't a
+/// `file:` or `dir:` part.
+pub const HASHES_VERSION: &'static str = "1";
+
 #[derive(Clone, Debug)]
 pub struct Components {
     prefix: InstallPrefix,
 }
 
 impl Components {
-    pub fn open(prefix: InstallPrefix) -> Result<Self> {
+    /// Opens the installation in the prefix. An interrupted
+    /// transaction is only reported, since it may have left the
+    /// metadata half-written. Opening doesn't recover it, since that
+    /// needs whatever lock the caller uses to keep others from
+    /// changing the prefix; callers run `recover_transaction` under
+    /// that lock before changing or running the installation.
+    pub fn open(prefix: InstallPrefix, notify_handler: NotifyHandler) -> Result<Self> {
+        if try!(transaction::has_interrupted_transaction(&prefix)) {
+            notify_handler.call(Notification::InterruptedTransaction(prefix.path()));
+        }
+
         let c = Components { prefix: prefix };
 
         // Validate that the metadata uses a format we know
@@ -39,6 +61,9 @@ impl Components {
     fn rel_component_manifest(&self, name: &str) -> PathBuf {
         self.prefix.rel_manifest_file(&format!("manifest-{}", name))
     }
+    fn rel_component_hashes(&self, name: &str) -> PathBuf {
+        self.prefix.rel_manifest_file(&format!("hashes-{}", name))
+    }
     fn read_version(&self) -> Result<Option<String>> {
         let p = self.prefix.manifest_file(VERSION_FILE);
         if utils::is_file(&p) {
@@ -82,6 +107,23 @@ impl Components {
         let result = try!(self.list());
         Ok(result.into_iter().filter(|c| (c.name() == name)).next())
     }
+    /// Finds a component whose component manifest exists but which is
+    /// missing from the components file, as left by a damaged
+    /// installation
+    pub fn find_unlisted(&self, name: &str) -> Result<Option<Component>> {
+        if try!(self.find(name)).is_some() {
+            return Ok(None);
+        }
+        let component = Component {
+            components: self.clone(),
+            name: name.to_owned(),
+        };
+        if utils::is_file(&component.manifest_file()) {
+            Ok(Some(component))
+        } else {
+            Ok(None)
+        }
+    }
     pub fn prefix(&self) -> InstallPrefix {
         self.prefix.clone()
     }
@@ -110,21 +152,29 @@ impl<'a> ComponentBuilder<'a> {
     }
 
     pub fn finish(mut self) -> Result<Transaction<'a>> {
+        // Hash everything that was installed, including the contents
+        // of directories
+        let mut records = Vec::new();
+        for part in &self.parts {
+            let mut files = Vec::new();
+            try!(installed_files(&self.components.prefix, &part.1, &mut files));
+            for file in files {
+                records.push(try!(FileRecord::from_installed(&self.components.prefix, file)));
+            }
+        }
+
         // Write component manifest
         let path = self.components.rel_component_manifest(&self.name);
-        let abs_path = self.components.prefix.abs_path(&path);
-        let mut file = try!(self.tx.add_file(&self.name, path));
-        for part in self.parts {
-            // FIXME: This writes relative paths to the component manifest,
-            // but rust-installer writes absolute paths.
-            try!(writeln!(file, "{}", part.encode()).map_err(|e| {
-                multirust_utils::Error::WritingFile {
-                    name: "component",
-                    path: abs_path.clone(),
-                    error: e,
-                }
-            }));
-        }
+        let parts: Vec<String> = self.parts.iter().map(|p| p.encode()).collect();
+        // FIXME: This writes relative paths to the component manifest,
+        // but rust-installer writes absolute paths.
+        try!(self.write_lines(path, parts.into_iter()));
+
+        // And the hashes of its files
+        let path = self.components.rel_component_hashes(&self.name);
+        let version = format!("version:{}", HASHES_VERSION);
+        let records = records.iter().map(|r| r.encode());
+        try!(self.write_lines(path, Some(version).into_iter().chain(records)));
 
         // Add component to components file
         let path = self.components.rel_components_file();
@@ -137,8 +187,25 @@ impl<'a> ComponentBuilder<'a> {
 
         Ok(self.tx)
     }
+
+    fn write_lines<I: Iterator<Item = String>>(&mut self, path: PathBuf, lines: I) -> Result<()> {
+        let abs_path = self.components.prefix.abs_path(&path);
+        let mut file = try!(self.tx.add_file(&self.name, path));
+        for line in lines {
+            try!(writeln!(file, "{}", line).map_err(|e| {
+                multirust_utils::Error::WritingFile {
+                    name: "component",
+                    path: abs_path.clone(),
+                    error: e,
+                }
+            }));
+        }
+        Ok(())
+    }
 }
 
+/// A `file:` or `dir:` entry of a component manifest. This is also
+/// the format of the `manifest.in` files of rust-installer packages.
 #[derive(Debug)]
 pub struct ComponentPart(pub String, pub PathBuf);
 
@@ -153,6 +220,104 @@ impl ComponentPart {
     }
 }
 
+/// The SHA-256 and mode of an installed file, recorded in the
+/// component's hashes file as `hash:<sha256>:<mode>:<path>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub hash: String,
+    pub mode: u32,
+}
+
+impl FileRecord {
+    pub fn from_installed(prefix: &InstallPrefix, path: PathBuf) -> Result<Self> {
+        let abs_path = prefix.abs_path(&path);
+        let mut hasher = Hasher::new(Type::SHA256);
+        try!(utils::tee_file("component file", &abs_path, &mut hasher));
+        let hash = hasher.finish()
+                         .iter()
+                         .map(|b| format!("{:02x}", b))
+                         .join("");
+
+        Ok(FileRecord {
+            path: path,
+            hash: hash,
+            mode: try!(file_mode(&abs_path)),
+        })
+    }
+    pub fn encode(&self) -> String {
+        format!("hash:{}:{:o}:{}", &self.hash, self.mode, &self.path.to_string_lossy())
+    }
+    pub fn decode(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, ':');
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some("hash"), Some(hash), Some(mode), Some(path)) => {
+                u32::from_str_radix(mode, 8).ok().map(|mode| {
+                    FileRecord {
+                        path: PathBuf::from(path),
+                        hash: hash.to_owned(),
+                        mode: mode,
+                    }
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = try!(fs::metadata(path)
+                        .map_err(|e| Error::InspectingComponentFile(path.to_owned(), e)));
+    Ok(meta.permissions().mode() & 0o777)
+}
+
+#[cfg(windows)]
+fn file_mode(path: &Path) -> Result<u32> {
+    let meta = try!(fs::metadata(path)
+                        .map_err(|e| Error::InspectingComponentFile(path.to_owned(), e)));
+    Ok(if meta.permissions().readonly() { 0o444 } else { 0o644 })
+}
+
+// Collects the files at or under a path relative to the prefix
+fn installed_files(prefix: &InstallPrefix, path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let abs_path = prefix.abs_path(path);
+    if utils::is_directory(&abs_path) {
+        let mut entries = Vec::new();
+        for entry in try!(utils::read_dir("component directory", &abs_path)) {
+            let entry = try!(entry.map_err(|e| Error::InspectingComponentFile(abs_path.clone(), e)));
+            entries.push(entry.file_name());
+        }
+        entries.sort();
+        for name in entries {
+            try!(installed_files(prefix, &path.join(name), files));
+        }
+    } else if utils::path_exists(&abs_path) {
+        files.push(path.to_owned());
+    }
+
+    Ok(())
+}
+
+/// The damage found in an installed component by `Component::verify`
+#[derive(Debug, Default)]
+pub struct Verification {
+    /// Whether the component manifest records file hashes. Older
+    /// manifests can only be checked for missing parts.
+    pub hashed: bool,
+    pub missing: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub extra: Vec<PathBuf>,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty() && self.extra.is_empty()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Component {
     components: Components,
@@ -169,18 +334,107 @@ impl Component {
     pub fn rel_manifest_file(&self) -> PathBuf {
         self.components.prefix.rel_manifest_file(&self.manifest_name())
     }
+    pub fn hashes_file(&self) -> PathBuf {
+        self.components.prefix.abs_path(self.components.rel_component_hashes(&self.name))
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
     pub fn parts(&self) -> Result<Vec<ComponentPart>> {
-        let mut result = Vec::new();
+        self.read_manifest()
+    }
+    /// The recorded hashes of the component's files, or `None` if it
+    /// was installed without them
+    pub fn file_records(&self) -> Result<Option<Vec<FileRecord>>> {
+        let ref path = self.hashes_file();
+        if !utils::is_file(path) {
+            return Ok(None);
+        }
+
+        let mut records = Vec::new();
+        for line in try!(utils::read_file("component hashes", path)).lines() {
+            if line.starts_with("version:") {
+                let version = &line["version:".len()..];
+                if version != HASHES_VERSION {
+                    return Err(Error::BadInstalledMetadataVersion(version.to_owned()));
+                }
+                continue;
+            }
+            records.push(try!(FileRecord::decode(line)
+                                  .ok_or_else(|| Error::CorruptComponent(self.name.clone()))));
+        }
+        Ok(Some(records))
+    }
+    /// The files currently installed by the component, including the
+    /// contents of its directories
+    pub fn installed_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for part in try!(self.parts()) {
+            try!(installed_files(&self.components.prefix, &part.1, &mut files));
+        }
+        Ok(files)
+    }
+    fn read_manifest(&self) -> Result<Vec<ComponentPart>> {
+        let mut parts = Vec::new();
         for line in try!(utils::read_file("component", &self.manifest_file())).lines() {
-            result.push(try!(ComponentPart::decode(line)
-                                 .ok_or_else(|| Error::CorruptComponent(self.name.clone()))));
+            parts.push(try!(ComponentPart::decode(line)
+                                .ok_or_else(|| Error::CorruptComponent(self.name.clone()))));
+        }
+        Ok(parts)
+    }
+    /// Checks the installed files against the component manifest.
+    /// Files that weren't installed by the component but are inside
+    /// one of its directories are reported as extra.
+    pub fn verify(&self) -> Result<Verification> {
+        let ref prefix = self.components.prefix;
+        let parts = try!(self.read_manifest());
+        let records = try!(self.file_records());
+        let mut result = Verification::default();
+
+        let records = match records {
+            Some(r) => r,
+            None => {
+                for part in parts {
+                    if !utils::path_exists(prefix.abs_path(&part.1)) {
+                        result.missing.push(part.1);
+                    }
+                }
+                return Ok(result);
+            }
+        };
+        result.hashed = true;
+
+        let mut installed = Vec::new();
+        for part in &parts {
+            try!(installed_files(prefix, &part.1, &mut installed));
+        }
+        let installed_set: HashSet<&PathBuf> = installed.iter().collect();
+        let recorded_set: HashSet<&PathBuf> = records.iter().map(|r| &r.path).collect();
+
+        for record in &records {
+            if !installed_set.contains(&record.path) {
+                result.missing.push(record.path.clone());
+            } else if try!(FileRecord::from_installed(prefix, record.path.clone())) != *record {
+                result.modified.push(record.path.clone());
+            }
+        }
+        for path in &installed {
+            if !recorded_set.contains(path) {
+                result.extra.push(path.clone());
+            }
         }
+
         Ok(result)
     }
-    pub fn uninstall<'a>(&self, mut tx: Transaction<'a>) -> Result<Transaction<'a>> {
+    pub fn uninstall<'a>(&self, tx: Transaction<'a>) -> Result<Transaction<'a>> {
+        self.uninstall_parts(tx, false)
+    }
+    /// Uninstalls a component some of whose files may already be
+    /// gone, skipping the parts that no longer exist
+    pub fn uninstall_damaged<'a>(&self, tx: Transaction<'a>) -> Result<Transaction<'a>> {
+        self.uninstall_parts(tx, true)
+    }
+    fn uninstall_parts<'a>(&self, mut tx: Transaction<'a>, skip_missing: bool) -> Result<Transaction<'a>> {
         // Update components file
         let path = self.components.rel_components_file();
         let abs_path = self.components.prefix.abs_path(&path);
@@ -194,6 +448,9 @@ impl Component {
 
         // Remove parts
         for part in try!(self.parts()).into_iter().rev() {
+            if skip_missing && !utils::path_exists(self.components.prefix.abs_path(&part.1)) {
+                continue;
+            }
             match &*part.0 {
                 "file" => try!(tx.remove_file(&self.name, part.1)),
                 "dir" => try!(tx.remove_dir(&self.name, part.1)),
@@ -203,6 +460,9 @@ impl Component {
 
         // Remove component manifest
         try!(tx.remove_file(&self.name, self.rel_manifest_file()));
+        if utils::is_file(&self.hashes_file()) {
+            try!(tx.remove_file(&self.name, self.components.rel_component_hashes(&self.name)));
+        }
 
         Ok(tx)
     }
@@ -5,6 +5,7 @@
 pub use self::transaction::*;
 pub use self::components::*;
 pub use self::package::*;
+pub use self::store::*;
 
 // Transactional file system tools
 mod transaction;
@@ -16,3 +17,5 @@ pub mod package;
 // The representation of *installed* components, and uninstallation
 mod components;
 
+// Hard linking identical files across installations
+mod store;
@@ -21,6 +21,9 @@ use std::fs::File;
 /// The current metadata revision used by rust-installer
 pub const INSTALLER_VERSION: &'static str = "3";
 pub const VERSION_FILE: &'static str = "rust-installer-version";
+/// The version of the packaged software, which rust tarballs carry
+/// alongside the installer metadata
+pub const PACKAGE_VERSION_FILE: &'static str = "version";
 
 pub trait Package: fmt::Debug {
     fn contains(&self, component: &str, short_name: Option<&str>) -> bool;
@@ -31,12 +34,48 @@ pub trait Package: fmt::Debug {
                    tx: Transaction<'a>)
                    -> Result<Transaction<'a>>;
     fn components(&self) -> Vec<String>;
+    fn version(&self) -> Option<&str>;
 }
 
+/// Opens an installer, which may be an unpacked rust-installer
+/// directory or a tarball. Tarballs are recognized by their contents,
+/// not their file extension.
+pub fn open_package<'a>(path: &Path, temp_cfg: &'a temp::Cfg) -> Result<Box<Package + 'a>> {
+    if utils::is_directory(path) {
+        return Ok(Box::new(try!(DirectoryPackage::new(path.to_owned()))));
+    }
+
+    let mut header = Vec::new();
+    {
+        let file = try!(File::open(path).map_err(Error::ExtractingPackage));
+        try!(file.take(TAR_MAGIC_OFFSET + TAR_MAGIC.len() as u64)
+                 .read_to_end(&mut header)
+                 .map_err(Error::ExtractingPackage));
+    }
+
+    if header.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(try!(TarGzPackage::new_file(path, temp_cfg))))
+    } else if header.starts_with(XZ_MAGIC) {
+        Err(Error::UnsupportedPackageCompression(path.to_owned(), "xz"))
+    } else if header.len() > TAR_MAGIC_OFFSET as usize &&
+              header[TAR_MAGIC_OFFSET as usize..].starts_with(TAR_MAGIC) {
+        Ok(Box::new(try!(TarPackage::new_file(path, temp_cfg))))
+    } else {
+        Err(Error::UnrecognizedPackage(path.to_owned()))
+    }
+}
+
+const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &'static [u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+// Both POSIX and GNU tar headers start with "ustar" at this offset
+const TAR_MAGIC: &'static [u8] = b"ustar";
+const TAR_MAGIC_OFFSET: u64 = 257;
+
 #[derive(Debug)]
 pub struct DirectoryPackage {
     path: PathBuf,
     components: HashSet<String>,
+    version: Option<String>,
 }
 
 impl DirectoryPackage {
@@ -45,9 +84,17 @@ impl DirectoryPackage {
 
         let content = try!(utils::read_file("package components", &path.join("components")));
         let components = content.lines().map(|l| l.to_owned()).collect();
+        let version_path = path.join(PACKAGE_VERSION_FILE);
+        let version = if utils::is_file(&version_path) {
+            let version = try!(utils::read_file("package version", &version_path));
+            Some(version.trim().to_owned())
+        } else {
+            None
+        };
         Ok(DirectoryPackage {
             path: path,
             components: components,
+            version: version,
         })
     }
 }
@@ -114,6 +161,9 @@ impl Package for DirectoryPackage {
     fn components(&self) -> Vec<String> {
         self.components.iter().cloned().collect()
     }
+    fn version(&self) -> Option<&str> {
+        self.version.as_ref().map(|v| &**v)
+    }
 }
 
 // On Unix we need to set up the file permissions correctly so
@@ -184,6 +234,10 @@ impl<'a> TarPackage<'a> {
 
         Ok(TarPackage(try!(DirectoryPackage::new(temp_dir.to_owned())), temp_dir))
     }
+    pub fn new_file(path: &Path, temp_cfg: &'a temp::Cfg) -> Result<Self> {
+        let file = try!(File::open(path).map_err(Error::ExtractingPackage));
+        Self::new(file, temp_cfg)
+    }
 }
 
 fn unpack_without_first_dir<R: Read>(archive: &mut tar::Archive<R>, path: &Path) -> Result<()> {
@@ -220,6 +274,9 @@ impl<'a> Package for TarPackage<'a> {
     fn components(&self) -> Vec<String> {
         self.0.components()
     }
+    fn version(&self) -> Option<&str> {
+        self.0.version()
+    }
 }
 
 #[derive(Debug)]
@@ -252,4 +309,7 @@ impl<'a> Package for TarGzPackage<'a> {
     fn components(&self) -> Vec<String> {
         self.0.components()
     }
+    fn version(&self) -> Option<&str> {
+        self.0.version()
+    }
 }
@@ -0,0 +1,142 @@
+//! A content-addressed store of installed files, shared by all the
+//! installations under one multirust home.
+//!
+//! Deduplicating an installation replaces each of its files with a
+//! hard link to the stored file with the same contents and mode, so
+//! files that are identical across toolchains only take up space
+//! once. Linked files must never be written in place: `Transaction`
+//! only replaces files by renaming them, and gives a file it modifies
+//! a copy of its own first.
+
+use multirust_utils::{self, utils, raw};
+use prefix::InstallPrefix;
+use errors::*;
+
+use component::{Components, FileRecord};
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What `ContentStore::dedup` did to an installation
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DedupStats {
+    /// The number of installed files examined
+    pub files: u64,
+    /// The number of files replaced by a link into the store
+    pub linked: u64,
+    /// The total size of the replaced files
+    pub bytes_saved: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ContentStore {
+    prefix: InstallPrefix,
+}
+
+impl ContentStore {
+    pub fn new(path: PathBuf) -> Self {
+        ContentStore { prefix: InstallPrefix::from(path) }
+    }
+    pub fn path(&self) -> &Path {
+        self.prefix.path()
+    }
+
+    /// Links the installed files of every component into the store.
+    /// A file whose contents the store already has is replaced by a
+    /// link to the stored copy, otherwise it becomes the stored copy.
+    ///
+    /// The caller must stop other processes from changing the
+    /// installation or the store while this runs.
+    pub fn dedup(&self, components: &Components) -> Result<DedupStats> {
+        try!(utils::ensure_dir_exists("content store",
+                                      self.path(),
+                                      multirust_utils::NotifyHandler::none()));
+
+        let prefix = components.prefix();
+        let mut stats = DedupStats::default();
+        let mut verified = HashSet::new();
+
+        for component in try!(components.list()) {
+            for path in try!(component.installed_files()) {
+                let abs_path = prefix.abs_path(&path);
+                let meta = try!(fs::symlink_metadata(&abs_path)
+                                    .map_err(|e| Error::InspectingComponentFile(abs_path.clone(), e)));
+                if !meta.file_type().is_file() {
+                    continue;
+                }
+                stats.files += 1;
+
+                // Files are keyed by mode as well, since the links
+                // to a file all share its permissions
+                let record = try!(FileRecord::from_installed(&prefix, path));
+                let key = format!("{}-{:o}", record.hash, record.mode);
+                let stored = self.prefix.abs_path(&key);
+
+                // A stored file that was written through one of its
+                // links no longer has the contents its name promises,
+                // so it is replaced rather than linked to
+                if !verified.contains(&key) {
+                    if utils::is_file(&stored) && !try!(self.is_intact(&key, &record)) {
+                        try!(utils::remove_file("content store", &stored));
+                    }
+                    verified.insert(key.clone());
+                }
+
+                if !utils::is_file(&stored) {
+                    try!(utils::hardlink_file(&abs_path, &stored));
+                    continue;
+                }
+                let installed_id = try!(identity(&abs_path));
+                let stored_id = try!(identity(&stored));
+                if installed_id.device == stored_id.device && installed_id.index == stored_id.index {
+                    continue;
+                }
+
+                // Link next to the file and rename the link over it,
+                // so the file is never missing
+                let link = link_path(&abs_path);
+                try!(utils::hardlink_file(&stored, &link));
+                try!(utils::rename_file("component", &link, &abs_path));
+                stats.linked += 1;
+                stats.bytes_saved += meta.len();
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Removes the stored files that no installation links to any
+    /// more, returning how many were removed
+    pub fn prune(&self) -> Result<u64> {
+        if !utils::is_directory(self.path()) {
+            return Ok(0);
+        }
+        let mut removed = 0;
+        for entry in try!(utils::read_dir("content store", self.path())) {
+            let entry = try!(entry.map_err(|e| {
+                Error::InspectingComponentFile(self.path().to_owned(), e)
+            }));
+            let path = entry.path();
+            if try!(identity(&path)).links <= 1 {
+                try!(utils::remove_file("content store", &path));
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn is_intact(&self, key: &str, record: &FileRecord) -> Result<bool> {
+        let stored = try!(FileRecord::from_installed(&self.prefix, PathBuf::from(key)));
+        Ok(stored.hash == record.hash && stored.mode == record.mode)
+    }
+}
+
+fn identity(path: &Path) -> Result<raw::FileIdentity> {
+    raw::file_identity(path).map_err(|e| Error::InspectingComponentFile(path.to_owned(), e))
+}
+
+fn link_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(String::new());
+    path.with_file_name(format!(".{}.multirust-link", name))
+}
@@ -6,18 +6,23 @@
 //! operations. If the Transaction is dropped without committing then
 //! it will *attempt* to roll back the transaction.
 //!
-//! FIXME: This uses ensure_dir_exists in some places but rollback
-//! does not remove any dirs created by it.
+//! Each change is recorded in a journal in the install prefix before
+//! it is made. If the process dies before the transaction is
+//! committed or rolled back, `recover_transaction` uses the journal
+//! to finish the job before the prefix is next changed.
 
-use multirust_utils::{self, utils};
+use multirust_utils::{self, utils, raw};
 use temp;
 use prefix::InstallPrefix;
 use errors::*;
 
-use std::fs::File;
-use std::io::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
+/// The journal of the transaction in progress, relative to the prefix
+pub const JOURNAL_FILE: &'static str = ".multirust-journal";
+
 /// A Transaction tracks changes to the file system, allowing them to
 /// be rolled back in case of an error. Instead of deleting or
 /// overwriting file, the old copies are moved to a temporary
@@ -27,7 +32,8 @@ use std::path::{Path, PathBuf};
 ///
 /// All operations that create files will automatically create any
 /// intermediate directories in the path to the file if they do not
-/// already exist.
+/// already exist. Rolling back removes these directories again if
+/// they are empty.
 ///
 /// All operations that create files will fail if the destination
 /// already exists.
@@ -35,6 +41,7 @@ use std::path::{Path, PathBuf};
 pub struct Transaction<'a> {
     prefix: InstallPrefix,
     changes: Vec<ChangedItem<'a>>,
+    journal: Journal,
     temp_cfg: &'a temp::Cfg,
     notify_handler: NotifyHandler<'a>,
     committed: bool,
@@ -46,6 +53,7 @@ impl<'a> Transaction<'a> {
                notify_handler: NotifyHandler<'a>)
                -> Self {
         Transaction {
+            journal: Journal::new(&prefix),
             prefix: prefix,
             changes: Vec::new(),
             temp_cfg: temp_cfg,
@@ -57,19 +65,58 @@ impl<'a> Transaction<'a> {
     /// Commit must be called for all successful transactions. If not
     /// called the transaction will be rolled back on drop.
     pub fn commit(mut self) {
+        // Once the commit is in the journal an interrupted cleanup
+        // is finished rather than rolled back
+        ok_ntfy!(self.notify_handler,
+                 Notification::NonFatalError,
+                 self.journal.append(&JournalEntry::Committed));
         self.committed = true;
+        self.changes.clear();
+        ok_ntfy!(self.notify_handler,
+                 Notification::NonFatalError,
+                 self.journal.remove());
     }
 
     fn change(&mut self, item: ChangedItem<'a>) {
         self.changes.push(item);
     }
 
+    // Creates the missing directories leading to a path relative to
+    // the prefix, outermost first, recording each one so that it can
+    // be removed on rollback.
+    fn create_parent_dirs(&mut self, relpath: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        let mut next = relpath.parent();
+        while let Some(dir) = next {
+            if utils::is_directory(&self.prefix.abs_path(dir)) {
+                break;
+            }
+            missing.push(dir.to_owned());
+            next = dir.parent();
+        }
+
+        for dir in missing.into_iter().rev() {
+            let item = ChangedItem::CreatedDir(dir);
+            try!(self.journal.append(&item.journal_entry()));
+            if let ChangedItem::CreatedDir(ref dir) = item {
+                try!(utils::ensure_dir_exists("component",
+                                              &self.prefix.abs_path(dir),
+                                              multirust_utils::NotifyHandler::none()));
+            }
+            self.change(item);
+        }
+
+        Ok(())
+    }
+
     /// Add a file at a relative path to the install prefix. Returns a
     /// `File` that may be used to subsequently write the
     /// contents.
     pub fn add_file(&mut self, component: &str, relpath: PathBuf) -> Result<File> {
         assert!(relpath.is_relative());
-        let (item, file) = try!(ChangedItem::add_file(&self.prefix, component, relpath));
+        try!(self.create_parent_dirs(&relpath));
+        let (item, file) = try!(ChangedItem::add_file(&self.prefix, component, relpath,
+                                                      &mut self.journal));
         self.change(item);
         Ok(file)
     }
@@ -77,7 +124,9 @@ impl<'a> Transaction<'a> {
     /// Copy a file to a relative path of the install prefix.
     pub fn copy_file(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = try!(ChangedItem::copy_file(&self.prefix, component, relpath, src));
+        try!(self.create_parent_dirs(&relpath));
+        let item = try!(ChangedItem::copy_file(&self.prefix, component, relpath, src,
+                                               &mut self.journal));
         self.change(item);
         Ok(())
     }
@@ -85,7 +134,9 @@ impl<'a> Transaction<'a> {
     /// Recursively copy a directory to a relative path of the install prefix.
     pub fn copy_dir(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = try!(ChangedItem::copy_dir(&self.prefix, component, relpath, src));
+        try!(self.create_parent_dirs(&relpath));
+        let item = try!(ChangedItem::copy_dir(&self.prefix, component, relpath, src,
+                                              &mut self.journal));
         self.change(item);
         Ok(())
     }
@@ -93,7 +144,8 @@ impl<'a> Transaction<'a> {
     /// Remove a file from a relative path to the install prefix.
     pub fn remove_file(&mut self, component: &str, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = try!(ChangedItem::remove_file(&self.prefix, component, relpath, &self.temp_cfg));
+        let item = try!(ChangedItem::remove_file(&self.prefix, component, relpath, &self.temp_cfg,
+                                                 &mut self.journal));
         self.change(item);
         Ok(())
     }
@@ -102,7 +154,8 @@ impl<'a> Transaction<'a> {
     /// install prefix.
     pub fn remove_dir(&mut self, component: &str, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = try!(ChangedItem::remove_dir(&self.prefix, component, relpath, &self.temp_cfg));
+        let item = try!(ChangedItem::remove_dir(&self.prefix, component, relpath, &self.temp_cfg,
+                                                &mut self.journal));
         self.change(item);
         Ok(())
     }
@@ -111,7 +164,9 @@ impl<'a> Transaction<'a> {
     /// the install prefix.
     pub fn write_file(&mut self, component: &str, relpath: PathBuf, content: String) -> Result<()> {
         assert!(relpath.is_relative());
-        let (item, mut file) = try!(ChangedItem::add_file(&self.prefix, component, relpath.clone()));
+        try!(self.create_parent_dirs(&relpath));
+        let (item, mut file) = try!(ChangedItem::add_file(&self.prefix, component, relpath.clone(),
+                                                          &mut self.journal));
         self.change(item);
         try!(write!(file, "{}", content).map_err(|e| {
             multirust_utils::Error::WritingFile {
@@ -126,10 +181,14 @@ impl<'a> Transaction<'a> {
     /// If the file exists back it up for rollback, otherwise ensure that the path
     /// to it exists so that subsequent calls to `File::create` will succeed.
     ///
-    /// This is used for arbitrarily manipulating a file.
+    /// This is used for arbitrarily manipulating a file. An existing
+    /// file is replaced by a copy first, so writing to it never
+    /// changes other hard links to the original.
     pub fn modify_file(&mut self, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = try!(ChangedItem::modify_file(&self.prefix, relpath, &self.temp_cfg));
+        try!(self.create_parent_dirs(&relpath));
+        let item = try!(ChangedItem::modify_file(&self.prefix, relpath, &self.temp_cfg,
+                                                 &mut self.journal));
         self.change(item);
         Ok(())
     }
@@ -151,10 +210,119 @@ impl<'a> Drop for Transaction<'a> {
             for item in self.changes.iter().rev() {
                 ok_ntfy!(self.notify_handler,
                          Notification::NonFatalError,
-                         item.roll_back(&self.prefix));
+                         item.journal_entry().roll_back(&self.prefix));
             }
+            ok_ntfy!(self.notify_handler,
+                     Notification::NonFatalError,
+                     self.journal.remove());
+        }
+    }
+}
+
+/// Finishes a transaction that was interrupted before it was committed
+/// or rolled back, using the journal it left in the prefix. A
+/// transaction that got as far as committing has its backups removed;
+/// anything else is rolled back.
+///
+/// A transaction keeps its journal locked, so one that is still in
+/// progress, in this process or another, is left alone.
+///
+/// Returns whether there was a transaction to recover.
+pub fn recover_transaction(prefix: &InstallPrefix, notify_handler: NotifyHandler) -> Result<bool> {
+    let ref path = prefix.abs_path(JOURNAL_FILE);
+    let mut file = match try!(lock_interrupted_journal(path)) {
+        Some(f) => f,
+        None => return Ok(false),
+    };
+
+    let read_err = |e: io::Error| {
+        multirust_utils::Error::ReadingFile {
+            name: "transaction journal",
+            path: path.clone(),
+            error: e,
+        }
+    };
+
+    // The last line may have been cut off mid-write. Its change was
+    // never made, so it is ignored.
+    let mut content = String::new();
+    try!(file.read_to_string(&mut content).map_err(&read_err));
+    let complete = match content.rfind('\n') {
+        Some(i) => &content[..i + 1],
+        None => "",
+    };
+    let mut entries = Vec::new();
+    for line in complete.lines() {
+        entries.push(try!(JournalEntry::decode(line)
+                              .ok_or_else(|| Error::CorruptTransactionJournal(path.clone()))));
+    }
+
+    notify_handler.call(Notification::RecoveringTransaction(prefix.path()));
+
+    if entries.last() == Some(&JournalEntry::Committed) {
+        for entry in &entries {
+            ok_ntfy!(notify_handler,
+                     Notification::NonFatalError,
+                     entry.remove_backup());
         }
+    } else {
+        notify_handler.call(Notification::RollingBack);
+        for entry in entries.iter().rev() {
+            ok_ntfy!(notify_handler,
+                     Notification::NonFatalError,
+                     entry.roll_back(prefix));
+            ok_ntfy!(notify_handler,
+                     Notification::NonFatalError,
+                     entry.remove_backup());
+        }
+    }
+
+    // Rolling back may have removed the prefix, journal and all
+    if utils::is_file(path) {
+        try!(utils::remove_file("transaction journal", path));
     }
+
+    Ok(true)
+}
+
+/// Whether a transaction was interrupted in the prefix, leaving a
+/// journal for `recover_transaction`. Only commands that hold the
+/// toolchain lock recover it; others can use this to warn that the
+/// installation may be half-changed.
+pub fn has_interrupted_transaction(prefix: &InstallPrefix) -> Result<bool> {
+    Ok(try!(lock_interrupted_journal(&prefix.abs_path(JOURNAL_FILE))).is_some())
+}
+
+// Opens and locks the journal at `path`, unless there is none or the
+// transaction that wrote it is still in progress
+fn lock_interrupted_journal(path: &Path) -> Result<Option<File>> {
+    if !utils::is_file(path) {
+        return Ok(None);
+    }
+
+    let read_err = |e: io::Error| {
+        multirust_utils::Error::ReadingFile {
+            name: "transaction journal",
+            path: path.to_owned(),
+            error: e,
+        }
+    };
+    let file = match File::open(path) {
+        Ok(f) => f,
+        // Finished since we looked
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(read_err(e).into()),
+    };
+    if !try!(raw::lock_file(&file, false).map_err(&read_err)) {
+        return Ok(None);
+    }
+    // The transaction may have finished and removed its journal
+    // between opening and locking it
+    if !utils::is_file(path) {
+        return Ok(None);
+    }
+
+    Ok(Some(file))
 }
 
 /// This is the set of fundamental operations supported on a
@@ -168,34 +336,26 @@ enum ChangedItem<'a> {
     RemovedFile(PathBuf, temp::File<'a>),
     RemovedDir(PathBuf, temp::Dir<'a>),
     ModifiedFile(PathBuf, Option<temp::File<'a>>),
+    /// An intermediate directory created for one of the other changes
+    CreatedDir(PathBuf),
 }
 
 impl<'a> ChangedItem<'a> {
-    fn roll_back(&self, prefix: &InstallPrefix) -> Result<()> {
+    fn journal_entry(&self) -> JournalEntry {
         use self::ChangedItem::*;
         match *self {
-            AddedFile(ref path) => try!(utils::remove_file("component", &prefix.abs_path(path))),
-            AddedDir(ref path) => {
-                try!(utils::remove_dir("component",
-                                       &prefix.abs_path(path),
-                                       multirust_utils::NotifyHandler::none()))
-            }
-            RemovedFile(ref path, ref tmp) | ModifiedFile(ref path, Some(ref tmp)) => {
-                try!(utils::rename_file("component", &tmp, &prefix.abs_path(path)))
-            }
-            RemovedDir(ref path, ref tmp) => {
-                try!(utils::rename_dir("component", &tmp.join("bk"), &prefix.abs_path(path)))
-            }
-            ModifiedFile(ref path, None) => {
-                let abs_path = prefix.abs_path(path);
-                if utils::is_file(&abs_path) {
-                    try!(utils::remove_file("component", &abs_path));
-                }
+            AddedFile(ref path) => JournalEntry::AddedFile(path.clone()),
+            AddedDir(ref path) => JournalEntry::AddedDir(path.clone()),
+            RemovedFile(ref path, ref tmp) => JournalEntry::RemovedFile(path.clone(), tmp.to_path_buf()),
+            RemovedDir(ref path, ref tmp) => JournalEntry::RemovedDir(path.clone(), tmp.to_path_buf()),
+            ModifiedFile(ref path, ref tmp) => {
+                JournalEntry::ModifiedFile(path.clone(), tmp.as_ref().map(|t| t.to_path_buf()))
             }
+            CreatedDir(ref path) => JournalEntry::CreatedDir(path.clone()),
         }
-        Ok(())
     }
-    fn add_file(prefix: &InstallPrefix, component: &str, relpath: PathBuf) -> Result<(Self, File)> {
+    fn add_file(prefix: &InstallPrefix, component: &str, relpath: PathBuf,
+                journal: &mut Journal) -> Result<(Self, File)> {
         let abs_path = prefix.abs_path(&relpath);
         if utils::path_exists(&abs_path) {
             Err(Error::ComponentConflict {
@@ -203,9 +363,8 @@ impl<'a> ChangedItem<'a> {
                 path: relpath.clone(),
             })
         } else {
-            if let Some(p) = abs_path.parent() {
-                try!(utils::ensure_dir_exists("component", p, multirust_utils::NotifyHandler::none()));
-            }
+            let item = ChangedItem::AddedFile(relpath);
+            try!(journal.append(&item.journal_entry()));
             let file = try!(File::create(&abs_path).map_err(|e| {
                 multirust_utils::Error::WritingFile {
                     name: "component",
@@ -213,13 +372,14 @@ impl<'a> ChangedItem<'a> {
                     error: e,
                 }
             }));
-            Ok((ChangedItem::AddedFile(relpath), file))
+            Ok((item, file))
         }
     }
     fn copy_file(prefix: &InstallPrefix,
                  component: &str,
                  relpath: PathBuf,
-                 src: &Path)
+                 src: &Path,
+                 journal: &mut Journal)
                  -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
         if utils::path_exists(&abs_path) {
@@ -228,14 +388,14 @@ impl<'a> ChangedItem<'a> {
                 path: relpath.clone(),
             })
         } else {
-            if let Some(p) = abs_path.parent() {
-                try!(utils::ensure_dir_exists("component", p, multirust_utils::NotifyHandler::none()));
-            }
+            let item = ChangedItem::AddedFile(relpath);
+            try!(journal.append(&item.journal_entry()));
             try!(utils::copy_file(src, &abs_path));
-            Ok(ChangedItem::AddedFile(relpath))
+            Ok(item)
         }
     }
-    fn copy_dir(prefix: &InstallPrefix, component: &str, relpath: PathBuf, src: &Path) -> Result<Self> {
+    fn copy_dir(prefix: &InstallPrefix, component: &str, relpath: PathBuf, src: &Path,
+                journal: &mut Journal) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
         if utils::path_exists(&abs_path) {
             Err(Error::ComponentConflict {
@@ -243,14 +403,14 @@ impl<'a> ChangedItem<'a> {
                 path: relpath.clone(),
             })
         } else {
-            if let Some(p) = abs_path.parent() {
-                try!(utils::ensure_dir_exists("component", p, multirust_utils::NotifyHandler::none()));
-            }
+            let item = ChangedItem::AddedDir(relpath);
+            try!(journal.append(&item.journal_entry()));
             try!(utils::copy_dir(src, &abs_path, multirust_utils::NotifyHandler::none()));
-            Ok(ChangedItem::AddedDir(relpath))
+            Ok(item)
         }
     }
-    fn remove_file(prefix: &InstallPrefix, component: &str, relpath: PathBuf, temp_cfg: &'a temp::Cfg) -> Result<Self> {
+    fn remove_file(prefix: &InstallPrefix, component: &str, relpath: PathBuf, temp_cfg: &'a temp::Cfg,
+                   journal: &mut Journal) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
         let backup = try!(temp_cfg.new_file());
         if !utils::path_exists(&abs_path) {
@@ -259,11 +419,16 @@ impl<'a> ChangedItem<'a> {
                 path: relpath.clone(),
             })
         } else {
-            try!(utils::rename_file("component", &abs_path, &backup));
-            Ok(ChangedItem::RemovedFile(relpath, backup))
+            let item = ChangedItem::RemovedFile(relpath, backup);
+            try!(journal.append(&item.journal_entry()));
+            if let ChangedItem::RemovedFile(_, ref backup) = item {
+                try!(utils::rename_file("component", &abs_path, backup));
+            }
+            Ok(item)
         }
     }
-    fn remove_dir(prefix: &InstallPrefix, component: &str, relpath: PathBuf, temp_cfg: &'a temp::Cfg) -> Result<Self> {
+    fn remove_dir(prefix: &InstallPrefix, component: &str, relpath: PathBuf, temp_cfg: &'a temp::Cfg,
+                  journal: &mut Journal) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
         let backup = try!(temp_cfg.new_directory());
         if !utils::path_exists(&abs_path) {
@@ -272,24 +437,240 @@ impl<'a> ChangedItem<'a> {
                 path: relpath.clone(),
             })
         } else {
-            try!(utils::rename_dir("component", &abs_path, &backup.join("bk")));
-            Ok(ChangedItem::RemovedDir(relpath, backup))
+            let item = ChangedItem::RemovedDir(relpath, backup);
+            try!(journal.append(&item.journal_entry()));
+            if let ChangedItem::RemovedDir(_, ref backup) = item {
+                try!(utils::rename_dir("component", &abs_path, &backup.join("bk")));
+            }
+            Ok(item)
         }
     }
-    fn modify_file(prefix: &InstallPrefix, relpath: PathBuf, temp_cfg: &'a temp::Cfg) -> Result<Self> {
+    fn modify_file(prefix: &InstallPrefix, relpath: PathBuf, temp_cfg: &'a temp::Cfg,
+                   journal: &mut Journal) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
 
-        if utils::is_file(&abs_path) {
+        // The backup is complete before the journal mentions it, so
+        // recovery never restores a partial copy
+        let item = if utils::is_file(&abs_path) {
             let backup = try!(temp_cfg.new_file());
             try!(utils::copy_file(&abs_path, &backup));
-            Ok(ChangedItem::ModifiedFile(relpath, Some(backup)))
+            ChangedItem::ModifiedFile(relpath, Some(backup))
         } else {
-            if let Some(p) = abs_path.parent() {
-                try!(utils::ensure_dir_exists("component", p, multirust_utils::NotifyHandler::none()));
+            ChangedItem::ModifiedFile(relpath, None)
+        };
+        try!(journal.append(&item.journal_entry()));
+
+        // The file may be hard linked into the content store, and so
+        // shared with other installations. Replace it with a copy of
+        // its own before the caller writes to it.
+        if let ChangedItem::ModifiedFile(_, Some(ref backup)) = item {
+            try!(utils::remove_file("component", &abs_path));
+            try!(utils::copy_file(backup, &abs_path));
+        }
+        Ok(item)
+    }
+}
+
+/// A `ChangedItem` as recorded in the journal, with the paths of its
+/// backups instead of the temp files that own them. Each entry is
+/// written before its change is made, so rolling one back must cope
+/// with the change not having happened.
+#[derive(Debug, PartialEq)]
+enum JournalEntry {
+    AddedFile(PathBuf),
+    AddedDir(PathBuf),
+    RemovedFile(PathBuf, PathBuf),
+    RemovedDir(PathBuf, PathBuf),
+    ModifiedFile(PathBuf, Option<PathBuf>),
+    CreatedDir(PathBuf),
+    Committed,
+}
+
+impl JournalEntry {
+    fn encode(&self) -> String {
+        use self::JournalEntry::*;
+        match *self {
+            AddedFile(ref p) => format!("added-file\t{}", p.to_string_lossy()),
+            AddedDir(ref p) => format!("added-dir\t{}", p.to_string_lossy()),
+            RemovedFile(ref p, ref b) => {
+                format!("removed-file\t{}\t{}", p.to_string_lossy(), b.to_string_lossy())
             }
-            Ok(ChangedItem::ModifiedFile(relpath, None))
+            RemovedDir(ref p, ref b) => {
+                format!("removed-dir\t{}\t{}", p.to_string_lossy(), b.to_string_lossy())
+            }
+            ModifiedFile(ref p, Some(ref b)) => {
+                format!("modified-file\t{}\t{}", p.to_string_lossy(), b.to_string_lossy())
+            }
+            ModifiedFile(ref p, None) => format!("modified-file\t{}", p.to_string_lossy()),
+            CreatedDir(ref p) => format!("created-dir\t{}", p.to_string_lossy()),
+            Committed => "committed".to_owned(),
         }
     }
+    fn decode(line: &str) -> Option<Self> {
+        use self::JournalEntry::*;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let entry = match fields.len() {
+            1 if fields[0] == "committed" => Committed,
+            2 => {
+                let p = PathBuf::from(fields[1]);
+                match fields[0] {
+                    "added-file" => AddedFile(p),
+                    "added-dir" => AddedDir(p),
+                    "modified-file" => ModifiedFile(p, None),
+                    "created-dir" => CreatedDir(p),
+                    _ => return None,
+                }
+            }
+            3 => {
+                let p = PathBuf::from(fields[1]);
+                let b = PathBuf::from(fields[2]);
+                match fields[0] {
+                    "removed-file" => RemovedFile(p, b),
+                    "removed-dir" => RemovedDir(p, b),
+                    "modified-file" => ModifiedFile(p, Some(b)),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(entry)
+    }
+    fn roll_back(&self, prefix: &InstallPrefix) -> Result<()> {
+        use self::JournalEntry::*;
+        match *self {
+            AddedFile(ref path) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_file(&abs_path) {
+                    try!(utils::remove_file("component", &abs_path));
+                }
+            }
+            AddedDir(ref path) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::path_exists(&abs_path) {
+                    try!(utils::remove_dir("component",
+                                           &abs_path,
+                                           multirust_utils::NotifyHandler::none()));
+                }
+            }
+            RemovedFile(ref path, ref tmp) => {
+                // If the file is still there it was never moved
+                let abs_path = prefix.abs_path(path);
+                if !utils::path_exists(&abs_path) {
+                    try!(utils::rename_file("component", tmp, &abs_path));
+                }
+            }
+            RemovedDir(ref path, ref tmp) => {
+                let abs_path = prefix.abs_path(path);
+                if !utils::path_exists(&abs_path) {
+                    try!(utils::rename_dir("component", &tmp.join("bk"), &abs_path));
+                }
+            }
+            ModifiedFile(ref path, Some(ref tmp)) => {
+                try!(utils::rename_file("component", tmp, &prefix.abs_path(path)))
+            }
+            ModifiedFile(ref path, None) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_file(&abs_path) {
+                    try!(utils::remove_file("component", &abs_path));
+                }
+            }
+            CreatedDir(ref path) => {
+                // Only remove the directory if nothing outside the
+                // transaction has put files in it. The journal itself
+                // lives in the prefix, which may have been created too.
+                let abs_path = prefix.abs_path(path);
+                if utils::is_directory(&abs_path) {
+                    let mut entries = try!(utils::read_dir("component", &abs_path));
+                    let is_empty = entries.all(|e| {
+                        e.map(|e| e.file_name().to_str() == Some(JOURNAL_FILE)).unwrap_or(false)
+                    });
+                    if is_empty {
+                        try!(utils::remove_dir("component",
+                                               &abs_path,
+                                               multirust_utils::NotifyHandler::none()));
+                    }
+                }
+            }
+            Committed => (),
+        }
+        Ok(())
+    }
+    // Deletes what's left of the backups of a change, after it has
+    // been rolled back or committed
+    fn remove_backup(&self) -> Result<()> {
+        use self::JournalEntry::*;
+        match *self {
+            RemovedFile(_, ref tmp) | ModifiedFile(_, Some(ref tmp)) => {
+                if utils::is_file(tmp) {
+                    try!(utils::remove_file("backup", tmp));
+                }
+            }
+            RemovedDir(_, ref tmp) => {
+                if utils::path_exists(tmp) {
+                    try!(utils::remove_dir("backup", tmp, multirust_utils::NotifyHandler::none()));
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
 }
 
+// The transaction journal, which is created on the first change
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+}
 
+impl Journal {
+    fn new(prefix: &InstallPrefix) -> Self {
+        Journal {
+            path: prefix.abs_path(JOURNAL_FILE),
+            file: None,
+        }
+    }
+    fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        if self.file.is_none() {
+            // The journal goes in the prefix, so that has to exist
+            // first. If it didn't exist the first change is to create
+            // it, which is undone along with the rest.
+            if let Some(p) = self.path.parent() {
+                try!(utils::ensure_dir_exists("transaction journal", p,
+                                              multirust_utils::NotifyHandler::none()));
+            }
+            let file = try!(OpenOptions::new().write(true).append(true).create(true)
+                                              .open(&self.path)
+                                              .map_err(|e| journal_error(&self.path, e)));
+            // Held until the journal is removed, to tell recovery that
+            // the transaction is still alive
+            try!(raw::lock_file(&file, true).map_err(|e| journal_error(&self.path, e)));
+            self.file = Some(file);
+        }
+
+        let ref path = self.path;
+        let file = self.file.as_mut().expect("journal is open");
+        let line = format!("{}\n", entry.encode());
+        try!(file.write_all(line.as_bytes()).map_err(|e| journal_error(path, e)));
+        try!(file.sync_data().map_err(|e| journal_error(path, e)));
+
+        Ok(())
+    }
+    fn remove(&mut self) -> Result<()> {
+        // Removed before it is unlocked, so that recovery doesn't find
+        // it unlocked and think it was abandoned
+        if utils::is_file(&self.path) {
+            try!(utils::remove_file("transaction journal", &self.path));
+        }
+        self.file = None;
+        Ok(())
+    }
+}
+
+fn journal_error(path: &Path, e: io::Error) -> multirust_utils::Error {
+    multirust_utils::Error::WritingFile {
+        name: "transaction journal",
+        path: path.to_owned(),
+        error: e,
+    }
+}
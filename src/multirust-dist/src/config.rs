@@ -2,7 +2,7 @@ use toml;
 
 use toml_utils::*;
 use errors::*;
-use super::manifest::Component;
+use super::manifest::{Component, Profile};
 
 pub const SUPPORTED_CONFIG_VERSIONS: [&'static str; 1] = ["1"];
 pub const DEFAULT_CONFIG_VERSION: &'static str = "1";
@@ -11,6 +11,10 @@ pub const DEFAULT_CONFIG_VERSION: &'static str = "1";
 pub struct Config {
     pub config_version: String,
     pub components: Vec<Component>,
+    pub profile: Option<Profile>,
+    /// Names of components installed from local installers rather
+    /// than from the channel manifest
+    pub local_components: Vec<String>,
 }
 
 impl Config {
@@ -24,9 +28,20 @@ impl Config {
         let components = try!(Self::toml_to_components(components,
                                                        &format!("{}{}.", path, "components")));
 
+        let profile = match try!(get_opt_string(&mut table, "profile", path)) {
+            Some(p) => Some(try!(Profile::from_str(&p))),
+            None => None,
+        };
+
+        let local_components = try!(get_array(&mut table, "local_components", path));
+        let local_components = try!(Self::toml_to_names(local_components,
+                                                        &format!("{}{}", path, "local_components")));
+
         Ok(Config {
             config_version: version,
             components: components,
+            profile: profile,
+            local_components: local_components,
         })
     }
     pub fn to_toml(self) -> toml::Table {
@@ -37,6 +52,13 @@ impl Config {
         if !components.is_empty() {
             result.insert("components".to_owned(), toml::Value::Array(components));
         }
+        if !self.local_components.is_empty() {
+            let names = self.local_components.into_iter().map(toml::Value::String).collect();
+            result.insert("local_components".to_owned(), toml::Value::Array(names));
+        }
+        if let Some(profile) = self.profile {
+            result.insert("profile".to_owned(), toml::Value::String(profile.name().to_owned()));
+        }
         result
     }
 
@@ -64,6 +86,20 @@ impl Config {
         Ok(result)
     }
 
+    fn toml_to_names(arr: toml::Array, path: &str) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+
+        for (i, v) in arr.into_iter().enumerate() {
+            if let toml::Value::String(s) = v {
+                result.push(s);
+            } else {
+                return Err(Error::ExpectedType("string", format!("{}[{}]", path, i)));
+            }
+        }
+
+        Ok(result)
+    }
+
     fn components_to_toml(components: Vec<Component>) -> toml::Array {
         let mut result = toml::Array::new();
         for v in components {
@@ -76,6 +112,8 @@ impl Config {
         Config {
             config_version: DEFAULT_CONFIG_VERSION.to_owned(),
             components: Vec::new(),
+            profile: None,
+            local_components: Vec::new(),
         }
     }
 }
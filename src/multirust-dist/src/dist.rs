@@ -3,10 +3,11 @@ use temp;
 use errors::*;
 use multirust_utils::utils;
 use prefix::InstallPrefix;
-use manifest::Component;
+use manifest::Profile;
 use manifest::Manifest as ManifestV2;
-use manifestation::{Manifestation, UpdateStatus, Changes};
+use manifestation::{Manifestation, UpdateStatus, UpdateSummary, Changes};
 use hyper;
+use time;
 
 use std::path::Path;
 use std::fmt;
@@ -212,6 +213,59 @@ pub fn download_and_check<'a>(url_str: &str,
     Ok(Some((file, partial_hash)))
 }
 
+/// Downloads a file that isn't part of a release, like a custom
+/// installer. It is checked against `hash` if given, and otherwise
+/// against the hash published next to it at `<url>.sha256`, if there
+/// is one. With neither the download fails if `require_hash` is set.
+pub fn download_and_verify<'a>(url_str: &str,
+                               hash: Option<&str>,
+                               require_hash: bool,
+                               cfg: DownloadCfg<'a>)
+                               -> Result<temp::File<'a>> {
+    let hash = match hash {
+        Some(h) => Some(try!(parse_sha256(h).ok_or(Error::InvalidChecksum(h.to_owned())))),
+        None => {
+            match download_hash(url_str, cfg) {
+                Ok(h) => Some(h),
+                Err(Error::Utils(::multirust_utils::errors::Error::DownloadingFile {
+                    error: ::multirust_utils::raw::DownloadError::Status(hyper::status::StatusCode::NotFound),
+                    ..
+                })) => None,
+                Err(e) => return Err(e),
+            }
+        }
+    };
+    if hash.is_none() {
+        if require_hash {
+            return Err(Error::MissingChecksum(url_str.to_owned()));
+        }
+        cfg.notify_handler.call(Notification::NoChecksum(url_str));
+    }
+
+    let url = try!(utils::parse_url(url_str));
+    let file = try!(cfg.temp_cfg.new_file());
+
+    let mut hasher = Hasher::new(Type::SHA256);
+    try!(utils::download_file(url, &file, Some(&mut hasher), ntfy!(&cfg.notify_handler)));
+    let actual_hash = hasher.finish()
+                            .iter()
+                            .map(|b| format!("{:02x}", b))
+                            .join("");
+
+    if let Some(hash) = hash {
+        if hash != actual_hash {
+            return Err(Error::ChecksumFailed {
+                url: url_str.to_owned(),
+                expected: hash,
+                calculated: actual_hash,
+            });
+        }
+        cfg.notify_handler.call(Notification::ChecksumValid(url_str));
+    }
+
+    Ok(file)
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct DownloadCfg<'a> {
     pub dist_root: &'a str,
@@ -261,41 +315,69 @@ pub fn download_hash(url: &str, cfg: DownloadCfg) -> Result<String> {
     let hash_url = try!(utils::parse_url(&(url.to_owned() + ".sha256")));
     let hash_file = try!(cfg.temp_cfg.new_file());
 
-    try!(utils::download_file(hash_url, &hash_file, None, ntfy!(&cfg.notify_handler)));
+    try!(utils::download_file(hash_url.clone(), &hash_file, None, ntfy!(&cfg.notify_handler)));
 
-    Ok(try!(utils::read_file("hash", &hash_file).map(|s| s[0..64].to_owned())))
+    // The checksum may be followed by the file name, as written by
+    // sha256sum
+    let contents = try!(utils::read_file("hash", &hash_file));
+    contents.split_whitespace()
+            .next()
+            .and_then(parse_sha256)
+            .ok_or(Error::InvalidPublishedChecksum(hash_url.serialize()))
+}
+
+/// Normalizes a SHA-256 checksum written as hexadecimal to lower
+/// case, or returns `None` if it isn't one
+pub fn parse_sha256(s: &str) -> Option<String> {
+    if s.len() == 64 && s.chars().all(|c| c.is_digit(16)) {
+        Some(s.to_lowercase())
+    } else {
+        None
+    }
 }
 
 // Installs or updates a toolchain from a dist server. If an initial
 // install then it will be installed with the default components. If
 // an upgrade then all the existing components will be upgraded.
+// Passing a profile selects which components are installed, and
+// otherwise the profile of the previous install is kept. `with` and
+// `without` name extra components to add or remove, as `pkg` for the
+// toolchain's own target or `pkg-target`.
 //
-// Returns the manifest's hash if anything changed.
+// Returns the manifest's hash and a summary of the changes if
+// anything changed.
 pub fn update_from_dist<'a>(download: DownloadCfg<'a>,
                             update_hash: Option<&Path>,
                             toolchain: &str,
                             prefix: &InstallPrefix,
-                            add: &[Component],
-                            remove: &[Component],
-                            ) -> Result<Option<String>> {
+                            with: &[String],
+                            without: &[String],
+                            profile: Option<Profile>,
+                            ) -> Result<Option<(String, UpdateSummary)>> {
+    let start = time::precise_time_s();
 
     let ref toolchain = try!(ToolchainDesc::from_str(toolchain));
     let trip = toolchain.target_triple();
-    let manifestation = try!(Manifestation::open(prefix.clone(), &trip));
+    let manifestation = try!(Manifestation::open(prefix.clone(), &trip,
+                                                  download.notify_handler.clone()));
 
-    let changes = Changes {
-        add_extensions: add.to_owned(),
-        remove_extensions: remove.to_owned(),
-    };
+    // Changing the set of components can change the installation even
+    // when the channel hasn't, so don't skip the update based on the hash
+    let has_changes = profile.is_some() || !with.is_empty() || !without.is_empty();
+    let update_hash = if has_changes { None } else { update_hash };
 
     // TODO: Add a notification about which manifest version is going to be used
     download.notify_handler.call(Notification::DownloadingManifest);
     match dl_v2_manifest(download, update_hash, toolchain) {
         Ok(Some((m, hash))) => {
+            let changes = try!(build_changes(&manifestation, &m, &trip, with, without, profile));
             return match try!(manifestation.update(&m, changes, &download.temp_cfg,
                                                    download.notify_handler.clone())) {
                 UpdateStatus::Unchanged => Ok(None),
-                UpdateStatus::Changed => Ok(Some(hash)),
+                UpdateStatus::Changed(mut summary) => {
+                    summary.elapsed = time::precise_time_s() - start;
+                    Ok(Some((hash, summary)))
+                }
             }
         }
         Ok(None) => return Ok(None),
@@ -309,14 +391,79 @@ pub fn update_from_dist<'a>(download: DownloadCfg<'a>,
         Err(e) => return Err(e)
     }
 
+    // Components can't be selected from v1 manifests
+    if !with.is_empty() || !without.is_empty() {
+        return Err(Error::ComponentsUnsupported(toolchain.manifest_name()));
+    }
+
     // If the v2 manifest is not found then try v1
     let manifest = try!(dl_v1_manifest(download, toolchain)
                         .map_err(|e| Error::NoManifestFound(toolchain.manifest_name(), Box::new(e))));
     match try!(manifestation.update_v1(&manifest, update_hash,
                                        &download.temp_cfg, download.notify_handler.clone())) {
         None => Ok(None),
-        Some(hash) => Ok(Some(hash)),
+        Some(hash) => {
+            let summary = UpdateSummary {
+                elapsed: time::precise_time_s() - start,
+                ..UpdateSummary::default()
+            };
+            Ok(Some((hash, summary)))
+        }
+    }
+}
+
+// Downloads the current v2 manifest of a toolchain's channel without
+// installing anything
+pub fn download_manifest<'a>(download: DownloadCfg<'a>, toolchain: &str) -> Result<ManifestV2> {
+    let ref toolchain = try!(ToolchainDesc::from_str(toolchain));
+
+    download.notify_handler.call(Notification::DownloadingManifest);
+    match dl_v2_manifest(download, None, toolchain) {
+        Ok(Some((m, _))) => Ok(m),
+        // Without an update hash there's nothing to skip the download
+        Ok(None) => Err(Error::ManifestNotDownloaded(toolchain.manifest_name())),
+        Err(e) => Err(Error::NoManifestFound(toolchain.manifest_name(), Box::new(e))),
+    }
+}
+
+/// Resolves the names of components to add and remove, as passed to
+/// `update_from_dist`, against the "rust" package of `manifest`
+pub fn build_changes(manifestation: &Manifestation,
+                 manifest: &ManifestV2,
+                 trip: &str,
+                 with: &[String],
+                 without: &[String],
+                 profile: Option<Profile>) -> Result<Changes> {
+    let config = try!(manifestation.read_config());
+    let installed = config.as_ref().map(|c| c.components.clone()).unwrap_or(Vec::new());
+    let new_profile = profile.or(config.and_then(|c| c.profile)).unwrap_or(Profile::Default);
+    let required = try!(manifest.get_profile_components(new_profile, trip));
+
+    let mut changes = Changes::none();
+    changes.profile = profile;
+
+    for name in with {
+        let component = try!(manifest.find_rust_component(name, trip));
+        // Components in the profile are installed regardless
+        if !required.contains(&component) && !changes.add_extensions.contains(&component) {
+            changes.add_extensions.push(component);
+        }
     }
+    for name in without {
+        let component = try!(manifest.find_rust_component(name, trip));
+        if required.contains(&component) {
+            return Err(Error::RemovingRequiredComponent(component, new_profile));
+        }
+        if changes.add_extensions.contains(&component) {
+            return Err(Error::InvalidChangeSet);
+        }
+        // Nothing to do for components that aren't installed
+        if installed.contains(&component) && !changes.remove_extensions.contains(&component) {
+            changes.remove_extensions.push(component);
+        }
+    }
+
+    Ok(changes)
 }
 
 fn dl_v2_manifest<'a>(download: DownloadCfg<'a>,
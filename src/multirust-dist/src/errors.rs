@@ -7,7 +7,7 @@ use walkdir;
 use toml;
 use multirust_utils;
 use multirust_utils::notify::{self, NotificationLevel, Notifyable};
-use manifest::Component;
+use manifest::{Component, Profile};
 
 #[derive(Debug)]
 pub enum Notification<'a> {
@@ -28,6 +28,14 @@ pub enum Notification<'a> {
     InstallingComponent(&'a str),
     DownloadingManifest,
     DownloadingLegacyManifest,
+    KeepingLocalComponent(&'a str),
+    CantRepairLocalComponent(&'a str),
+    UnknownComponent(&'a str),
+    RecoveringTransaction(&'a Path),
+    InterruptedTransaction(&'a Path),
+    InstallingToStaging(&'a Path),
+    RestoringOldInstallation(&'a Path),
+    NoChecksum(&'a str),
 }
 
 #[derive(Debug)]
@@ -78,6 +86,20 @@ pub enum Error {
     MissingPackageForComponent(Component),
     RequestedComponentsUnavailable(Vec<Component>),
     NoManifestFound(String, Box<Error>),
+    UnknownProfile(String),
+    MissingProfile(String),
+    UnknownComponentName(String),
+    RemovingRequiredComponent(Component, Profile),
+    ComponentsUnsupported(String),
+    InspectingComponentFile(PathBuf, io::Error),
+    CorruptTransactionJournal(PathBuf),
+    UnrecognizedPackage(PathBuf),
+    UnsupportedPackageCompression(PathBuf, &'static str),
+    MissingChecksum(String),
+    InvalidChecksum(String),
+    InvalidPublishedChecksum(String),
+    LocalComponentConflict(String),
+    ManifestNotDownloaded(String),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -98,13 +120,18 @@ impl<'a> Notification<'a> {
             Temp(ref n) => n.level(),
             Utils(ref n) => n.level(),
             ChecksumValid(_) | NoUpdateHash(_) |
-            DownloadingLegacyManifest  => NotificationLevel::Verbose,
+            DownloadingLegacyManifest | InstallingToStaging(_) => NotificationLevel::Verbose,
             Extracting(_, _) | SignatureValid(_)  |
             DownloadingComponent(_) |
             InstallingComponent(_) |
             ComponentAlreadyInstalled(_)  |
             RollingBack | DownloadingManifest => NotificationLevel::Info,
             CantReadUpdateHash(_) | ExtensionNotInstalled(_) |
+            KeepingLocalComponent(_) | CantRepairLocalComponent(_) |
+            UnknownComponent(_) | RecoveringTransaction(_) |
+            InterruptedTransaction(_) |
+            RestoringOldInstallation(_) |
+            NoChecksum(_) |
             MissingInstalledComponent(_) => NotificationLevel::Warn,
             NonFatalError(_) => NotificationLevel::Error,
         }
@@ -140,6 +167,33 @@ impl<'a> Display for Notification<'a> {
             InstallingComponent(c) => write!(f, "installing component '{}'", c),
             DownloadingManifest => write!(f, "downloading toolchain manifest"),
             DownloadingLegacyManifest => write!(f, "manifest not found. trying legacy manifest"),
+            KeepingLocalComponent(c) => {
+                write!(f, "keeping locally installed component '{}'; it is not updated from the channel", c)
+            }
+            CantRepairLocalComponent(c) => {
+                write!(f, "locally installed component '{}' is damaged; reinstall it from its installer", c)
+            }
+            UnknownComponent(c) => {
+                write!(f, "component '{}' is not from the installed manifest; leaving it as it is", c)
+            }
+            RecoveringTransaction(path) => {
+                write!(f, "recovering from an interrupted installation in '{}'", path.display())
+            }
+            InterruptedTransaction(path) => {
+                write!(f,
+                       "an installation in '{}' was interrupted; run `multirust update` or \
+                        `multirust repair` to recover it",
+                       path.display())
+            }
+            InstallingToStaging(path) => {
+                write!(f, "installing to staging directory '{}'", path.display())
+            }
+            RestoringOldInstallation(path) => {
+                write!(f, "restoring '{}', which an interrupted update had moved aside", path.display())
+            }
+            NoChecksum(url) => {
+                write!(f, "no checksum is published for '{}'; it was not verified", url)
+            }
         }
     }
 }
@@ -180,6 +234,20 @@ impl error::Error for Error {
             MissingPackageForComponent(_) => "missing package for component",
             RequestedComponentsUnavailable(_) => "some requested components are unavailable to download",
             NoManifestFound(_, _) => "no release found",
+            UnknownProfile(_) => "unknown installation profile",
+            MissingProfile(_) => "profile not found in manifest",
+            UnknownComponentName(_) => "toolchain does not contain component",
+            RemovingRequiredComponent(_, _) => "required component cannot be removed",
+            ComponentsUnsupported(_) => "toolchain does not support components",
+            InspectingComponentFile(_, _) => "failed to inspect installed file",
+            CorruptTransactionJournal(_) => "corrupt transaction journal",
+            UnrecognizedPackage(_) => "not a rust-installer package",
+            UnsupportedPackageCompression(_, _) => "unsupported package compression",
+            MissingChecksum(_) => "no checksum to verify download",
+            InvalidChecksum(_) => "invalid checksum",
+            InvalidPublishedChecksum(_) => "invalid published checksum",
+            LocalComponentConflict(_) => "local component conflicts with the release",
+            ManifestNotDownloaded(_) => "manifest not downloaded",
         }
     }
 
@@ -193,6 +261,7 @@ impl error::Error for Error {
             ExtractingPackage(ref e) => Some(e),
             ComponentDownloadFailed(_, ref e) => Some(e),
             NoManifestFound(_, ref e) => Some(e),
+            InspectingComponentFile(_, ref e) => Some(e),
             InvalidFileExtension |
             InvalidInstaller |
             InvalidToolchainName(_) |
@@ -217,7 +286,20 @@ impl error::Error for Error {
             MissingRoot |
             UnsupportedVersion(_) |
             MissingPackageForComponent(_) |
-            RequestedComponentsUnavailable(_) => None
+            RequestedComponentsUnavailable(_) |
+            UnknownProfile(_) |
+            MissingProfile(_) |
+            UnknownComponentName(_) |
+            RemovingRequiredComponent(_, _) |
+            ComponentsUnsupported(_) |
+            CorruptTransactionJournal(_) |
+            UnrecognizedPackage(_) |
+            UnsupportedPackageCompression(_, _) |
+            MissingChecksum(_) |
+            InvalidChecksum(_) |
+            InvalidPublishedChecksum(_) |
+            LocalComponentConflict(_) |
+            ManifestNotDownloaded(_) => None
         }
     }
 }
@@ -323,6 +405,48 @@ impl Display for Error {
                     }
                 }
             }
+            UnknownProfile(ref p) => {
+                use itertools::Itertools;
+                write!(f, "unknown profile: '{}', expected one of: {}",
+                       p, Profile::names().iter().join(", "))
+            }
+            MissingProfile(ref p) => write!(f, "manifest does not define profile '{}'", p),
+            UnknownComponentName(ref n) => write!(f, "toolchain does not contain component '{}'", n),
+            RemovingRequiredComponent(ref c, ref p) => {
+                write!(f, "component '{}' for target '{}' is required by the '{}' profile and cannot be removed",
+                       c.pkg, c.target, p)
+            }
+            ComponentsUnsupported(ref t) => write!(f, "toolchain '{}' does not support components", t),
+            InspectingComponentFile(ref p, ref e) => {
+                write!(f, "failed to inspect installed file '{}': {}", p.display(), e)
+            }
+            CorruptTransactionJournal(ref p) => {
+                write!(f, "transaction journal '{}' is corrupt", p.display())
+            }
+            UnrecognizedPackage(ref p) => {
+                write!(f, "'{}' is not a rust-installer directory or tarball", p.display())
+            }
+            UnsupportedPackageCompression(ref p, c) => {
+                write!(f, "'{}' is compressed with {}, which is not supported", p.display(), c)
+            }
+            MissingChecksum(ref url) => {
+                write!(f, "no checksum is published for '{}', and one is required", url)
+            }
+            InvalidChecksum(ref s) => {
+                write!(f, "'{}' is not a SHA-256 checksum of 64 hexadecimal digits", s)
+            }
+            InvalidPublishedChecksum(ref url) => {
+                write!(f, "'{}' does not contain a SHA-256 checksum", url)
+            }
+            LocalComponentConflict(ref name) => {
+                write!(f,
+                       "component '{}' is part of the installed release, and can't be \
+                        installed from a local installer",
+                       name)
+            }
+            ManifestNotDownloaded(ref name) => {
+                write!(f, "the manifest '{}' was not downloaded", name)
+            }
             NoManifestFound(ref ch, ref e) => {
                 use multirust_utils::raw::DownloadError;
                 use hyper::status::StatusCode::NotFound;
@@ -15,6 +15,7 @@ use toml;
 use toml_utils::*;
 
 use std::collections::HashMap;
+use std::fmt;
 
 pub const SUPPORTED_MANIFEST_VERSIONS: [&'static str; 1] = ["2"];
 pub const DEFAULT_MANIFEST_VERSION: &'static str = "2";
@@ -24,6 +25,7 @@ pub struct Manifest {
     pub manifest_version: String,
     pub date: String,
     pub packages: HashMap<String, Package>,
+    pub profiles: HashMap<Profile, Vec<String>>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -47,6 +49,16 @@ pub struct Component {
     pub target: String,
 }
 
+/// A named subset of the "rust" package's components, selected at
+/// install time and recorded in the dist config so that later updates
+/// install the same set.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Profile {
+    Minimal,
+    Default,
+    Complete,
+}
+
 impl Manifest {
     pub fn parse(data: &str) -> Result<Self> {
         let mut parser = toml::Parser::new(data);
@@ -69,6 +81,7 @@ impl Manifest {
         Ok(Manifest {
             manifest_version: version,
             date: try!(get_string(&mut table, "date", path)),
+            profiles: try!(Self::table_to_profiles(&mut table, path)),
             packages: try!(Self::table_to_packages(table, path)),
         })
     }
@@ -82,6 +95,11 @@ impl Manifest {
         let packages = Self::packages_to_table(self.packages);
         result.insert("pkg".to_owned(), toml::Value::Table(packages));
 
+        let profiles = Self::profiles_to_table(self.profiles);
+        if !profiles.is_empty() {
+            result.insert("profiles".to_owned(), toml::Value::Table(profiles));
+        }
+
         result
     }
 
@@ -105,11 +123,81 @@ impl Manifest {
         result
     }
 
+    fn table_to_profiles(table: &mut toml::Table, path: &str) -> Result<HashMap<Profile, Vec<String>>> {
+        let mut result = HashMap::new();
+        let profile_table = try!(get_table(table, "profiles", path));
+
+        for (k, v) in profile_table {
+            // Profiles this version doesn't know about are skipped so
+            // that newer manifests can still be read
+            let profile = if let Ok(p) = Profile::from_str(&k) { p } else { continue };
+            let path = format!("{}profiles.{}", path, k);
+            if let toml::Value::Array(a) = v {
+                let mut pkgs = Vec::new();
+                for (i, v) in a.into_iter().enumerate() {
+                    if let toml::Value::String(s) = v {
+                        pkgs.push(s);
+                    } else {
+                        return Err(Error::ExpectedType("string", format!("{}[{}]", path, i)));
+                    }
+                }
+                result.insert(profile, pkgs);
+            } else {
+                return Err(Error::ExpectedType("array", path));
+            }
+        }
+
+        Ok(result)
+    }
+    fn profiles_to_table(profiles: HashMap<Profile, Vec<String>>) -> toml::Table {
+        let mut result = toml::Table::new();
+        for (k, v) in profiles {
+            let pkgs = v.into_iter().map(toml::Value::String).collect();
+            result.insert(k.name().to_owned(), toml::Value::Array(pkgs));
+        }
+        result
+    }
+
 
     pub fn get_package(&self, name: &str) -> Result<&Package> {
         self.packages.get(name).ok_or_else(|| Error::PackageNotFound(name.to_owned()))
     }
 
+    /// Finds a component or extension of the "rust" package for
+    /// `target` by name, either `pkg` for `target` itself or
+    /// `pkg-target`.
+    pub fn find_rust_component(&self, name: &str, target: &str) -> Result<Component> {
+        let rust_package = try!(self.get_package("rust"));
+        let rust_target_package = try!(rust_package.get_target(target));
+
+        rust_target_package.components.iter()
+            .chain(rust_target_package.extensions.iter())
+            .find(|c| c.name() == name || (c.pkg == name && c.target == target))
+            .cloned()
+            .ok_or_else(|| Error::UnknownComponentName(name.to_owned()))
+    }
+
+    /// The components of the "rust" package for `target` that make up
+    /// `profile`. Manifests without a `[profiles]` table predate
+    /// profiles, and every profile is the full list of components.
+    pub fn get_profile_components(&self, profile: Profile, target: &str) -> Result<Vec<Component>> {
+        let rust_package = try!(self.get_package("rust"));
+        let rust_target_package = try!(rust_package.get_target(target));
+
+        if self.profiles.is_empty() {
+            return Ok(rust_target_package.components.clone());
+        }
+
+        let pkgs = try!(self.profiles.get(&profile)
+                        .ok_or_else(|| Error::MissingProfile(profile.name().to_owned())));
+
+        Ok(rust_target_package.components.iter()
+           .chain(rust_target_package.extensions.iter())
+           .filter(|c| pkgs.contains(&c.pkg) && c.target == target)
+           .cloned()
+           .collect())
+    }
+
     fn validate(&self) -> Result<()> {
         // Every component mentioned must have an actual package to download
         for (_, pkg) in &self.packages {
@@ -237,3 +325,30 @@ impl Component {
     }
 }
 
+impl Profile {
+    pub fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "minimal" => Ok(Profile::Minimal),
+            "default" => Ok(Profile::Default),
+            "complete" => Ok(Profile::Complete),
+            _ => Err(Error::UnknownProfile(name.to_owned())),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Profile::Minimal => "minimal",
+            Profile::Default => "default",
+            Profile::Complete => "complete",
+        }
+    }
+    pub fn names() -> &'static [&'static str] {
+        &["minimal", "default", "complete"]
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
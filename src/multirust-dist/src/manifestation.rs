@@ -2,16 +2,18 @@
 //! platform components from a distribution server.
 
 use config::Config;
-use manifest::{Component, Manifest, TargettedPackage};
+use manifest::{Component, Manifest, Profile, TargettedPackage};
 use dist::{download_and_check, DownloadCfg};
-use component::{Components, Transaction, TarGzPackage, Package};
+use component::{Components, Transaction, TarGzPackage, Package, open_package};
+use component::Component as InstalledComponent;
 use temp;
 use errors::*;
 use multirust_utils::utils;
 use prefix::InstallPrefix;
 use openssl::crypto::hash::{Type, Hasher};
 use itertools::Itertools;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::fs;
 
 pub const DIST_MANIFEST: &'static str = "multirust-channel-manifest.toml";
 pub const CONFIG_FILE: &'static str = "multirust-config.toml";
@@ -26,6 +28,11 @@ pub struct Manifestation {
 pub struct Changes {
     pub add_extensions: Vec<Component>,
     pub remove_extensions: Vec<Component>,
+    /// The profile to install. `None` keeps the profile recorded by
+    /// the previous install, or the default profile.
+    pub profile: Option<Profile>,
+    /// Components from local installers to uninstall, by name
+    pub remove_local: Vec<String>,
 }
 
 impl Changes {
@@ -33,12 +40,32 @@ impl Changes {
         Changes {
             add_extensions: Vec::new(),
             remove_extensions: Vec::new(),
+            profile: None,
+            remove_local: Vec::new(),
         }
     }
 }
 
 #[derive(PartialEq, Debug)]
-pub enum UpdateStatus { Changed, Unchanged }
+pub enum UpdateStatus { Changed(UpdateSummary), Unchanged }
+
+/// Describes what an update changed
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct UpdateSummary {
+    /// The date of the previously installed manifest, if there was one
+    pub old_date: Option<String>,
+    /// The date of the new manifest. Updates from v1 manifests don't
+    /// have one.
+    pub new_date: Option<String>,
+    /// Installed packages whose version changed, as the package name
+    /// with its old and new versions
+    pub packages: Vec<(String, String, String)>,
+    pub added: Vec<Component>,
+    pub removed: Vec<Component>,
+    pub downloaded_bytes: u64,
+    /// The time the update took in seconds, including downloads
+    pub elapsed: f64,
+}
 
 impl Manifestation {
     /// Open the install prefix for updates from a distribution
@@ -46,11 +73,11 @@ impl Manifestation {
     /// it will be created as needed. If there's an existing install
     /// then the rust-install installation format will be verified. A
     /// bad installer version is the only reason this will fail.
-    pub fn open(prefix: InstallPrefix, triple: &str) -> Result<Self> {
+    pub fn open(prefix: InstallPrefix, triple: &str, notify_handler: NotifyHandler) -> Result<Self> {
         // TODO: validate the triple with the existing install as well
         // as the metadata format of the existing install
         Ok(Manifestation {
-            installation: try!(Components::open(prefix)),
+            installation: try!(Components::open(prefix, notify_handler)),
             target_triple: triple.to_string(),
         })
     }
@@ -78,8 +105,6 @@ impl Manifestation {
 
         // Some vars we're going to need a few times
         let prefix = self.installation.prefix();
-        let ref rel_installed_manifest_path = prefix.rel_manifest_file(DIST_MANIFEST);
-        let ref installed_manifest_path = prefix.path().join(rel_installed_manifest_path);
         let rust_package = try!(new_manifest.get_package("rust"));
         let rust_target_package = try!(rust_package.get_target(&self.target_triple));
 
@@ -89,18 +114,48 @@ impl Manifestation {
         // Load the configuration and list of installed components.
         let ref config = try!(self.read_config());
 
+        // The profile used by the previous install, and the one that
+        // this update installs
+        let old_profile = config.as_ref().and_then(|c| c.profile).unwrap_or(Profile::Default);
+        let profile = changes.profile.unwrap_or(old_profile);
+        let profile_components = try!(new_manifest.get_profile_components(profile,
+                                                                          &self.target_triple));
+        // A newer manifest may no longer define the old profile, in
+        // which case the new one stands in for it
+        let old_profile_components = match new_manifest.get_profile_components(
+            old_profile, &self.target_triple) {
+            Ok(c) => c,
+            Err(Error::MissingProfile(_)) => profile_components.clone(),
+            Err(e) => return Err(e),
+        };
+
+        let old_local_components = config.as_ref().map(|c| c.local_components.clone())
+                                         .unwrap_or(Vec::new());
+        let ref remove_local = changes.remove_local.clone();
+        for name in remove_local {
+            if !old_local_components.contains(name) {
+                return Err(Error::UnknownComponentName(name.clone()));
+            }
+        }
+
         // Create the lists of components needed for installation
         let component_lists = try!(build_update_component_lists(new_manifest, old_manifest, config,
                                                                 changes, &rust_target_package,
+                                                                &old_profile_components,
+                                                                &profile_components,
                                                                 notify_handler));
         let (components_to_uninstall,
              components_to_install,
              final_component_list) = component_lists;
 
-        if components_to_uninstall.is_empty() && components_to_install.is_empty() {
+        if components_to_uninstall.is_empty() && components_to_install.is_empty()
+            && remove_local.is_empty() && profile == old_profile {
             return Ok(UpdateStatus::Unchanged);
         }
 
+        let mut summary = summarize_update(old_manifest, new_manifest, config,
+                                           &final_component_list);
+
         // Validate that the requested components are available
         let unavailable_components: Vec<Component> = components_to_install.iter().filter(|c| {
             use manifest::*;
@@ -113,49 +168,47 @@ impl Manifestation {
             return Err(Error::RequestedComponentsUnavailable(unavailable_components));
         }
 
-        // Map components to urls and hashes
-        let mut components_urls_and_hashes: Vec<(Component, String, String)> = Vec::new();
-        for component in components_to_install {
-            let package = try!(new_manifest.get_package(&component.pkg));
-            let target_package = try!(package.get_target(&component.target));
-            let c_u_h = (component, target_package.url.clone(), target_package.hash.clone());
-            components_urls_and_hashes.push(c_u_h);
-        }
-
         // Download component packages and validate hashes
         let mut things_to_install: Vec<(Component, temp::File)> = Vec::new();
-        for (component, url, hash) in components_urls_and_hashes {
-
-            notify_handler.call(Notification::DownloadingComponent(&component.pkg));
-
-            // Download each package to temp file
-            let temp_file = try!(temp_cfg.new_file());
-            let url_url = try!(utils::parse_url(&url));
-
-            let mut hasher = Hasher::new(Type::SHA256);
-            try!(utils::download_file(url_url, &temp_file, Some(&mut hasher), ntfy!(&notify_handler))
-                 .map_err(|e| Error::ComponentDownloadFailed(component.clone(), e)));
-
-            let actual_hash = hasher.finish()
-                                    .iter()
-                                    .map(|b| format!("{:02x}", b))
-                                    .join("");
-
-            if hash != actual_hash {
-                // Incorrect hash
-                return Err(Error::ChecksumFailed {
-                    url: url,
-                    expected: hash,
-                    calculated: actual_hash,
-                });
-            } else {
-                notify_handler.call(Notification::ChecksumValid(&url));
-            }
+        for component in components_to_install {
+            try!(utils::check_cancelled());
+            let installer_file = try!(download_component(new_manifest, &component,
+                                                         temp_cfg, notify_handler));
+            summary.downloaded_bytes += fs::metadata(&*installer_file).map(|m| m.len())
+                                                                      .unwrap_or(0);
+            things_to_install.push((component, installer_file));
+        }
 
-            things_to_install.push((component, temp_file));
+        // Components from local installers aren't part of the
+        // manifest and are left as they are, unless being removed
+        let local_components: Vec<String> = old_local_components.into_iter()
+            .filter(|n| !remove_local.contains(n))
+            .collect();
+        for name in &local_components {
+            notify_handler.call(Notification::KeepingLocalComponent(name));
+        }
+        let mut new_config = Config::new();
+        new_config.components = final_component_list;
+        new_config.profile = Some(profile);
+        new_config.local_components = local_components;
+
+        // A new release replaces every installed component. Doing
+        // that in place pulls the toolchain out from under anything
+        // using it, so instead the release is installed next to it
+        // and swapped in. Local components can't be carried over, so
+        // installations that have them are updated in place.
+        let is_full_reinstall = old_manifest.as_ref() != Some(new_manifest)
+            && new_config.local_components.is_empty()
+            && !try!(self.installation.list()).is_empty();
+
+        if is_full_reinstall {
+            try!(self.update_staged(new_manifest, things_to_install, new_config,
+                                    temp_cfg, notify_handler));
+            return Ok(UpdateStatus::Changed(summary));
         }
 
-        // Begin transaction
+        // Begin transaction. Cancelling from here on drops the
+        // transaction, which rolls back what has been done so far.
         let mut tx = Transaction::new(prefix.clone(), temp_cfg, notify_handler);
 
         // If the previous installation was from a v1 manifest we need
@@ -163,33 +216,44 @@ impl Manifestation {
         tx = try!(self.maybe_handle_v2_upgrade(config, tx));
 
         // Uninstall components
+        for name in remove_local {
+            try!(utils::check_cancelled());
+            if let Some(c) = try!(self.installation.find(name)) {
+                tx = try!(c.uninstall(tx));
+            } else {
+                notify_handler.call(Notification::MissingInstalledComponent(name));
+            }
+        }
         for component in components_to_uninstall {
+            try!(utils::check_cancelled());
             tx = try!(self.uninstall_component(&component, tx, notify_handler.clone()));
         }
 
-        // Install components
-        for (component, installer_file) in things_to_install {
-
-            notify_handler.call(Notification::InstallingComponent(&component.pkg));
+        tx = try!(self.install_release(new_manifest, things_to_install, new_config, temp_cfg, tx));
 
-            let package = try!(TarGzPackage::new_file(&installer_file, temp_cfg));
+        // End transaction
+        try!(utils::check_cancelled());
+        tx.commit();
 
-            // For historical reasons, the rust-installer component
-            // names are not the same as the dist manifest component
-            // names. Some are just the component name some are the
-            // component name plus the target triple.
-            let ref name = format!("{}-{}", component.pkg, component.target);
-            let ref short_name = format!("{}", component.pkg);
+        Ok(UpdateStatus::Changed(summary))
+    }
 
-            // If the package doesn't contain the component that the
-            // manifest says it does the somebody must be playing a joke on us.
-            if !package.contains(name, Some(short_name)) {
-                return Err(Error::CorruptComponent(component.pkg.clone()));
-            }
+    // Installs downloaded components, along with the manifest they
+    // came from and the dist config
+    fn install_release<'a>(&self,
+                           new_manifest: &Manifest,
+                           things_to_install: Vec<(Component, temp::File)>,
+                           config: Config,
+                           temp_cfg: &temp::Cfg,
+                           mut tx: Transaction<'a>) -> Result<Transaction<'a>> {
+        let prefix = self.installation.prefix();
+        let ref rel_installed_manifest_path = prefix.rel_manifest_file(DIST_MANIFEST);
+        let ref installed_manifest_path = prefix.path().join(rel_installed_manifest_path);
 
-            tx = try!(package.install(&self.installation,
-                                      name, Some(short_name),
-                                      tx));
+        // Install components
+        for (component, installer_file) in things_to_install {
+            try!(utils::check_cancelled());
+            tx = try!(self.install_component(&component, &installer_file, temp_cfg, tx));
         }
 
         // Install new distribution manifest
@@ -203,18 +267,84 @@ impl Manifestation {
         // that identify installed components. The rust-installer metadata maintained by
         // `Components` *also* tracks what is installed, but it only tracks names, not
         // name/target. Needs to be fixed in rust-installer.
-        let mut config = Config::new();
-        config.components = final_component_list;
-        let ref config_str = config.stringify();
-        let ref rel_config_path = prefix.rel_manifest_file(CONFIG_FILE);
-        let ref config_path = prefix.path().join(rel_config_path);
-        try!(tx.modify_file(rel_config_path.to_owned()));
-        try!(utils::write_file("dist config", config_path, config_str));
+        tx = try!(self.write_config(config, tx));
 
-        // End transaction
-        tx.commit();
+        Ok(tx)
+    }
+
+    // Installs a release into a staging directory next to the
+    // installation, then renames it into place. The old installation
+    // is renamed out of the way first, and only deleted once the new
+    // one is in place.
+    fn update_staged(&self,
+                     new_manifest: &Manifest,
+                     things_to_install: Vec<(Component, temp::File)>,
+                     config: Config,
+                     temp_cfg: &temp::Cfg,
+                     notify_handler: NotifyHandler) -> Result<()> {
+        let prefix = self.installation.prefix();
+        let ref path = prefix.path().to_owned();
+        let ref staging_path = sibling_path(path, "staging");
+        let ref old_path = sibling_path(path, "old");
+
+        // Left over from an update that was killed. If that left no
+        // installation in place the old one is put back first.
+        try!(recover_staged_update(&prefix, notify_handler));
+        for leftover in &[staging_path, old_path] {
+            if utils::path_exists(leftover) {
+                try!(utils::remove_dir("toolchain", leftover, ntfy!(&notify_handler)));
+            }
+        }
+
+        notify_handler.call(Notification::InstallingToStaging(staging_path));
+
+        let result = Manifestation::open(InstallPrefix::from(staging_path.clone()),
+                                         &self.target_triple,
+                                         notify_handler).and_then(|staging| {
+            let tx = Transaction::new(staging.installation.prefix(), temp_cfg, notify_handler);
+            let tx = try!(staging.install_release(new_manifest, things_to_install, config,
+                                                  temp_cfg, tx));
+            try!(utils::check_cancelled());
+            tx.commit();
+            Ok(())
+        }).and_then(|()| {
+            // Where possible the swap is atomic, so there is always an
+            // installation at `path`
+            if try!(utils::exchange_dirs("toolchain", staging_path, path)) {
+                try!(utils::rename_dir("toolchain", staging_path, old_path));
+                return Ok(());
+            }
 
-        Ok(UpdateStatus::Changed)
+            // Otherwise a crash between these renames leaves only the
+            // old installation, which `recover_staged_update` restores
+            try!(utils::rename_dir("toolchain", path, old_path));
+            if let Err(e) = utils::rename_dir("toolchain", staging_path, path) {
+                ok_ntfy!(notify_handler,
+                         Notification::NonFatalError,
+                         utils::rename_dir("toolchain", old_path, path).map_err(Error::from));
+                return Err(e.into());
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            if utils::path_exists(staging_path) {
+                ok_ntfy!(notify_handler,
+                         Notification::NonFatalError,
+                         utils::remove_dir("toolchain", staging_path, ntfy!(&notify_handler))
+                             .map_err(Error::from));
+            }
+            return Err(e);
+        }
+
+        // Anything still using the old installation keeps what it
+        // has open, so failing to delete it isn't fatal
+        ok_ntfy!(notify_handler,
+                 Notification::NonFatalError,
+                 utils::remove_dir("toolchain", old_path, ntfy!(&notify_handler))
+                     .map_err(Error::from));
+
+        Ok(())
     }
 
     pub fn uninstall(&self, temp_cfg: &temp::Cfg, notify_handler: NotifyHandler) -> Result<()> {
@@ -231,30 +361,287 @@ impl Manifestation {
         for component in config.components {
             tx = try!(self.uninstall_component(&component, tx, notify_handler));
         }
+        for name in config.local_components {
+            if let Some(c) = try!(self.installation.find(&name)) {
+                tx = try!(c.uninstall(tx));
+            } else {
+                notify_handler.call(Notification::MissingInstalledComponent(&name));
+            }
+        }
         tx.commit();
 
         Ok(())
     }
 
-    fn uninstall_component<'a>(&self, component: &Component, mut tx: Transaction<'a>,
-                               notify_handler: NotifyHandler) -> Result<Transaction<'a>> {
+    fn install_component<'a>(&self, component: &Component, installer_file: &Path,
+                             temp_cfg: &temp::Cfg, tx: Transaction<'a>) -> Result<Transaction<'a>> {
+        tx.notify_handler().call(Notification::InstallingComponent(&component.pkg));
+
+        let package = try!(TarGzPackage::new_file(installer_file, temp_cfg));
+
         // For historical reasons, the rust-installer component
         // names are not the same as the dist manifest component
         // names. Some are just the component name some are the
         // component name plus the target triple.
         let ref name = format!("{}-{}", component.pkg, component.target);
         let ref short_name = format!("{}", component.pkg);
+
+        // If the package doesn't contain the component that the
+        // manifest says it does the somebody must be playing a joke on us.
+        if !package.contains(name, Some(short_name)) {
+            return Err(Error::CorruptComponent(component.pkg.clone()));
+        }
+
+        package.install(&self.installation, name, Some(short_name), tx)
+    }
+
+    // Finds the installed rust-installer component for a manifest
+    // component
+    fn find_installed(&self, component: &Component) -> Result<Option<InstalledComponent>> {
+        let ref name = format!("{}-{}", component.pkg, component.target);
+        let ref short_name = format!("{}", component.pkg);
         if let Some(c) = try!(self.installation.find(&name)) {
-            tx = try!(c.uninstall(tx));
-        } else if let Some(c) = try!(self.installation.find(&short_name)) {
+            Ok(Some(c))
+        } else {
+            self.installation.find(&short_name)
+        }
+    }
+
+    // Finds a component that was installed but is missing from the
+    // rust-installer components file
+    fn find_unlisted(&self, component: &Component) -> Result<Option<InstalledComponent>> {
+        let ref name = format!("{}-{}", component.pkg, component.target);
+        let ref short_name = format!("{}", component.pkg);
+        if let Some(c) = try!(self.installation.find_unlisted(&name)) {
+            Ok(Some(c))
+        } else {
+            self.installation.find_unlisted(&short_name)
+        }
+    }
+
+    // Reconstructs a lost dist config from the installed components
+    // that the manifest knows about. The profile is the largest one
+    // the manifest defines whose components are all installed.
+    fn rebuild_config(&self, manifest: &Manifest) -> Result<Config> {
+        let rust_package = try!(manifest.get_package("rust"));
+        let rust_target_package = try!(rust_package.get_target(&self.target_triple));
+
+        let mut config = Config::new();
+        for component in rust_target_package.components.iter()
+                                            .chain(rust_target_package.extensions.iter()) {
+            // Only components for the toolchain's own target may be
+            // installed under their short name
+            let mut names = vec![component.name()];
+            if component.target == self.target_triple {
+                names.push(component.pkg.clone());
+            }
+            let mut installed = false;
+            for name in &names {
+                installed = installed ||
+                            try!(self.installation.find(name)).is_some() ||
+                            try!(self.installation.find_unlisted(name)).is_some();
+            }
+            if installed && !config.components.contains(component) {
+                config.components.push(component.clone());
+            }
+        }
+
+        // Profiles the manifest doesn't define can't match. If none
+        // matches, no profile is recorded and the default is assumed.
+        let mut profile = None;
+        for &p in &[Profile::Minimal, Profile::Default, Profile::Complete] {
+            let profile_components = match manifest.get_profile_components(p, &self.target_triple) {
+                Ok(c) => c,
+                Err(Error::MissingProfile(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            if profile_components.iter().all(|c| config.components.contains(c)) {
+                profile = Some(p);
+            }
+        }
+        config.profile = profile;
+
+        Ok(config)
+    }
+
+    fn uninstall_component<'a>(&self, component: &Component, mut tx: Transaction<'a>,
+                               notify_handler: NotifyHandler) -> Result<Transaction<'a>> {
+        // For historical reasons, the rust-installer component
+        // names are not the same as the dist manifest component
+        // names. Some are just the component name some are the
+        // component name plus the target triple.
+        if let Some(c) = try!(self.find_installed(component)) {
             tx = try!(c.uninstall(tx));
         } else {
+            let ref name = format!("{}-{}", component.pkg, component.target);
             notify_handler.call(Notification::MissingInstalledComponent(&name));
         }
 
         Ok(tx)
     }
 
+    /// Reinstalls the components of an existing installation that are
+    /// missing or whose files no longer match their recorded hashes,
+    /// downloading them again as described by `manifest`, which should
+    /// be the installed manifest. Installed components that neither
+    /// the manifest nor the dist config account for are left alone,
+    /// with a warning.
+    ///
+    /// Returns the repaired components.
+    pub fn repair(&self,
+                  manifest: &Manifest,
+                  temp_cfg: &temp::Cfg,
+                  notify_handler: NotifyHandler) -> Result<Vec<Component>> {
+        let config = match try!(self.read_config()) {
+            Some(c) => c,
+            None => try!(self.rebuild_config(manifest)),
+        };
+
+        // Find the damaged components, with whatever is left of their
+        // installation, and the installed ones that are neither from
+        // the manifest nor local
+        let mut damaged = Vec::new();
+        let mut known_names = Vec::new();
+        for component in &config.components {
+            match try!(self.find_installed(component)) {
+                Some(c) => {
+                    known_names.push(c.name().to_owned());
+                    if !try!(c.verify()).is_intact() {
+                        damaged.push((component.clone(), Some(c)));
+                    }
+                }
+                None => {
+                    let remains = try!(self.find_unlisted(component));
+                    damaged.push((component.clone(), remains));
+                }
+            }
+        }
+        for name in &config.local_components {
+            known_names.push(name.clone());
+            if let Some(c) = try!(self.installation.find(name)) {
+                if !try!(c.verify()).is_intact() {
+                    notify_handler.call(Notification::CantRepairLocalComponent(name));
+                }
+            }
+        }
+        let unknown: Vec<InstalledComponent> = try!(self.installation.list()).into_iter()
+            .filter(|c| !known_names.iter().any(|n| n == c.name()))
+            .collect();
+
+        for c in &unknown {
+            notify_handler.call(Notification::UnknownComponent(c.name()));
+        }
+
+        // A lost config is rewritten even if nothing is damaged
+        let config_exists = utils::is_file(&self.installation.prefix().manifest_file(CONFIG_FILE));
+        if damaged.is_empty() && config_exists {
+            return Ok(Vec::new());
+        }
+
+        let mut things_to_install = Vec::new();
+        for (component, remains) in damaged {
+            let installer_file = try!(download_component(manifest, &component,
+                                                         temp_cfg, notify_handler));
+            things_to_install.push((component, remains, installer_file));
+        }
+
+        // Begin transaction
+        let mut tx = Transaction::new(self.installation.prefix(), temp_cfg, notify_handler);
+
+        let mut repaired = Vec::new();
+        for (component, remains, installer_file) in things_to_install {
+            if let Some(c) = remains {
+                tx = try!(c.uninstall_damaged(tx));
+            }
+            tx = try!(self.install_component(&component, &installer_file, temp_cfg, tx));
+            repaired.push(component);
+        }
+
+        tx = try!(self.write_config(config, tx));
+
+        // End transaction
+        tx.commit();
+
+        Ok(repaired)
+    }
+
+    /// Installs every component of a local installer, which may be a
+    /// .tar.gz or .tar tarball or an unpacked directory, recording them
+    /// in the dist config as local components. Installing a component
+    /// that is already installed locally replaces it. Components of
+    /// the installed release can't be replaced this way, since updates
+    /// and repairs would then treat them as their own.
+    ///
+    /// Returns the names of the installed components.
+    pub fn install_local(&self,
+                         installer: &Path,
+                         temp_cfg: &temp::Cfg,
+                         notify_handler: NotifyHandler) -> Result<Vec<String>> {
+        let prefix = self.installation.prefix();
+        let mut config = try!(self.read_config()).unwrap_or(Config::new());
+
+        let package = try!(open_package(installer, temp_cfg));
+
+        // The names a component of the release may be installed as
+        let mut release_names = Vec::new();
+        for component in &config.components {
+            release_names.push(component.name());
+        }
+        if let Some(manifest) = try!(self.load_manifest()) {
+            let rust_package = try!(manifest.get_package("rust"));
+            if let Ok(rust_target_package) = rust_package.get_target(&self.target_triple) {
+                for component in rust_target_package.components.iter()
+                                     .chain(rust_target_package.extensions.iter()) {
+                    release_names.push(component.name());
+                    if component.target == self.target_triple {
+                        release_names.push(component.pkg.clone());
+                    }
+                }
+            }
+        }
+        let names = package.components();
+        for name in &names {
+            if release_names.contains(name) {
+                return Err(Error::LocalComponentConflict(name.clone()));
+            }
+        }
+
+        // Begin transaction
+        let mut tx = Transaction::new(prefix.clone(), temp_cfg, notify_handler);
+
+        for name in &names {
+            if config.local_components.contains(name) {
+                if let Some(c) = try!(self.installation.find(name)) {
+                    tx = try!(c.uninstall(tx));
+                }
+            } else {
+                config.local_components.push(name.clone());
+            }
+
+            notify_handler.call(Notification::InstallingComponent(name));
+
+            tx = try!(package.install(&self.installation, name, None, tx));
+        }
+
+        tx = try!(self.write_config(config, tx));
+
+        // End transaction
+        tx.commit();
+
+        Ok(names)
+    }
+
+    fn write_config<'a>(&self, config: Config, mut tx: Transaction<'a>) -> Result<Transaction<'a>> {
+        let prefix = self.installation.prefix();
+        let ref config_str = config.stringify();
+        let ref rel_config_path = prefix.rel_manifest_file(CONFIG_FILE);
+        let ref config_path = prefix.path().join(rel_config_path);
+        try!(tx.modify_file(rel_config_path.to_owned()));
+        try!(utils::write_file("dist config", config_path, config_str));
+
+        Ok(tx)
+    }
+
     // Read the config file. Config files are presently only created
     // for v2 installations.
     pub fn read_config(&self) -> Result<Option<Config>> {
@@ -366,18 +753,27 @@ fn build_update_component_lists(
     config: &Option<Config>,
     changes: Changes,
     rust_target_package: &TargettedPackage,
+    old_profile_components: &[Component],
+    profile_components: &[Component],
     notify_handler: NotifyHandler,
     ) -> Result<(Vec<Component>, Vec<Component>, Vec<Component>)> {
 
+    // Components of the rust package that may be added or removed
+    // individually: extensions, plus components the profile leaves out
+    let is_optional = |c: &Component| {
+        (rust_target_package.extensions.contains(c) || rust_target_package.components.contains(c))
+            && !profile_components.contains(c)
+    };
+
     // Check some invariantns
     for component_to_add in &changes.add_extensions {
-        assert!(rust_target_package.extensions.contains(component_to_add),
+        assert!(is_optional(component_to_add),
                 "package must contain extension to add");
         assert!(!changes.remove_extensions.contains(component_to_add),
                 "can't both add and remove extensions");
     }
     for component_to_remove in &changes.remove_extensions {
-        assert!(rust_target_package.extensions.contains(component_to_remove),
+        assert!(is_optional(component_to_remove),
                 "package must contain extension to remove");
         let config = config.as_ref().expect("removing extension on fresh install?");
         assert!(config.components.contains(component_to_remove),
@@ -395,12 +791,12 @@ fn build_update_component_lists(
     let mut components_to_install = Vec::new();
 
     // Find the final list of components we want to be left with when
-    // we're done: required components, added extensions, and existing
-    // installed extensions.
+    // we're done: components in the profile, added extensions, and
+    // existing installed extensions.
 
-    // Add components required by the package, according to the
+    // Add components required by the profile, according to the
     // manifest
-    for required_component in &rust_target_package.components {
+    for required_component in profile_components {
         final_component_list.push(required_component.clone());
     }
 
@@ -409,9 +805,12 @@ fn build_update_component_lists(
         final_component_list.push(extension.clone());
     }
 
-    // Add extensions that are already installed
+    // Add extensions that are already installed. Components that
+    // were only installed because the previous profile asked for
+    // them are left to the new profile.
     for existing_component in &starting_list {
-        let is_extension = rust_target_package.extensions.contains(existing_component);
+        let is_extension = is_optional(existing_component)
+            && !old_profile_components.contains(existing_component);
         let is_removed = changes.remove_extensions.contains(existing_component);
         let is_already_included = final_component_list.contains(existing_component);
 
@@ -451,3 +850,115 @@ fn build_update_component_lists(
 
     Ok((components_to_uninstall, components_to_install, final_component_list))
 }
+
+/// Puts back the old installation set aside by a staged update that
+/// was killed before the new one was moved into its place. Returns
+/// whether anything was restored.
+pub fn recover_staged_update(prefix: &InstallPrefix, notify_handler: NotifyHandler) -> Result<bool> {
+    let ref path = prefix.path().to_owned();
+    let ref old_path = sibling_path(path, "old");
+    // Even a broken link at `path` means it wasn't left empty
+    if fs::symlink_metadata(path).is_ok() || !utils::is_directory(old_path) {
+        return Ok(false);
+    }
+
+    notify_handler.call(Notification::RestoringOldInstallation(path));
+    try!(utils::rename_dir("toolchain", old_path, path));
+    Ok(true)
+}
+
+// A hidden directory next to an installation, e.g. `.nightly.staging`
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(String::new());
+    path.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+// Compares the installed components and their package versions
+// before and after an update
+fn summarize_update(old_manifest: &Option<Manifest>,
+                    new_manifest: &Manifest,
+                    old_config: &Option<Config>,
+                    new_components: &[Component]) -> UpdateSummary {
+    let old_components = old_config.as_ref().map(|c| c.components.clone()).unwrap_or(Vec::new());
+
+    // Only packages that were installed before can have changed
+    let was_installed = |pkg: &str| old_components.iter().any(|c| c.pkg == pkg);
+    let mut pkgs: Vec<&str> = new_components.iter()
+                                            .map(|c| &*c.pkg)
+                                            .filter(|pkg| was_installed(pkg))
+                                            .collect();
+    pkgs.sort();
+    pkgs.dedup();
+
+    let packages = pkgs.into_iter().filter_map(|pkg| {
+        let old_version = old_manifest.as_ref()
+                                      .and_then(|m| m.get_package(pkg).ok())
+                                      .map(|p| p.version.clone());
+        let new_version = new_manifest.get_package(pkg).ok().map(|p| p.version.clone());
+        match (old_version, new_version) {
+            (Some(old), Some(new)) => {
+                if old != new {
+                    Some((pkg.to_owned(), old, new))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }).collect();
+
+    UpdateSummary {
+        old_date: old_manifest.as_ref().map(|m| m.date.clone()),
+        new_date: Some(new_manifest.date.clone()),
+        packages: packages,
+        added: new_components.iter()
+                             .filter(|c| !old_components.contains(c))
+                             .cloned()
+                             .collect(),
+        removed: old_components.iter()
+                               .filter(|c| !new_components.contains(c))
+                               .cloned()
+                               .collect(),
+        downloaded_bytes: 0,
+        elapsed: 0.0,
+    }
+}
+
+// Downloads the package of a component, validating its hash
+fn download_component<'a>(manifest: &Manifest,
+                          component: &Component,
+                          temp_cfg: &'a temp::Cfg,
+                          notify_handler: NotifyHandler) -> Result<temp::File<'a>> {
+    let package = try!(manifest.get_package(&component.pkg));
+    let target_package = try!(package.get_target(&component.target));
+    let ref url = target_package.url;
+    let ref hash = target_package.hash;
+
+    notify_handler.call(Notification::DownloadingComponent(&component.pkg));
+
+    // Download each package to temp file
+    let temp_file = try!(temp_cfg.new_file());
+    let url_url = try!(utils::parse_url(url));
+
+    let mut hasher = Hasher::new(Type::SHA256);
+    try!(utils::download_file(url_url, &temp_file, Some(&mut hasher), ntfy!(&notify_handler))
+         .map_err(|e| Error::ComponentDownloadFailed(component.clone(), e)));
+
+    let actual_hash = hasher.finish()
+                            .iter()
+                            .map(|b| format!("{:02x}", b))
+                            .join("");
+
+    if *hash != actual_hash {
+        // Incorrect hash
+        return Err(Error::ChecksumFailed {
+            url: url.clone(),
+            expected: hash.clone(),
+            calculated: actual_hash,
+        });
+    } else {
+        notify_handler.call(Notification::ChecksumValid(url));
+    }
+
+    Ok(temp_file)
+}
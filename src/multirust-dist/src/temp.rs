@@ -5,6 +5,7 @@ use std::io;
 use std::ops;
 use std::fmt::{self, Display};
 use multirust_utils::raw;
+use time;
 
 use multirust_utils::notify::{self, NotificationLevel, Notifyable};
 
@@ -22,6 +23,10 @@ pub enum Error {
         path: PathBuf,
         error: io::Error,
     },
+    ReadingRoot {
+        path: PathBuf,
+        error: io::Error,
+    },
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -103,6 +108,7 @@ impl error::Error for Error {
             CreatingRoot {..} => "could not create temp root",
             CreatingFile {..} => "could not create temp file",
             CreatingDirectory {..} => "could not create temp directory",
+            ReadingRoot {..} => "could not read temp root",
         }
     }
 
@@ -111,7 +117,8 @@ impl error::Error for Error {
         match *self {
             CreatingRoot { ref error, .. } |
             CreatingFile { ref error, .. } |
-            CreatingDirectory { ref error, .. } => Some(error),
+            CreatingDirectory { ref error, .. } |
+            ReadingRoot { ref error, .. } => Some(error),
         }
     }
 }
@@ -129,6 +136,9 @@ impl Display for Error {
             CreatingDirectory { ref path, error: _ } => {
                 write!(f, "could not create temp directory: {}", path.display())
             }
+            ReadingRoot { ref path, error: _ } => {
+                write!(f, "could not read temp root: {}", path.display())
+            }
         }
     }
 }
@@ -141,6 +151,10 @@ impl Cfg {
         }
     }
 
+    pub fn root_directory(&self) -> &Path {
+        &self.root_directory
+    }
+
     pub fn create_root(&self) -> Result<bool> {
         raw::ensure_dir_exists(&self.root_directory, |p| {
             self.notify_handler.call(Notification::CreatingRoot(p));
@@ -157,7 +171,7 @@ impl Cfg {
         try!(self.create_root());
 
         loop {
-            let temp_name = raw::random_string(16) + "_dir";
+            let temp_name = entry_name(raw::random_string(16) + "_dir");
 
             let temp_dir = self.root_directory.join(temp_name);
 
@@ -187,7 +201,7 @@ impl Cfg {
         try!(self.create_root());
 
         loop {
-            let temp_name = prefix.to_owned() + &raw::random_string(16) + "_file" + ext;
+            let temp_name = entry_name(prefix.to_owned() + &raw::random_string(16) + "_file" + ext);
 
             let temp_file = self.root_directory.join(temp_name);
 
@@ -208,6 +222,76 @@ impl Cfg {
             }
         }
     }
+
+    /// Removes the entries left behind by processes that have exited,
+    /// and any older than `max_age` seconds, which may belong to a
+    /// process whose pid has been reused. Entries made by older
+    /// versions don't record their owner and are removed as well.
+    /// Returns the number of entries removed.
+    pub fn clean(&self, max_age: i64) -> Result<usize> {
+        if !raw::is_directory(&self.root_directory) {
+            return Ok(0);
+        }
+
+        let read_err = |e: io::Error| {
+            Error::ReadingRoot {
+                path: PathBuf::from(&self.root_directory),
+                error: e,
+            }
+        };
+
+        let now = time::get_time().sec;
+        let mut removed = 0;
+        for entry in try!(fs::read_dir(&self.root_directory).map_err(&read_err)) {
+            let entry = try!(entry.map_err(&read_err));
+            let is_stale = match entry.file_name().to_str().and_then(entry_owner) {
+                Some((pid, created)) => !raw::process_exists(pid) || now - created > max_age,
+                None => true,
+            };
+            if !is_stale {
+                continue;
+            }
+
+            // Failures are reported but don't stop the sweep
+            let path = entry.path();
+            let is_removed = if raw::is_directory(&path) {
+                let result = raw::remove_dir(&path);
+                let is_ok = result.is_ok();
+                self.notify_handler.call(Notification::DirectoryDeletion(&path, result));
+                is_ok
+            } else {
+                let result = fs::remove_file(&path);
+                let is_ok = result.is_ok();
+                self.notify_handler.call(Notification::FileDeletion(&path, result));
+                is_ok
+            };
+            if is_removed {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+// Temp entries are named `<pid>-<time>-<name>` after the process that
+// made them and when, so that `Cfg::clean` can tell which ones were
+// left behind by crashed runs
+fn entry_name(name: String) -> String {
+    format!("{}-{}-{}", raw::process_id(), time::get_time().sec, name)
+}
+
+fn entry_owner(name: &str) -> Option<(u32, i64)> {
+    let mut fields = name.splitn(3, '-');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(pid), Some(created), Some(_)) => {
+            match (pid.parse(), created.parse()) {
+                (Ok(pid), Ok(created)) => Some((pid, created)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
 }
 
 impl<'a> ops::Deref for Dir<'a> {
@@ -48,3 +48,15 @@ pub fn get_array(table: &mut toml::Table, key: &str, path: &str) -> Result<toml:
         Ok(toml::Array::new())
     }
 }
+
+pub fn get_opt_string(table: &mut toml::Table, key: &str, path: &str) -> Result<Option<String>> {
+    if let Some(v) = table.remove(key) {
+        if let toml::Value::String(s) = v {
+            Ok(Some(s))
+        } else {
+            Err(Error::ExpectedType("string", path.to_owned() + key))
+        }
+    } else {
+        Ok(None)
+    }
+}
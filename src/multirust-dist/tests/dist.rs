@@ -23,10 +23,10 @@ use multirust_utils::utils;
 use multirust_utils::raw as utils_raw;
 use multirust_dist::temp;
 use multirust_dist::manifestation::{Manifestation, UpdateStatus, Changes};
-use multirust_dist::manifest::{Manifest, Component};
+use multirust_dist::manifest::{Manifest, Component, Profile};
 use hyper::Url;
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::Path;
 use tempdir::TempDir;
 use itertools::Itertools;
@@ -50,7 +50,7 @@ pub fn create_mock_channel(channel: &str, date: &str,
 
     let rust_pkg = MockPackage {
         name: "rust",
-        version: "1.0.0",
+        version: "1.0.0".to_owned(),
         targets: vec![
             MockTargettedPackage {
                 target: "x86_64-apple-darwin".to_string(),
@@ -102,7 +102,7 @@ pub fn create_mock_channel(channel: &str, date: &str,
 
     let rustc_pkg = MockPackage {
         name: "rustc",
-        version: "1.0.0",
+        version: format!("1.0.0 ({})", date),
         targets: vec![
             MockTargettedPackage {
                 target: "x86_64-apple-darwin".to_string(),
@@ -136,7 +136,7 @@ pub fn create_mock_channel(channel: &str, date: &str,
 
     let std_pkg = MockPackage {
         name: "rust-std",
-        version: "1.0.0",
+        version: "1.0.0".to_owned(),
         targets: vec![
             MockTargettedPackage {
                 target: "x86_64-apple-darwin".to_string(),
@@ -199,7 +199,7 @@ pub fn create_mock_channel(channel: &str, date: &str,
     // for various tests
     let bonus_pkg = MockPackage {
         name: "bonus",
-        version: "1.0.0",
+        version: "1.0.0".to_owned(),
         targets: vec![
             MockTargettedPackage {
                 target: "x86_64-apple-darwin".to_string(),
@@ -269,6 +269,18 @@ fn update_from_dist(dist_server: &Url,
                     remove: &[Component],
                     temp_cfg: &temp::Cfg,
                     notify_handler: NotifyHandler) -> Result<UpdateStatus, Error> {
+    update_from_dist_with_profile(dist_server, toolchain, prefix, add, remove, None,
+                                  temp_cfg, notify_handler)
+}
+
+fn update_from_dist_with_profile(dist_server: &Url,
+                                 toolchain: &ToolchainDesc,
+                                 prefix: &InstallPrefix,
+                                 add: &[Component],
+                                 remove: &[Component],
+                                 profile: Option<Profile>,
+                                 temp_cfg: &temp::Cfg,
+                                 notify_handler: NotifyHandler) -> Result<UpdateStatus, Error> {
 
     // Download the dist manifest and place it into the installation prefix
     let ref manifest_url = try!(make_manifest_url(dist_server, toolchain));
@@ -283,11 +295,13 @@ fn update_from_dist(dist_server: &Url,
 
     // Read the manifest to update the components
     let trip = toolchain.target_triple();
-    let manifestation = try!(Manifestation::open(prefix.clone(), &trip));
+    let manifestation = try!(Manifestation::open(prefix.clone(), &trip, notify_handler.clone()));
 
     let changes = Changes {
         add_extensions: add.to_owned(),
         remove_extensions: remove.to_owned(),
+        profile: profile,
+        remove_local: Vec::new(),
     };
 
     manifestation.update(&manifest, changes, temp_cfg, notify_handler.clone())
@@ -308,7 +322,7 @@ fn make_manifest_url(dist_server: &Url, toolchain: &ToolchainDesc) -> Result<Url
 fn uninstall(toolchain: &ToolchainDesc, prefix: &InstallPrefix, temp_cfg: &temp::Cfg,
              notify_handler: NotifyHandler) -> Result<(), Error> {
     let trip = toolchain.target_triple();
-    let manifestation = try!(Manifestation::open(prefix.clone(), &trip));
+    let manifestation = try!(Manifestation::open(prefix.clone(), &trip, notify_handler.clone()));
 
     try!(manifestation.uninstall(temp_cfg, notify_handler.clone()));
 
@@ -375,6 +389,53 @@ fn upgrade() {
     });
 }
 
+// Full upgrades install the new release beside the old one and swap
+// it in, rather than replacing the installation's files in place
+#[test]
+fn upgrade_swaps_in_staged_installation() {
+    setup(None, &|url, toolchain, prefix, temp_cfg| {
+        change_channel_date(url, "nightly", "2016-02-01");
+        update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
+
+        let ref rustc = prefix.path().join("bin/rustc");
+        let mut open_rustc = fs::File::open(rustc).unwrap();
+        // Not part of any component, so only an update in place keeps it
+        utils_raw::write_file(&prefix.path().join("stray"), "").unwrap();
+
+        change_channel_date(url, "nightly", "2016-02-02");
+        update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
+
+        let mut old_rustc = String::new();
+        open_rustc.read_to_string(&mut old_rustc).unwrap();
+        assert_eq!(old_rustc, "2016-02-01");
+        assert_eq!("2016-02-02", utils_raw::read_file(rustc).unwrap());
+        assert!(!utils::path_exists(&prefix.path().join("stray")));
+
+        let name = prefix.path().file_name().unwrap().to_string_lossy().into_owned();
+        assert!(!utils::path_exists(&prefix.path().with_file_name(format!(".{}.staging", name))));
+        assert!(!utils::path_exists(&prefix.path().with_file_name(format!(".{}.old", name))));
+    });
+}
+
+#[test]
+fn add_extension_updates_in_place() {
+    setup(None, &|url, toolchain, prefix, temp_cfg| {
+        update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
+        utils_raw::write_file(&prefix.path().join("stray"), "").unwrap();
+
+        let ref adds = vec![
+            Component {
+                pkg: "rust-std".to_string(), target: "i686-apple-darwin".to_string()
+            }
+            ];
+
+        update_from_dist(url, toolchain, prefix, adds, &[], temp_cfg, NotifyHandler::none()).unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("lib/i686-apple-darwin/libstd.rlib")));
+        assert!(utils::path_exists(&prefix.path().join("stray")));
+    });
+}
+
 #[test]
 fn update_removes_components_that_dont_exist() {
     // On day 1 install the 'bonus' component, on day 2 its no londer a component
@@ -491,12 +552,48 @@ fn update_preserves_components_that_became_extensions() {
 fn update_makes_no_changes_for_identical_manifest() {
     setup(None, &|url, toolchain, prefix, temp_cfg| {
         let status = update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
-        assert_eq!(status, UpdateStatus::Changed);
+        assert!(status != UpdateStatus::Unchanged);
         let status = update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
         assert_eq!(status, UpdateStatus::Unchanged);
     });
 }
 
+#[test]
+fn update_summarizes_changes() {
+    // On day 1 install the 'bonus' component, on day 2 its no longer a component
+    let edit = &|date: &str, pkg: &mut MockPackage| {
+        if date == "2016-02-01" {
+            let mut tpkg = pkg.targets.iter_mut().find(|p| p.target == "x86_64-apple-darwin").unwrap();
+            tpkg.components.push(MockComponent {
+                name: "bonus".to_string(),
+                target: "x86_64-apple-darwin".to_string(),
+            });
+        }
+    };
+    setup(Some(edit), &|url, toolchain, prefix, temp_cfg| {
+        change_channel_date(url, "nightly", "2016-02-01");
+        update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
+        change_channel_date(url, "nightly", "2016-02-02");
+        let status = update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
+
+        let summary = match status {
+            UpdateStatus::Changed(summary) => summary,
+            UpdateStatus::Unchanged => panic!("expected the update to change the installation"),
+        };
+        assert_eq!(summary.old_date, Some("2016-02-01".to_owned()));
+        assert_eq!(summary.new_date, Some("2016-02-02".to_owned()));
+        assert_eq!(summary.packages, vec![("rustc".to_owned(),
+                                           "1.0.0 (2016-02-01)".to_owned(),
+                                           "1.0.0 (2016-02-02)".to_owned())]);
+        assert!(summary.added.is_empty());
+        assert_eq!(summary.removed, vec![Component {
+            pkg: "bonus".to_owned(),
+            target: "x86_64-apple-darwin".to_owned(),
+        }]);
+        assert!(summary.downloaded_bytes > 0);
+    });
+}
+
 #[test]
 fn add_extensions_for_initial_install() {
     setup(None, &|url, toolchain, prefix, temp_cfg| {
@@ -923,3 +1020,55 @@ fn unable_to_download_component() {
         }
     });
 }
+
+#[test]
+fn install_minimal_profile() {
+    setup(None, &|url, toolchain, prefix, temp_cfg| {
+        add_channel_profiles(url, "nightly", &[("minimal", &["rustc"]),
+                                               ("default", &["rustc", "rust-std"])]);
+
+        update_from_dist_with_profile(url, toolchain, prefix, &[], &[], Some(Profile::Minimal),
+                                      temp_cfg, NotifyHandler::none()).unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert!(!utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+    });
+}
+
+#[test]
+fn update_keeps_installed_profile() {
+    setup(None, &|url, toolchain, prefix, temp_cfg| {
+        change_channel_date(url, "nightly", "2016-02-01");
+        add_channel_profiles(url, "nightly", &[("minimal", &["rustc"]),
+                                               ("default", &["rustc", "rust-std"])]);
+        update_from_dist_with_profile(url, toolchain, prefix, &[], &[], Some(Profile::Minimal),
+                                      temp_cfg, NotifyHandler::none()).unwrap();
+
+        change_channel_date(url, "nightly", "2016-02-02");
+        add_channel_profiles(url, "nightly", &[("minimal", &["rustc"]),
+                                               ("default", &["rustc", "rust-std"])]);
+        update_from_dist(url, toolchain, prefix, &[], &[], temp_cfg, NotifyHandler::none()).unwrap();
+
+        assert_eq!("2016-02-02", utils_raw::read_file(&prefix.path().join("bin/rustc")).unwrap());
+        assert!(!utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+    });
+}
+
+#[test]
+fn change_profile_for_same_manifest() {
+    setup(None, &|url, toolchain, prefix, temp_cfg| {
+        add_channel_profiles(url, "nightly", &[("minimal", &["rustc"]),
+                                               ("default", &["rustc", "rust-std"])]);
+        update_from_dist_with_profile(url, toolchain, prefix, &[], &[], Some(Profile::Minimal),
+                                      temp_cfg, NotifyHandler::none()).unwrap();
+        assert!(!utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+
+        update_from_dist_with_profile(url, toolchain, prefix, &[], &[], Some(Profile::Default),
+                                      temp_cfg, NotifyHandler::none()).unwrap();
+        assert!(utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+
+        update_from_dist_with_profile(url, toolchain, prefix, &[], &[], Some(Profile::Minimal),
+                                      temp_cfg, NotifyHandler::none()).unwrap();
+        assert!(!utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+    });
+}
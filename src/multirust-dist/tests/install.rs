@@ -11,6 +11,7 @@ use multirust_utils::utils;
 use multirust_dist::NotifyHandler;
 use multirust_dist::prefix::InstallPrefix;
 use std::fs::File;
+use std::path::PathBuf;
 use std::io::Write;
 use tempdir::TempDir;
 use multirust_mock::{MockInstallerBuilder, MockCommand};
@@ -113,7 +114,7 @@ fn basic_install() {
     let notify = NotifyHandler::none();
     let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
 
-    let components = Components::open(prefix.clone()).unwrap();
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
 
     let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
 
@@ -152,7 +153,7 @@ fn multiple_component_install() {
     let notify = NotifyHandler::none();
     let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
 
-    let components = Components::open(prefix.clone()).unwrap();
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
 
     let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
 
@@ -196,7 +197,7 @@ fn uninstall() {
     let notify = NotifyHandler::none();
     let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
 
-    let components = Components::open(prefix.clone()).unwrap();
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
 
     let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
 
@@ -221,6 +222,178 @@ fn uninstall() {
     assert!(components.find("mycomponent2").unwrap().is_none());
 }
 
+#[test]
+fn install_records_file_hashes() {
+    let pkgdir = TempDir::new("multirust").unwrap();
+
+    let mock = MockInstallerBuilder {
+        components: vec![("mycomponent".to_string(),
+                          vec![MockCommand::File("bin/foo".to_string()),
+                               MockCommand::Dir("doc/stuff".to_string())],
+                          vec![("bin/foo".to_string(), "foo".into()),
+                               ("doc/stuff/doc1".to_string(), "".into()),
+                               ("doc/stuff/doc2".to_string(), "".into())])]
+    };
+
+    mock.build(pkgdir.path());
+
+    let instdir = TempDir::new("multirust").unwrap();
+    let prefix = InstallPrefix::from(instdir.path().to_owned());
+
+    let notify = temp::SharedNotifyHandler::none();
+    let tmpdir = TempDir::new("multirust").unwrap();
+    let tmpcfg = temp::Cfg::new(tmpdir.path().to_owned(), notify);
+    let notify = NotifyHandler::none();
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
+
+    let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
+
+    let tx = pkg.install(&components, "mycomponent", None, tx).unwrap();
+    tx.commit();
+
+    let component = components.find("mycomponent").unwrap().unwrap();
+    let records = component.file_records().unwrap().unwrap();
+    assert_eq!(records.len(), 3);
+    let foo = records.iter().find(|r| r.path == PathBuf::from("bin/foo")).unwrap();
+    // sha256 of "foo"
+    assert_eq!(foo.hash, "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+    assert_eq!(component.parts().unwrap().len(), 2);
+
+    // The component manifest stays in the format rust-installer reads
+    let manifest = utils::read_file("", &component.manifest_file()).unwrap();
+    assert!(manifest.lines().all(|l| l.starts_with("file:") || l.starts_with("dir:")));
+
+    assert!(component.verify().unwrap().is_intact());
+}
+
+#[test]
+fn uninstall_removes_file_hashes() {
+    let pkgdir = TempDir::new("multirust").unwrap();
+
+    let mock = MockInstallerBuilder {
+        components: vec![("mycomponent".to_string(),
+                          vec![MockCommand::File("bin/foo".to_string())],
+                          vec![("bin/foo".to_string(), "foo".into())])]
+    };
+
+    mock.build(pkgdir.path());
+
+    let instdir = TempDir::new("multirust").unwrap();
+    let prefix = InstallPrefix::from(instdir.path().to_owned());
+
+    let notify = temp::SharedNotifyHandler::none();
+    let tmpdir = TempDir::new("multirust").unwrap();
+    let tmpcfg = temp::Cfg::new(tmpdir.path().to_owned(), notify);
+    let notify = NotifyHandler::none();
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
+
+    let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
+
+    let tx = pkg.install(&components, "mycomponent", None, tx).unwrap();
+    tx.commit();
+
+    let component = components.find("mycomponent").unwrap().unwrap();
+    let ref hashes = component.hashes_file();
+    assert!(utils::is_file(hashes));
+
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+    let tx = component.uninstall(tx).unwrap();
+    tx.commit();
+
+    assert!(!utils::path_exists(hashes));
+}
+
+#[test]
+fn verify_damaged_component() {
+    let pkgdir = TempDir::new("multirust").unwrap();
+
+    let mock = MockInstallerBuilder {
+        components: vec![("mycomponent".to_string(),
+                          vec![MockCommand::File("bin/foo".to_string()),
+                               MockCommand::File("lib/bar".to_string()),
+                               MockCommand::Dir("doc/stuff".to_string())],
+                          vec![("bin/foo".to_string(), "foo".into()),
+                               ("lib/bar".to_string(), "bar".into()),
+                               ("doc/stuff/doc1".to_string(), "".into())])]
+    };
+
+    mock.build(pkgdir.path());
+
+    let instdir = TempDir::new("multirust").unwrap();
+    let prefix = InstallPrefix::from(instdir.path().to_owned());
+
+    let notify = temp::SharedNotifyHandler::none();
+    let tmpdir = TempDir::new("multirust").unwrap();
+    let tmpcfg = temp::Cfg::new(tmpdir.path().to_owned(), notify);
+    let notify = NotifyHandler::none();
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
+
+    let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
+
+    let tx = pkg.install(&components, "mycomponent", None, tx).unwrap();
+    tx.commit();
+
+    utils::write_file("", &instdir.path().join("bin/foo"), "bogus").unwrap();
+    utils::remove_file("", &instdir.path().join("lib/bar")).unwrap();
+    utils::write_file("", &instdir.path().join("doc/stuff/doc2"), "").unwrap();
+
+    let component = components.find("mycomponent").unwrap().unwrap();
+    let verification = component.verify().unwrap();
+    assert!(verification.hashed);
+    assert_eq!(verification.modified, vec![PathBuf::from("bin/foo")]);
+    assert_eq!(verification.missing, vec![PathBuf::from("lib/bar")]);
+    assert_eq!(verification.extra, vec![PathBuf::from("doc/stuff/doc2")]);
+}
+
+#[test]
+fn verify_component_without_hashes() {
+    let pkgdir = TempDir::new("multirust").unwrap();
+
+    let mock = MockInstallerBuilder {
+        components: vec![("mycomponent".to_string(),
+                          vec![MockCommand::File("bin/foo".to_string()),
+                               MockCommand::File("lib/bar".to_string())],
+                          vec![("bin/foo".to_string(), "foo".into()),
+                               ("lib/bar".to_string(), "bar".into())])]
+    };
+
+    mock.build(pkgdir.path());
+
+    let instdir = TempDir::new("multirust").unwrap();
+    let prefix = InstallPrefix::from(instdir.path().to_owned());
+
+    let notify = temp::SharedNotifyHandler::none();
+    let tmpdir = TempDir::new("multirust").unwrap();
+    let tmpcfg = temp::Cfg::new(tmpdir.path().to_owned(), notify);
+    let notify = NotifyHandler::none();
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
+
+    let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
+
+    let tx = pkg.install(&components, "mycomponent", None, tx).unwrap();
+    tx.commit();
+
+    // As installed by older versions
+    let component = components.find("mycomponent").unwrap().unwrap();
+    utils::remove_file("", &component.hashes_file()).unwrap();
+    utils::write_file("", &instdir.path().join("bin/foo"), "bogus").unwrap();
+    utils::remove_file("", &instdir.path().join("lib/bar")).unwrap();
+
+    assert!(component.file_records().unwrap().is_none());
+    let verification = component.verify().unwrap();
+    assert!(!verification.hashed);
+    assert!(verification.modified.is_empty());
+    assert_eq!(verification.missing, vec![PathBuf::from("lib/bar")]);
+}
+
 // If any single file can't be uninstalled, it is not a fatal error
 // and the subsequent files will still be removed.
 #[test]
@@ -249,7 +422,7 @@ fn component_bad_version() {
     let notify = NotifyHandler::none();
     let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
 
-    let components = Components::open(prefix.clone()).unwrap();
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
 
     let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
 
@@ -260,7 +433,7 @@ fn component_bad_version() {
     utils::write_file("", &prefix.manifest_file("rust-installer-version"), "100\n").unwrap();
 
     // Can't open components now
-    let e = Components::open(prefix.clone()).unwrap_err();
+    let e = Components::open(prefix.clone(), NotifyHandler::none()).unwrap_err();
     if let multirust_dist::Error::BadInstalledMetadataVersion(_) = e { } else { panic!() }
 }
 
@@ -296,7 +469,7 @@ fn unix_permissions() {
     let notify = NotifyHandler::none();
     let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
 
-    let components = Components::open(prefix.clone()).unwrap();
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
 
     let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
 
@@ -341,7 +514,7 @@ fn install_to_prefix_that_does_not_exist() {
     let notify = NotifyHandler::none();
     let tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
 
-    let components = Components::open(prefix.clone()).unwrap();
+    let components = Components::open(prefix.clone(), NotifyHandler::none()).unwrap();
 
     let pkg = DirectoryPackage::new(pkgdir.path().to_owned()).unwrap();
 
@@ -1,6 +1,6 @@
 extern crate multirust_dist;
 
-use multirust_dist::manifest::Manifest;
+use multirust_dist::manifest::{Manifest, Profile};
 use multirust_dist::Error;
 
 // Example manifest from https://public.etherpad-mozilla.org/p/Rust-infra-work-week
@@ -84,3 +84,58 @@ date = "2015-10-10"
         _ => panic!(),
     }
 }
+
+#[test]
+fn parse_profiles() {
+    let manifest = r#"
+manifest-version = "2"
+date = "2015-10-10"
+[profiles]
+  minimal = ["rustc"]
+  default = ["rustc", "rust-docs"]
+  bogus = ["rustc"]
+[pkg.rust]
+  version = "rustc 1.3.0 (9a92aaf19 2015-09-15)"
+  [pkg.rust.target.x86_64-unknown-linux-gnu]
+    available = true
+    url = "example.com"
+    hash = "..."
+    [[pkg.rust.target.x86_64-unknown-linux-gnu.components]]
+      pkg = "rustc"
+      target = "x86_64-unknown-linux-gnu"
+    [[pkg.rust.target.x86_64-unknown-linux-gnu.components]]
+      pkg = "rust-docs"
+      target = "x86_64-unknown-linux-gnu"
+[pkg.rustc]
+  version = "rustc 1.3.0 (9a92aaf19 2015-09-15)"
+  [pkg.rustc.target.x86_64-unknown-linux-gnu]
+    available = true
+    url = "example.com"
+    hash = "..."
+[pkg.rust-docs]
+  version = "rustc 1.3.0 (9a92aaf19 2015-09-15)"
+  [pkg.rust-docs.target.x86_64-unknown-linux-gnu]
+    available = true
+    url = "example.com"
+    hash = "..."
+"#;
+
+    let pkg = Manifest::parse(manifest).unwrap();
+    assert_eq!(pkg.profiles.len(), 2);
+
+    let minimal = pkg.get_profile_components(Profile::Minimal, "x86_64-unknown-linux-gnu").unwrap();
+    assert_eq!(minimal.len(), 1);
+    assert_eq!(minimal[0].pkg, "rustc");
+
+    let default = pkg.get_profile_components(Profile::Default, "x86_64-unknown-linux-gnu").unwrap();
+    assert_eq!(default.len(), 2);
+
+    match pkg.get_profile_components(Profile::Complete, "x86_64-unknown-linux-gnu").unwrap_err() {
+        Error::MissingProfile(_) => {},
+        _ => panic!(),
+    }
+
+    let serialized = pkg.clone().stringify();
+    let new = Manifest::parse(&serialized).unwrap();
+    assert_eq!(pkg, new);
+}
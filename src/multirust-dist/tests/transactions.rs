@@ -4,15 +4,17 @@ extern crate tempdir;
 
 use multirust_dist::NotifyHandler;
 use multirust_dist::prefix::InstallPrefix;
-use multirust_dist::component::Transaction;
+use multirust_dist::component::{Transaction, Components, recover_transaction,
+                                has_interrupted_transaction, JOURNAL_FILE};
 use multirust_dist::temp;
 use multirust_utils::utils;
 use multirust_utils::raw as utils_raw;
 use multirust_dist::Error;
 use tempdir::TempDir;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::mem;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
 #[test]
 fn add_file() {
@@ -541,6 +543,41 @@ fn modify_file_that_exists_then_rollback() {
     assert_eq!(utils_raw::read_file(path).unwrap(), "wow");
 }
 
+// A file hard linked into another installation keeps its contents
+// there, whether the transaction commits or not
+#[test]
+fn modify_file_breaks_hard_link() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let otherdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let ref path = prefix.path().join("foo");
+    let ref other = otherdir.path().join("foo");
+    utils_raw::write_file(path, "wow").unwrap();
+    fs::hard_link(path, other).unwrap();
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+    tx.modify_file(PathBuf::from("foo")).unwrap();
+    utils::append_file("foo", path, "eww").unwrap();
+    drop(tx);
+
+    assert_eq!(utils_raw::read_file(other).unwrap(), "wow");
+
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+    tx.modify_file(PathBuf::from("foo")).unwrap();
+    utils_raw::write_file(path, "eww").unwrap();
+    tx.commit();
+
+    assert_eq!(utils_raw::read_file(path).unwrap(), "eww");
+    assert_eq!(utils_raw::read_file(other).unwrap(), "wow");
+}
+
 // This is testing that the backup scheme is smart enough not
 // to overwrite the earliest backup.
 #[test]
@@ -689,9 +726,279 @@ fn rollback_failure_keeps_going() {
     assert!(!utils::path_exists(prefix.path().join("baz")));
 }
 
+// The paths under a directory, with the contents of files
+fn snapshot(path: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+    fn walk(root: &Path, relpath: PathBuf, result: &mut Vec<(PathBuf, Option<Vec<u8>>)>) {
+        let path = root.join(&relpath);
+        if utils::is_directory(&path) {
+            let mut entries: Vec<_> = fs::read_dir(&path).unwrap()
+                                         .map(|e| e.unwrap().file_name())
+                                         .collect();
+            entries.sort();
+            result.push((relpath.clone(), None));
+            for entry in entries {
+                walk(root, relpath.join(entry), result);
+            }
+        } else {
+            let mut contents = Vec::new();
+            fs::File::open(&path).unwrap().read_to_end(&mut contents).unwrap();
+            result.push((relpath, Some(contents)));
+        }
+    }
+
+    let mut result = Vec::new();
+    walk(path, PathBuf::new(), &mut result);
+    result
+}
+
 // Test that when a transaction creates intermediate directories that
 // they are deleted during rollback.
 #[test]
-#[ignore]
 fn intermediate_dir_rollback() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    fs::create_dir_all(prefix.path().join("lib")).unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/existing"), "").unwrap();
+    let before = snapshot(prefix.path());
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    write!(&mut tx.add_file("", PathBuf::from("lib/rustlib/foo/bar")).unwrap(), "").unwrap();
+    tx.write_file("", PathBuf::from("share/doc/rust/index.html"), "".to_string()).unwrap();
+    tx.modify_file(PathBuf::from("etc/bash_completion.d/cargo")).unwrap();
+
+    assert!(utils::is_directory(prefix.path().join("lib/rustlib/foo")));
+    assert!(utils::is_directory(prefix.path().join("etc/bash_completion.d")));
+
+    drop(tx);
+
+    assert_eq!(snapshot(prefix.path()), before);
+}
+
+// Intermediate directories that gain other files during the
+// transaction are left in place.
+#[test]
+fn intermediate_dir_rollback_keeps_nonempty_dirs() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    write!(&mut tx.add_file("", PathBuf::from("lib/rustlib/foo")).unwrap(), "").unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/other"), "").unwrap();
+
+    drop(tx);
+
+    assert!(!utils::path_exists(prefix.path().join("lib/rustlib")));
+    assert!(utils::path_exists(prefix.path().join("lib/other")));
+}
+
+#[test]
+fn multiple_op_transaction_rollback_restores_prefix() {
+    let srcdir = TempDir::new("multirust").unwrap();
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    fs::create_dir_all(prefix.path().join("bin")).unwrap();
+    fs::create_dir_all(prefix.path().join("olddoc/html")).unwrap();
+    fs::create_dir_all(prefix.path().join("lib/rustlib")).unwrap();
+    utils_raw::write_file(&prefix.path().join("bin/oldrustc"), "oldrustc").unwrap();
+    utils_raw::write_file(&prefix.path().join("olddoc/html/index.html"), "olddoc").unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/rustlib/components"), "rustc\n").unwrap();
+    let before = snapshot(prefix.path());
+
+    fs::create_dir_all(srcdir.path().join("bin")).unwrap();
+    fs::create_dir_all(srcdir.path().join("doc/html")).unwrap();
+    utils_raw::write_file(&srcdir.path().join("bin/rustc"), "rustc").unwrap();
+    utils_raw::write_file(&srcdir.path().join("doc/html/index.html"), "doc").unwrap();
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    tx.remove_file("", PathBuf::from("bin/oldrustc")).unwrap();
+    tx.remove_dir("", PathBuf::from("olddoc")).unwrap();
+    tx.copy_file("", PathBuf::from("bin/rustc"), &srcdir.path().join("bin/rustc")).unwrap();
+    tx.copy_file("", PathBuf::from("lib/rustlib/x86_64-unknown-linux-gnu/lib/libstd.rlib"),
+                 &srcdir.path().join("bin/rustc")).unwrap();
+    tx.copy_dir("", PathBuf::from("share/doc/rust"), &srcdir.path().join("doc")).unwrap();
+    tx.modify_file(PathBuf::from("lib/rustlib/components")).unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/rustlib/components"), "rustc\ncargo\n").unwrap();
+    tx.write_file("", PathBuf::from("lib/rustlib/manifest-cargo"), "file:bin/cargo\n".to_string()).unwrap();
+
+    drop(tx);
+
+    assert_eq!(snapshot(prefix.path()), before);
+}
+
+// A transaction that is never committed or dropped, as when the
+// process is killed, is rolled back from its journal.
+#[test]
+fn recover_interrupted_transaction() {
+    let srcdir = TempDir::new("multirust").unwrap();
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    fs::create_dir_all(prefix.path().join("bin")).unwrap();
+    fs::create_dir_all(prefix.path().join("olddoc/html")).unwrap();
+    fs::create_dir_all(prefix.path().join("lib/rustlib")).unwrap();
+    utils_raw::write_file(&prefix.path().join("bin/oldrustc"), "oldrustc").unwrap();
+    utils_raw::write_file(&prefix.path().join("olddoc/html/index.html"), "olddoc").unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/rustlib/components"), "rustc\n").unwrap();
+    let before = snapshot(prefix.path());
+
+    utils_raw::write_file(&srcdir.path().join("rustc"), "rustc").unwrap();
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+
+    tx.remove_file("", PathBuf::from("bin/oldrustc")).unwrap();
+    tx.remove_dir("", PathBuf::from("olddoc")).unwrap();
+    tx.copy_file("", PathBuf::from("lib/rustlib/x86_64-unknown-linux-gnu/lib/libstd.rlib"),
+                 &srcdir.path().join("rustc")).unwrap();
+    tx.modify_file(PathBuf::from("lib/rustlib/components")).unwrap();
+    utils_raw::write_file(&prefix.path().join("lib/rustlib/components"), "rustc\ncargo\n").unwrap();
+
+    mem::forget(tx);
+
+    assert!(utils::is_file(prefix.path().join(JOURNAL_FILE)));
+    assert!(recover_transaction(&prefix, notify).unwrap());
+
+    assert_eq!(snapshot(prefix.path()), before);
+    assert_eq!(fs::read_dir(txdir.path()).unwrap().count(), 0);
+}
+
+// A transaction that was interrupted after committing only has its
+// backups cleaned up.
+#[test]
+fn recover_committed_transaction() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    fs::create_dir_all(prefix.path().join("bin")).unwrap();
+    utils_raw::write_file(&prefix.path().join("bin/rustc"), "rustc").unwrap();
+    let backup = txdir.path().join("oldrustc");
+    utils_raw::write_file(&backup, "oldrustc").unwrap();
+
+    let journal = format!("removed-file\tbin/oldrustc\t{}\nadded-file\tbin/rustc\ncommitted\n",
+                          backup.display());
+    utils_raw::write_file(&prefix.path().join(JOURNAL_FILE), &journal).unwrap();
+
+    assert!(recover_transaction(&prefix, NotifyHandler::none()).unwrap());
+
+    assert!(!utils::path_exists(prefix.path().join("bin/oldrustc")));
+    assert!(utils::is_file(prefix.path().join("bin/rustc")));
+    assert!(!utils::path_exists(&backup));
+    assert!(!utils::path_exists(prefix.path().join(JOURNAL_FILE)));
+}
+
+// The last entry may have been cut off by the crash, in which case
+// its change was never made.
+#[test]
+fn recover_ignores_partial_journal_entry() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    utils_raw::write_file(&prefix.path().join("foo"), "").unwrap();
+    utils_raw::write_file(&prefix.path().join(JOURNAL_FILE), "added-file\tfoo\nremoved-fi").unwrap();
+
+    assert!(recover_transaction(&prefix, NotifyHandler::none()).unwrap());
+
+    assert!(!utils::path_exists(prefix.path().join("foo")));
+    assert!(!utils::path_exists(prefix.path().join(JOURNAL_FILE)));
+}
+
+#[test]
+fn recover_without_journal() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    assert!(!recover_transaction(&prefix, NotifyHandler::none()).unwrap());
+}
+
+#[test]
+fn recover_corrupt_journal() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    utils_raw::write_file(&prefix.path().join(JOURNAL_FILE), "bogus\tfoo\n").unwrap();
+
+    match recover_transaction(&prefix, NotifyHandler::none()) {
+        Err(Error::CorruptTransactionJournal(_)) => (),
+        _ => panic!(),
+    }
+}
+
+// Opening an installation leaves an interrupted transaction for a
+// caller that holds the toolchain lock to recover
+#[test]
+fn opening_components_keeps_interrupted_transaction() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+    tx.write_file("", PathBuf::from("lib/rustlib/components"), "rustc\n".to_string()).unwrap();
+    mem::forget(tx);
+
+    assert!(has_interrupted_transaction(&prefix).unwrap());
+    Components::open(prefix.clone(), notify).unwrap();
+    assert!(utils::path_exists(prefix.path().join(JOURNAL_FILE)));
+
+    assert!(recover_transaction(&prefix, notify).unwrap());
+    assert!(!has_interrupted_transaction(&prefix).unwrap());
+    let components = Components::open(prefix.clone(), notify).unwrap();
+    assert!(components.list().unwrap().is_empty());
+    assert!(!utils::path_exists(prefix.path().join("lib")));
+}
+
+// A transaction in progress isn't interrupted
+#[test]
+fn transaction_in_progress_is_not_interrupted() {
+    let prefixdir = TempDir::new("multirust").unwrap();
+    let txdir = TempDir::new("multirust").unwrap();
+
+    let tmpnotify = temp::SharedNotifyHandler::none();
+    let tmpcfg = temp::Cfg::new(txdir.path().to_owned(), tmpnotify);
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let notify = NotifyHandler::none();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, notify);
+    tx.write_file("", PathBuf::from("foo"), "foo".to_string()).unwrap();
+
+    assert!(!has_interrupted_transaction(&prefix).unwrap());
+    tx.commit();
 }
@@ -12,7 +12,7 @@ use tempdir::TempDir;
 use {MockInstallerBuilder, MockCommand};
 use dist::{MockDistServer, MockChannel, MockPackage,
            MockTargettedPackage, MockComponent, change_channel_date,
-           ManifestVersion};
+           add_channel_profiles, create_tarball, ManifestVersion};
 use hyper::Url;
 
 /// The configuration used by the tests in this module
@@ -91,6 +91,40 @@ pub fn set_current_dist_date(config: &Config, date: &str) {
     }
 }
 
+/// Build an installer of a single component that isn't part of any
+/// release, like a locally built tool, returning the tarball's path
+pub fn create_local_installer(config: &Config, name: &str) -> PathBuf {
+    let ref installer_name = format!("{}-local", name);
+    let ref installer_dir = create_local_installer_dir(config, name);
+
+    let tarball = config.customdir.path().join(format!("{}.tar.gz", installer_name));
+    create_tarball(&PathBuf::from(installer_name), installer_dir, &tarball);
+    tarball
+}
+
+/// Like `create_local_installer`, but leaves the installer unpacked
+pub fn create_local_installer_dir(config: &Config, name: &str) -> PathBuf {
+    let installer = MockInstallerBuilder {
+        components: vec![
+            (name.to_string(),
+             vec![MockCommand::File(format!("lib/{}/{}.rlib", name, name))],
+             vec![(format!("lib/{}/{}.rlib", name, name), "".into())])
+            ]
+    };
+    let installer_dir = config.customdir.path().join(format!("{}-local", name));
+    fs::create_dir_all(&installer_dir).unwrap();
+    installer.build(&installer_dir);
+    installer_dir
+}
+
+/// Add installation profiles to the current distribution manifests
+pub fn add_dist_profiles(config: &Config, profiles: &[(&str, &[&str])]) {
+    let ref url = Url::from_file_path(config.distdir.path()).unwrap();
+    for channel in &["nightly", "beta", "stable"] {
+        add_channel_profiles(url, channel, profiles);
+    }
+}
+
 pub fn expect_ok(config: &Config, args: &[&str]) {
     expect_stdout_ok(config, args, "");
 }
@@ -288,7 +322,8 @@ fn build_mock_channel(channel: &str, date: &str,
 
         MockPackage {
             name: name,
-            version: version,
+            // Like real manifests, include the commit hash
+            version: format!("{} ({})", version, version_hash),
             targets: target_pkgs.collect()
         }
     });
@@ -56,6 +56,26 @@ pub fn change_channel_date(dist_server: &Url, channel: &str, date: &str) {
     }
 }
 
+// Appends a [profiles] table to the current v2 manifest for a
+// channel, mapping each profile name to a list of package names
+pub fn add_channel_profiles(dist_server: &Url, channel: &str, profiles: &[(&str, &[&str])]) {
+    let path = dist_server.to_file_path().unwrap();
+
+    let manifest_name = format!("dist/channel-rust-{}", channel);
+    let ref manifest_path = path.join(format!("{}.toml", manifest_name));
+    let ref hash_path = path.join(format!("{}.toml.sha256", manifest_name));
+
+    let mut manifest_str = String::new();
+    File::open(manifest_path).unwrap().read_to_string(&mut manifest_str).unwrap();
+    manifest_str.push_str("\n[profiles]\n");
+    for &(name, pkgs) in profiles {
+        let pkgs = pkgs.iter().map(|p| format!("\"{}\"", p)).join(", ");
+        manifest_str.push_str(&format!("{} = [{}]\n", name, pkgs));
+    }
+    write_file(manifest_path, &manifest_str);
+    create_hash(manifest_path, hash_path);
+}
+
 // The manifest version created by this mock
 pub const MOCK_MANIFEST_VERSION: &'static str = "2";
 
@@ -80,7 +100,7 @@ pub struct MockChannel {
 pub struct MockPackage {
     // rust, rustc, rust-std-$triple, rust-doc, etc.
     pub name: &'static str,
-    pub version: &'static str,
+    pub version: String,
     pub targets: Vec<MockTargettedPackage>,
 }
 
@@ -278,7 +298,7 @@ impl MockDistServer {
     }
 }
 
-fn create_tarball(relpath: &Path, src: &Path, dst: &Path) {
+pub fn create_tarball(relpath: &Path, src: &Path, dst: &Path) {
     let outfile = File::create(dst).unwrap();
     let gzwriter = flate2::write::GzEncoder::new(outfile, flate2::Compression::None);
     let mut tar = tar::Builder::new(gzwriter);
@@ -22,6 +22,7 @@ pub enum Notification<'a> {
     /// Download has finished.
     DownloadFinished,
     NoCanonicalPath(&'a Path),
+    WaitingForLock(&'a Path, Option<u32>),
 }
 
 #[derive(Debug)]
@@ -123,8 +124,14 @@ pub enum Error {
         path: PathBuf,
         error: io::Error,
     },
+    LockingFile {
+        name: &'static str,
+        path: PathBuf,
+        error: io::Error,
+    },
     CargoHome,
     MultirustHome,
+    Cancelled,
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -142,6 +149,7 @@ impl<'a> Notification<'a> {
             DownloadDataReceived(_) |
             DownloadFinished => NotificationLevel::Verbose,
             NoCanonicalPath(_) => NotificationLevel::Warn,
+            WaitingForLock(_, _) => NotificationLevel::Info,
         }
     }
 }
@@ -163,6 +171,10 @@ impl<'a> Display for Notification<'a> {
             DownloadDataReceived(len) => write!(f, "received some data of size {}", len),
             DownloadFinished => write!(f, "download finished"),
             NoCanonicalPath(path) => write!(f, "could not canonicalize path: '{}'", path.display()),
+            WaitingForLock(path, Some(pid)) => {
+                write!(f, "waiting for lock on '{}' held by pid {}", path.display(), pid)
+            }
+            WaitingForLock(path, None) => write!(f, "waiting for lock on '{}'", path.display()),
         }
     }
 }
@@ -194,8 +206,10 @@ impl error::Error for Error {
             OpeningBrowser { error: Some(_) } => "could not open browser",
             OpeningBrowser { error: None } => "could not open browser: no browser installed",
             SettingPermissions {..} => "failed to set permissions",
+            LockingFile {..} => "could not lock file",
             CargoHome => "couldn't find value of CARGO_HOME",
             MultirustHome => "couldn't find value of MULTIRUST_HOME",
+            Cancelled => "operation cancelled",
         }
     }
 
@@ -221,7 +235,8 @@ impl error::Error for Error {
             CopyingFile { ref error, .. } |
             RemovingFile { ref error, .. } |
             RemovingDirectory { ref error, .. } |
-            SettingPermissions { ref error, .. } => Some(error),
+            SettingPermissions { ref error, .. } |
+            LockingFile { ref error, .. } => Some(error),
             // Variants that carry `error: raw::CommandError`.
             RunningCommand { ref error, .. } |
             CopyingDirectory { ref error, .. } => Some(error),
@@ -231,7 +246,8 @@ impl error::Error for Error {
             OpeningBrowser { error: Some(ref e) } => Some(e),
             OpeningBrowser { error: None } => None,
             CargoHome |
-            MultirustHome => None,
+            MultirustHome |
+            Cancelled => None,
         }
     }
 }
@@ -364,8 +380,16 @@ impl Display for Error {
                        path.display(),
                        error)
             },
+            LockingFile { ref name, ref path, ref error } => {
+                write!(f,
+                       "could not lock {} file: '{}' ({})",
+                       name,
+                       path.display(),
+                       error)
+            }
             CargoHome => write!(f, "couldn't find value of CARGO_HOME"),
             MultirustHome => write!(f, "couldn't find value of MULTIRUST_HOME"),
+            Cancelled => write!(f, "operation cancelled"),
         }
     }
 }
@@ -4,6 +4,7 @@
 extern crate hyper;
 extern crate openssl;
 extern crate rand;
+extern crate libc;
 #[macro_use]
 extern crate scopeguard;
 
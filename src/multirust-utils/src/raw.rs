@@ -11,11 +11,47 @@ use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::thread;
 use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use hyper::{self, Client};
 use openssl::crypto::hash::Hasher;
 
 use rand::random;
 
+static CANCELLED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Asks long-running operations to stop at their next opportunity.
+/// This only stores to an atomic, so it may be called from a signal
+/// handler.
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+// Lets tests cancel an operation partway through, once the number of
+// files in MULTIRUST_TEST_CANCEL_AFTER_DOWNLOADS have been fetched.
+// Only built for the integration tests, which enable `test-hooks`.
+#[cfg(feature = "test-hooks")]
+fn count_test_download() {
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+
+    static DOWNLOADS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    let limit = env::var("MULTIRUST_TEST_CANCEL_AFTER_DOWNLOADS").ok()
+                    .and_then(|n| n.parse::<usize>().ok());
+    if let Some(limit) = limit {
+        if DOWNLOADS.fetch_add(1, Ordering::SeqCst) + 1 >= limit {
+            cancel();
+        }
+    }
+}
+
+#[cfg(not(feature = "test-hooks"))]
+fn count_test_download() {}
+
 pub fn ensure_dir_exists<P: AsRef<Path>, F: FnOnce(&Path)>(path: P,
                                                            callback: F)
                                                            -> io::Result<bool> {
@@ -157,6 +193,7 @@ pub enum DownloadError {
     Network(hyper::Error),
     File(io::Error),
     FilePathParse,
+    Cancelled,
 }
 pub type DownloadResult<T> = Result<T, DownloadError>;
 
@@ -168,6 +205,7 @@ impl error::Error for DownloadError {
             Network(_) => "network error",
             File(_) => "error writing file",
             FilePathParse => "failed to parse URL as file path",
+            Cancelled => "download cancelled",
         }
     }
 
@@ -177,7 +215,8 @@ impl error::Error for DownloadError {
             Network(ref e) => Some(e),
             File(ref e) => Some(e),
             Status(_) |
-            FilePathParse => None,
+            FilePathParse |
+            Cancelled => None,
         }
     }
 }
@@ -189,6 +228,7 @@ impl fmt::Display for DownloadError {
             DownloadError::Network(ref e) => write!(f, "Network: {}", e),
             DownloadError::File(ref e) => write!(f, "File: {}", e),
             DownloadError::FilePathParse => write!(f, "failed to parse URL as file path"),
+            DownloadError::Cancelled => write!(f, "download cancelled"),
         }
     }
 }
@@ -201,6 +241,10 @@ pub fn download_file<P: AsRef<Path>>(url: hyper::Url,
     use hyper::header::ContentLength;
     use errors::Notification;
 
+    if is_cancelled() {
+        return Err(DownloadError::Cancelled);
+    }
+
     // The file scheme is mostly for use by tests to mock the dist server
     if url.scheme == "file" {
         let src = try!(url.to_file_path().map_err(|_| DownloadError::FilePathParse));
@@ -224,6 +268,7 @@ pub fn download_file<P: AsRef<Path>>(url: hyper::Url,
             }
         }
 
+        count_test_download();
         return Ok(());
     }
 
@@ -244,6 +289,10 @@ pub fn download_file<P: AsRef<Path>>(url: hyper::Url,
     }
 
     loop {
+        if is_cancelled() {
+            return Err(DownloadError::Cancelled);
+        }
+
         let bytes_read = try!(io::Read::read(&mut res, &mut buffer)
                                   .map_err(hyper::Error::Io)
                                   .map_err(DownloadError::Network));
@@ -264,6 +313,224 @@ pub fn download_file<P: AsRef<Path>>(url: hyper::Url,
     }
 }
 
+/// The id of the current process
+pub fn process_id() -> u32 {
+    #[cfg(unix)]
+    fn process_id_inner() -> u32 {
+        use libc;
+        unsafe { libc::getpid() as u32 }
+    }
+    #[cfg(windows)]
+    fn process_id_inner() -> u32 {
+        use kernel32;
+        unsafe { kernel32::GetCurrentProcessId() as u32 }
+    }
+
+    process_id_inner()
+}
+
+/// Whether a process with the given id is running
+pub fn process_exists(pid: u32) -> bool {
+    #[cfg(unix)]
+    fn process_exists_inner(pid: u32) -> bool {
+        use libc;
+
+        // Zero and negative pids name process groups, not processes
+        if pid == 0 || pid as libc::pid_t <= 0 {
+            return false;
+        }
+        // Signal 0 only checks whether the process could be signalled.
+        // It fails with EPERM for other users' processes.
+        if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+            return true;
+        }
+        io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(windows)]
+    fn process_exists_inner(pid: u32) -> bool {
+        use kernel32;
+        use winapi::DWORD;
+
+        const PROCESS_QUERY_LIMITED_INFORMATION: DWORD = 0x1000;
+        const STILL_ACTIVE: DWORD = 259;
+        const ERROR_ACCESS_DENIED: i32 = 5;
+
+        unsafe {
+            let handle = kernel32::OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid as DWORD);
+            if handle.is_null() {
+                return io::Error::last_os_error().raw_os_error() == Some(ERROR_ACCESS_DENIED);
+            }
+            let mut code: DWORD = 0;
+            let ok = kernel32::GetExitCodeProcess(handle, &mut code);
+            kernel32::CloseHandle(handle);
+            ok == 0 || code == STILL_ACTIVE
+        }
+    }
+
+    process_exists_inner(pid)
+}
+
+/// Atomically swaps two existing paths. Returns `Ok(false)`, having
+/// changed nothing, where the platform or filesystem can't do that.
+pub fn exchange_paths(a: &Path, b: &Path) -> io::Result<bool> {
+    #[cfg(all(target_os = "linux",
+              any(target_arch = "x86_64", target_arch = "x86",
+                  target_arch = "aarch64", target_arch = "arm")))]
+    fn exchange_paths_inner(a: &Path, b: &Path) -> io::Result<bool> {
+        use libc;
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        // renameat2 has no libc wrapper on older systems
+        #[cfg(target_arch = "x86_64")]
+        const SYS_RENAMEAT2: libc::c_long = 316;
+        #[cfg(target_arch = "x86")]
+        const SYS_RENAMEAT2: libc::c_long = 353;
+        #[cfg(target_arch = "aarch64")]
+        const SYS_RENAMEAT2: libc::c_long = 276;
+        #[cfg(target_arch = "arm")]
+        const SYS_RENAMEAT2: libc::c_long = 382;
+        const RENAME_EXCHANGE: libc::c_uint = 2;
+
+        let to_cstring = |p: &Path| {
+            CString::new(p.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))
+        };
+        let a = try!(to_cstring(a));
+        let b = try!(to_cstring(b));
+
+        let result = unsafe {
+            libc::syscall(SYS_RENAMEAT2,
+                          libc::AT_FDCWD, a.as_ptr(),
+                          libc::AT_FDCWD, b.as_ptr(),
+                          RENAME_EXCHANGE)
+        };
+        if result == 0 {
+            return Ok(true);
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            // Kernels before 3.15 and filesystems without support
+            Some(libc::ENOSYS) | Some(libc::EINVAL) => Ok(false),
+            _ => Err(e),
+        }
+    }
+    #[cfg(not(all(target_os = "linux",
+                  any(target_arch = "x86_64", target_arch = "x86",
+                      target_arch = "aarch64", target_arch = "arm"))))]
+    fn exchange_paths_inner(_: &Path, _: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    exchange_paths_inner(a, b)
+}
+
+/// Takes an exclusive advisory lock on an open file, which is released
+/// when the file is closed. If `block` is false and another process
+/// holds the lock this returns `Ok(false)` instead of waiting.
+pub fn lock_file(file: &fs::File, block: bool) -> io::Result<bool> {
+    #[cfg(unix)]
+    fn lock_file_inner(file: &fs::File, block: bool) -> io::Result<bool> {
+        use libc;
+        use std::os::unix::io::AsRawFd;
+
+        let op = if block { libc::LOCK_EX } else { libc::LOCK_EX | libc::LOCK_NB };
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+                return Ok(true);
+            }
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                // A cancelled operation stops waiting
+                ErrorKind::Interrupted if !is_cancelled() => (),
+                ErrorKind::WouldBlock if !block => return Ok(false),
+                _ => return Err(e),
+            }
+        }
+    }
+    #[cfg(windows)]
+    fn lock_file_inner(file: &fs::File, block: bool) -> io::Result<bool> {
+        use kernel32;
+        use winapi::{DWORD, HANDLE, OVERLAPPED};
+        use std::mem;
+        use std::os::windows::io::AsRawHandle;
+
+        const LOCKFILE_FAIL_IMMEDIATELY: DWORD = 0x1;
+        const LOCKFILE_EXCLUSIVE_LOCK: DWORD = 0x2;
+        const ERROR_LOCK_VIOLATION: i32 = 33;
+
+        let flags = if block {
+            LOCKFILE_EXCLUSIVE_LOCK
+        } else {
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY
+        };
+        unsafe {
+            // Lock a byte well past the end of the file, so that other
+            // processes can still read its contents
+            let mut overlapped: OVERLAPPED = mem::zeroed();
+            overlapped.OffsetHigh = 1;
+            let handle = file.as_raw_handle() as HANDLE;
+            if kernel32::LockFileEx(handle, flags, 0, 1, 0, &mut overlapped) != 0 {
+                return Ok(true);
+            }
+        }
+        let e = io::Error::last_os_error();
+        if !block && e.raw_os_error() == Some(ERROR_LOCK_VIOLATION) {
+            Ok(false)
+        } else {
+            Err(e)
+        }
+    }
+
+    lock_file_inner(file, block)
+}
+
+/// Where a file lives on disk, and how many hard links it has. Two
+/// paths refer to the same file if their device and index match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileIdentity {
+    pub device: u64,
+    pub index: u64,
+    pub links: u64,
+}
+
+pub fn file_identity(path: &Path) -> io::Result<FileIdentity> {
+    #[cfg(unix)]
+    fn file_identity_inner(path: &Path) -> io::Result<FileIdentity> {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = try!(fs::metadata(path));
+        Ok(FileIdentity {
+            device: meta.dev() as u64,
+            index: meta.ino() as u64,
+            links: meta.nlink() as u64,
+        })
+    }
+    #[cfg(windows)]
+    fn file_identity_inner(path: &Path) -> io::Result<FileIdentity> {
+        use kernel32;
+        use winapi::{BY_HANDLE_FILE_INFORMATION, HANDLE};
+        use std::mem;
+        use std::os::windows::io::AsRawHandle;
+
+        let file = try!(fs::File::open(path));
+        unsafe {
+            let mut info: BY_HANDLE_FILE_INFORMATION = mem::zeroed();
+            let handle = file.as_raw_handle() as HANDLE;
+            if kernel32::GetFileInformationByHandle(handle, &mut info) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(FileIdentity {
+                device: info.dwVolumeSerialNumber as u64,
+                index: (info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64,
+                links: info.nNumberOfLinks as u64,
+            })
+        }
+    }
+
+    file_identity_inner(path)
+}
+
 pub fn symlink_dir(src: &Path, dest: &Path) -> io::Result<()> {
     #[cfg(windows)]
     fn symlink_dir_inner(src: &Path, dest: &Path) -> io::Result<()> {
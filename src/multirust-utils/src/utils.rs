@@ -1,6 +1,7 @@
 use errors::Result;
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::collections::HashSet;
 use std::io;
 use std::process::Command;
 use std::ffi::OsString;
@@ -14,7 +15,7 @@ use raw;
 use winapi::DWORD;
 
 pub use raw::{is_directory, is_file, path_exists, if_not_empty, random_string, prefix_arg,
-                    has_cmd, find_cmd};
+                    has_cmd, find_cmd, process_id};
 
 
 pub fn ensure_dir_exists(name: &'static str,
@@ -84,6 +85,19 @@ pub fn rename_dir(name: &'static str, src: &Path, dest: &Path) -> Result<()> {
     })
 }
 
+/// Atomically swaps two directories where the platform allows.
+/// Returns whether it did; otherwise nothing was changed.
+pub fn exchange_dirs(name: &'static str, a: &Path, b: &Path) -> Result<bool> {
+    raw::exchange_paths(a, b).map_err(|e| {
+        Error::RenamingDirectory {
+            name: name,
+            src: PathBuf::from(a),
+            dest: PathBuf::from(b),
+            error: e,
+        }
+    })
+}
+
 pub fn filter_file<F: FnMut(&str) -> bool>(name: &'static str,
                                            src: &Path,
                                            dest: &Path,
@@ -136,14 +150,150 @@ pub fn download_file(url: hyper::Url,
                      -> Result<()> {
     notify_handler.call(Notification::DownloadingFile(&url, path));
     raw::download_file(url.clone(), path, hasher, notify_handler).map_err(|e| {
-        Error::DownloadingFile {
-            url: url,
-            path: PathBuf::from(path),
-            error: e,
+        match e {
+            raw::DownloadError::Cancelled => Error::Cancelled,
+            e => Error::DownloadingFile {
+                url: url,
+                path: PathBuf::from(path),
+                error: e,
+            },
         }
     })
 }
 
+/// Returns `Error::Cancelled` once `raw::cancel` has been called, for
+/// long-running operations to check between steps.
+pub fn check_cancelled() -> Result<()> {
+    if raw::is_cancelled() {
+        Err(Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// An advisory lock on a file, held until it is dropped. The file
+/// records the pid of the process holding the lock so that others can
+/// say who they're waiting for.
+#[derive(Debug)]
+pub struct FileLock {
+    file: fs::File,
+}
+
+impl FileLock {
+    /// Takes the lock at `path`, creating the file if necessary and
+    /// waiting if another process holds it.
+    pub fn acquire(name: &'static str, path: &Path, notify_handler: NotifyHandler) -> Result<Self> {
+        let lock = try!(FileLock::open(name, path, true, notify_handler));
+        Ok(lock.expect("waiting for a lock always takes it"))
+    }
+
+    /// Takes the lock at `path` if no other process holds it
+    pub fn try_acquire(name: &'static str, path: &Path,
+                       notify_handler: NotifyHandler) -> Result<Option<Self>> {
+        FileLock::open(name, path, false, notify_handler)
+    }
+
+    fn open(name: &'static str, path: &Path, wait: bool,
+            notify_handler: NotifyHandler) -> Result<Option<Self>> {
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        if let Some(parent) = path.parent() {
+            try!(ensure_dir_exists(name, parent, notify_handler));
+        }
+
+        let lock_err = |e: io::Error| {
+            Error::LockingFile {
+                name: name,
+                path: PathBuf::from(path),
+                error: e,
+            }
+        };
+
+        let mut file = try!(fs::OpenOptions::new().read(true).write(true).create(true)
+                                                  .open(path)
+                                                  .map_err(&lock_err));
+        if !try!(raw::lock_file(&file, false).map_err(&lock_err)) {
+            if !wait {
+                return Ok(None);
+            }
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            let pid = holder.trim().parse().ok();
+            notify_handler.call(Notification::WaitingForLock(path, pid));
+            try!(raw::lock_file(&file, true).map_err(&lock_err));
+        }
+
+        try!(file.set_len(0)
+                 .and_then(|_| file.seek(SeekFrom::Start(0)))
+                 .and_then(|_| write!(file, "{}", raw::process_id()))
+                 .map_err(&lock_err));
+
+        Ok(Some(FileLock { file: file }))
+    }
+}
+
+/// The size of some files on disk. Hard links to a file that was
+/// already counted add to `bytes` but not to `unique_bytes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Size {
+    pub bytes: u64,
+    pub unique_bytes: u64,
+}
+
+/// Measures the disk space used by files, remembering the files it
+/// has seen so that a file hard linked into several places only
+/// counts once towards the unique sizes.
+#[derive(Debug, Default)]
+pub struct DiskUsage {
+    seen: HashSet<(u64, u64)>,
+}
+
+impl DiskUsage {
+    pub fn new() -> Self {
+        DiskUsage::default()
+    }
+
+    /// Adds up the sizes of the files at or under `path`, without
+    /// following symlinks. A path that doesn't exist has no size.
+    pub fn measure(&mut self, path: &Path) -> Result<Size> {
+        let mut size = Size::default();
+        try!(self.measure_into(path, &mut size));
+        Ok(size)
+    }
+
+    fn measure_into(&mut self, path: &Path, size: &mut Size) -> Result<()> {
+        let read_err = |e: io::Error| {
+            Error::ReadingFile {
+                name: "disk usage",
+                path: PathBuf::from(path),
+                error: e,
+            }
+        };
+
+        // Files can disappear while they're being counted, e.g. from
+        // the temp directory
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(read_err(e)),
+        };
+        if meta.is_dir() {
+            for entry in try!(read_dir("disk usage", path)) {
+                let entry = try!(entry.map_err(&read_err));
+                try!(self.measure_into(&entry.path(), size));
+            }
+        } else if meta.file_type().is_file() {
+            let id = try!(raw::file_identity(path).map_err(&read_err));
+            size.bytes += meta.len();
+            if id.links <= 1 || self.seen.insert((id.device, id.index)) {
+                size.unique_bytes += meta.len();
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub fn parse_url(url: &str) -> Result<hyper::Url> {
     hyper::Url::parse(url).map_err(|_| Error::InvalidUrl { url: url.to_owned() })
 }
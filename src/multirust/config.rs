@@ -3,19 +3,27 @@ use std::borrow::Cow;
 use std::env;
 use std::io;
 use std::process::Command;
+use std::collections::HashSet;
 use std::fmt::{self, Display};
 
 use itertools::Itertools;
 
 use errors::*;
 use multirust_dist::{temp, dist};
-use multirust_utils::utils;
-use override_db::OverrideDB;
+use multirust_dist::component::{ContentStore, has_interrupted_transaction};
+use multirust_dist::manifestation::{UpdateStatus, UpdateSummary};
+use multirust_dist::prefix::InstallPrefix;
+use multirust_utils::{raw, utils};
+use override_db::{OverrideDB, DB_DELIMITER};
 use toolchain::Toolchain;
 
 // Note: multirust-rs jumped from 2 to 12 to leave multirust.sh room to diverge
 pub const METADATA_VERSION: &'static str = "12";
 
+// Temp entries older than this are assumed to be abandoned, even if a
+// process with the pid that made them is running
+const TEMP_MAX_AGE: i64 = 24 * 60 * 60;
+
 #[derive(Debug)]
 pub enum OverrideReason {
     Environment,
@@ -41,10 +49,15 @@ pub struct Cfg {
     pub default_file: PathBuf,
     pub toolchains_dir: PathBuf,
     pub update_hash_dir: PathBuf,
+    pub last_used_dir: PathBuf,
+    pub sources_dir: PathBuf,
+    pub locks_dir: PathBuf,
+    pub store_dir: PathBuf,
     pub temp_cfg: temp::Cfg,
     pub gpg_key: Cow<'static, str>,
     pub env_override: Option<String>,
     pub dist_root_url: Cow<'static, str>,
+    pub dedup_on_install: bool,
     pub notify_handler: SharedNotifyHandler,
 }
 
@@ -61,6 +74,10 @@ impl Cfg {
         let default_file = multirust_dir.join("default");
         let toolchains_dir = multirust_dir.join("toolchains");
         let update_hash_dir = multirust_dir.join("update-hashes");
+        let last_used_dir = multirust_dir.join("last-used");
+        let sources_dir = multirust_dir.join("sources");
+        let locks_dir = multirust_dir.join("locks");
+        let store_dir = multirust_dir.join("store");
 
         let notify_clone = notify_handler.clone();
         let temp_cfg = temp::Cfg::new(multirust_dir.join("tmp"),
@@ -86,6 +103,12 @@ impl Cfg {
                                 .and_then(utils::if_not_empty)
                                 .map_or(Cow::Borrowed(dist::DEFAULT_DIST_ROOT), Cow::Owned);
 
+        // Hard link the files of newly installed toolchains into the
+        // content store, as `multirust dedup` does
+        let dedup_on_install = env::var_os("MULTIRUST_DEDUP")
+                                   .and_then(utils::if_not_empty)
+                                   .is_some();
+
         Ok(Cfg {
             multirust_dir: multirust_dir,
             version_file: version_file,
@@ -93,15 +116,79 @@ impl Cfg {
             default_file: default_file,
             toolchains_dir: toolchains_dir,
             update_hash_dir: update_hash_dir,
+            last_used_dir: last_used_dir,
+            sources_dir: sources_dir,
+            locks_dir: locks_dir,
+            store_dir: store_dir,
             temp_cfg: temp_cfg,
             gpg_key: gpg_key,
             notify_handler: notify_handler,
             env_override: env_override,
             dist_root_url: dist_root_url,
+            dedup_on_install: dedup_on_install,
         })
     }
 
+    /// Takes the lock that serializes changes to the default
+    /// toolchain and the override database. Reading them doesn't
+    /// need the lock.
+    ///
+    /// Whoever holds the lock also sweeps the temp directory, so that
+    /// crashed runs don't leave their files behind for long.
+    pub fn lock_metadata(&self) -> Result<utils::FileLock> {
+        let lock = try!(self.acquire_metadata_lock());
+        ok_ntfy!(self.notify_handler, Notification::NonFatalError, self.sweep_tmp());
+        Ok(lock)
+    }
+
+    fn acquire_metadata_lock(&self) -> Result<utils::FileLock> {
+        Ok(try!(utils::FileLock::acquire("metadata lock",
+                                         &self.locks_dir.join("metadata.lock"),
+                                         ntfy!(&self.notify_handler))))
+    }
+
+    /// Removes the temp files and directories left behind by runs of
+    /// multirust that crashed or were killed, returning how many were
+    /// removed
+    pub fn clean_tmp(&self) -> Result<usize> {
+        // Toolchain locks come before the metadata lock
+        for name in try!(self.list_toolchains()) {
+            try!(try!(self.get_toolchain(&name, false)).recover_interrupted());
+        }
+        let _lock = try!(self.acquire_metadata_lock());
+        self.sweep_tmp()
+    }
+
+    // An interrupted transaction needs the backups it kept in the temp
+    // directory to be rolled back, so nothing is removed until it has
+    // been recovered under its toolchain's lock
+    fn sweep_tmp(&self) -> Result<usize> {
+        for name in try!(self.list_toolchains()) {
+            let prefix = InstallPrefix::from(self.toolchains_dir.join(&name));
+            if try!(has_interrupted_transaction(&prefix)) {
+                return Ok(0);
+            }
+        }
+        Ok(try!(self.temp_cfg.clean(TEMP_MAX_AGE)))
+    }
+
+    /// Takes the lock that serializes changes to the content store.
+    /// It is taken after any toolchain lock, never before.
+    pub fn lock_store(&self) -> Result<utils::FileLock> {
+        Ok(try!(utils::FileLock::acquire("store lock",
+                                         &self.locks_dir.join("store.lock"),
+                                         ntfy!(&self.notify_handler))))
+    }
+
+    /// Removes files from the content store that no toolchain uses
+    /// any more, returning how many were removed
+    pub fn prune_store(&self) -> Result<u64> {
+        let _lock = try!(self.lock_store());
+        Ok(try!(ContentStore::new(self.store_dir.clone()).prune()))
+    }
+
     pub fn set_default(&self, toolchain: &str) -> Result<()> {
+        let _lock = try!(self.lock_metadata());
         let work_file = try!(self.temp_cfg.new_file());
 
         try!(utils::write_file("temp", &work_file, toolchain));
@@ -163,6 +250,8 @@ impl Cfg {
             return Ok(());
         }
 
+        let _lock = try!(self.lock_metadata());
+
         self.notify_handler
             .call(Notification::UpgradingMetadata(&current_version, METADATA_VERSION));
 
@@ -206,37 +295,57 @@ impl Cfg {
         }
     }
 
-    pub fn find_default(&self) -> Result<Option<Toolchain>> {
+    /// The name of the default toolchain, without checking that it's
+    /// installed
+    pub fn default_name(&self) -> Result<Option<String>> {
         if !utils::is_file(&self.default_file) {
             return Ok(None);
         }
         let content = try!(utils::read_file("default", &self.default_file));
         let name = content.trim_matches('\n');
         if name.is_empty() {
-            return Ok(None);
+            Ok(None)
+        } else {
+            Ok(Some(name.to_owned()))
         }
+    }
 
-        let toolchain = try!(self.verify_toolchain(name).map_err(|_| Error::ToolchainNotInstalled(name.to_string())));
+    pub fn find_default(&self) -> Result<Option<Toolchain>> {
+        let name = match try!(self.default_name()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        let toolchain = try!(self.verify_toolchain(&name).map_err(|e| not_installed(&name, e)));
 
         Ok(Some(toolchain))
     }
 
     pub fn find_override(&self, path: &Path) -> Result<Option<(Toolchain, OverrideReason)>> {
         if let Some(ref name) = self.env_override {
-            let toolchain = try!(self.verify_toolchain(name).map_err(|_| Error::ToolchainNotInstalled(name.to_string())));
+            let toolchain = try!(self.verify_toolchain(name).map_err(|e| not_installed(name, e)));
 
             return Ok(Some((toolchain, OverrideReason::Environment)));
         }
 
         if let Some((name, reason_path)) = try!(self.override_db
                                                     .find(path, self.notify_handler.as_ref())) {
-            let toolchain = try!(self.verify_toolchain(&name).map_err(|_| Error::ToolchainNotInstalled(name.to_string())));
+            let toolchain = try!(self.verify_toolchain(&name).map_err(|e| not_installed(&name, e)));
             return Ok(Some((toolchain, OverrideReason::OverrideDB(reason_path))));
         }
 
         Ok(None)
     }
 
+    /// The name of the toolchain overriding the default for `path`,
+    /// without checking that it's installed
+    pub fn find_override_name(&self, path: &Path) -> Result<Option<String>> {
+        if let Some(ref name) = self.env_override {
+            return Ok(Some(name.clone()));
+        }
+        Ok(try!(self.override_db.find(path, self.notify_handler.as_ref())).map(|(name, _)| name))
+    }
+
     pub fn find_override_toolchain_or_default
         (&self,
          path: &Path)
@@ -250,9 +359,11 @@ impl Cfg {
 
     pub fn list_toolchains(&self) -> Result<Vec<String>> {
         if utils::is_directory(&self.toolchains_dir) {
+            // Hidden directories are updates being staged
             let toolchains: Vec<_> = try!(utils::read_dir("toolchains", &self.toolchains_dir))
                                          .filter_map(io::Result::ok)
                                          .filter_map(|e| e.file_name().into_string().ok())
+                                         .filter(|n| !n.starts_with("."))
                                          .collect();
 
             Ok(toolchains)
@@ -261,25 +372,98 @@ impl Cfg {
         }
     }
 
-    pub fn update_all_channels(&self) -> Result<Vec<(String, Result<()>)>> {
+    /// The toolchains that are the default or used by an override
+    pub fn toolchains_in_use(&self) -> Result<HashSet<String>> {
+        let mut in_use = HashSet::new();
+        in_use.extend(try!(self.default_name()));
+        in_use.extend(self.env_override.clone());
+        for o in try!(self.override_db.list()) {
+            if let Some(name) = o.rsplitn(2, DB_DELIMITER).next() {
+                in_use.insert(name.to_owned());
+            }
+        }
+        Ok(in_use)
+    }
+
+    /// Finds the toolchains that aren't the default, aren't used by
+    /// an override, and haven't been used for `secs` seconds, with
+    /// the time each was last used. Toolchains whose use has never
+    /// been recorded are kept.
+    pub fn find_unused_toolchains(&self, secs: i64) -> Result<Vec<(String, i64)>> {
+        let in_use = try!(self.toolchains_in_use());
         let mut toolchains = try!(self.list_toolchains());
         toolchains.sort();
 
-        Ok(toolchains.into_iter()
-                     .merge(["beta", "nightly", "stable"].into_iter().map(|s| (*s).to_owned()))
-                     .dedup()
-                     .filter(|name| {
-                         dist::ToolchainDesc::from_str(&name).map(|d| d.is_tracking()).ok() == Some(true)
-                     })
-                     .map(|name| {
-                         let result = self.get_toolchain(&name, true)
-                                          .and_then(|t| t.install_from_dist());
-                         if let Err(ref e) = result {
-                             self.notify_handler.call(Notification::NonFatalError(e));
-                         }
-                         (name, result)
-                     })
-                     .collect())
+        let mut unused = Vec::new();
+        for name in toolchains {
+            if in_use.contains(&name) {
+                continue;
+            }
+            let toolchain = try!(self.get_toolchain(&name, false));
+            if let Some(t) = try!(toolchain.unused_since(secs)) {
+                unused.push((name, t));
+            }
+        }
+
+        Ok(unused)
+    }
+
+    /// Starts tracking the use of toolchains whose use has never been
+    /// recorded, so that `find_unused_toolchains` can find them later
+    pub fn track_unrecorded_toolchains(&self) -> Result<()> {
+        for name in try!(self.list_toolchains()) {
+            let toolchain = try!(self.get_toolchain(&name, false));
+            if try!(toolchain.last_used()).is_none() {
+                toolchain.record_use();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_all_channels(&self) -> Result<Vec<(String, Result<UpdateStatus>)>> {
+        let mut toolchains = try!(self.list_toolchains());
+        toolchains.sort();
+
+        let toolchains: Vec<String> =
+            toolchains.into_iter()
+                      .merge(["beta", "nightly", "stable"].into_iter().map(|s| (*s).to_owned()))
+                      .dedup()
+                      .filter(|name| {
+                          // Custom toolchains are updated from where
+                          // they were installed from, if that's known
+                          let toolchain = Toolchain::from(self, name);
+                          if toolchain.is_custom() {
+                              toolchain.source().ok().and_then(|s| s).is_some()
+                          } else {
+                              toolchain.is_tracking()
+                          }
+                      })
+                      .collect();
+
+        let mut results = Vec::new();
+        for name in toolchains {
+            let result = self.get_toolchain(&name, true).and_then(|t| {
+                if t.is_custom() {
+                    // There's nothing to compare a custom
+                    // toolchain's new contents with
+                    t.update_from_source()
+                     .map(|_| UpdateStatus::Changed(UpdateSummary::default()))
+                } else {
+                    t.install_from_dist(None, &[], &[])
+                }
+            });
+            // Cancelling stops the whole update rather than failing
+            // the remaining toolchains one by one
+            if raw::is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            if let Err(ref e) = result {
+                self.notify_handler.call(Notification::NonFatalError(e));
+            }
+            results.push((name, result));
+        }
+
+        Ok(results)
     }
 
     pub fn check_metadata_version(&self) -> Result<()> {
@@ -325,3 +509,11 @@ impl Cfg {
     }
 }
 
+// Any failure to verify an override or default toolchain is reported
+// as it not being installed, unless it's a link that has broken
+fn not_installed(name: &str, e: Error) -> Error {
+    match e {
+        Error::ToolchainBroken(_, _) => e,
+        _ => Error::ToolchainNotInstalled(name.to_owned()),
+    }
+}
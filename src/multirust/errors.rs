@@ -26,6 +26,7 @@ pub enum Notification<'a> {
     UninstalledToolchain(&'a str),
     ToolchainNotInstalled(&'a str),
     UpdateHashMatches,
+    DeduplicatingToolchain(&'a str),
     UpgradingMetadata(&'a str, &'a str),
     MetadataUpgradeNotNeeded(&'a str),
     WritingMetadataVersion(&'a str),
@@ -33,6 +34,7 @@ pub enum Notification<'a> {
     NonFatalError(&'a Error),
     UpgradeRemovesToolchains,
     MissingFileDuringSelfUninstall(PathBuf),
+    RestoringToolchainLink(&'a str),
 }
 
 #[derive(Debug)]
@@ -46,21 +48,29 @@ pub enum Error {
     NoDefaultToolchain,
     PermissionDenied,
     ToolchainNotInstalled(String),
+    ToolchainDamaged(String),
+    ToolchainBroken(String, PathBuf),
+    NoToolchainSource(String),
     UnknownHostTriple,
     InfiniteRecursion,
     NeedMetadataUpgrade,
     UpgradeIoError(io::Error),
-    BadInstallerType(String),
     ComponentsUnsupported(String),
     UnknownComponent(String, Component),
     AddingRequiredComponent(String, Component),
-    RemovingRequiredComponent(String, Component),
+    RemovingRequiredToolchainComponent(String, Component),
     NoExeName,
     NotSelfInstalled(PathBuf),
     CantSpawnWindowsGcExe,
     WindowsUninstallMadness(io::Error),
     SelfUpdateFailed,
     ReadStdin,
+    InvalidDuration(String),
+    WrongChecksumCount {
+        urls: usize,
+        checksums: usize,
+    },
+    Cancelled,
     Custom {
         id: String,
         desc: String,
@@ -89,7 +99,8 @@ impl<'a> Notification<'a> {
             ToolchainDirectory(_, _) |
             LookingForToolchain(_) |
             WritingMetadataVersion(_) |
-            ReadMetadataVersion(_) => NotificationLevel::Verbose,
+            ReadMetadataVersion(_) |
+            DeduplicatingToolchain(_) => NotificationLevel::Verbose,
             SetDefaultToolchain(_) |
             SetOverrideToolchain(_, _) |
             UpdatingToolchain(_) |
@@ -104,6 +115,7 @@ impl<'a> Notification<'a> {
             UpdateHashMatches => NotificationLevel::Info,
             NonFatalError(_) => NotificationLevel::Error,
             UpgradeRemovesToolchains |
+            RestoringToolchainLink(_) |
             MissingFileDuringSelfUninstall(_) => NotificationLevel::Warn,
         }
     }
@@ -131,6 +143,7 @@ impl<'a> Display for Notification<'a> {
             UsingExistingToolchain(name) => write!(f, "using existing install for '{}'", name),
             UninstallingToolchain(name) => write!(f, "uninstalling toolchain '{}'", name),
             UninstalledToolchain(name) => write!(f, "toolchain '{}' uninstalled", name),
+            DeduplicatingToolchain(name) => write!(f, "deduplicating files of toolchain '{}'", name),
             ToolchainNotInstalled(name) => write!(f, "no toolchain installed for '{}'", name),
             UpdateHashMatches => {
                 write!(f, "toolchain is already up to date")
@@ -153,6 +166,12 @@ impl<'a> Display for Notification<'a> {
             MissingFileDuringSelfUninstall(ref p) => {
                 write!(f, "expected file does not exist to uninstall: {}", p.display())
             }
+            RestoringToolchainLink(name) => {
+                write!(f,
+                       "restoring the link of toolchain '{}', which an interrupted \
+                        installation had moved aside",
+                       name)
+            }
         }
     }
 }
@@ -169,21 +188,26 @@ impl error::Error for Error {
             NoDefaultToolchain => "no default toolchain configured",
             PermissionDenied => "permission denied",
             ToolchainNotInstalled(_) => "toolchain is not installed",
+            ToolchainDamaged(_) => "toolchain has damaged components",
+            ToolchainBroken(_, _) => "linked toolchain is broken",
+            NoToolchainSource(_) => "custom toolchain has no recorded source",
             UnknownHostTriple => "unknown host triple",
             InfiniteRecursion =>  "infinite recursion detected",
             NeedMetadataUpgrade => "multirust's metadata is out of date. run multirust upgrade-data.",
             UpgradeIoError(_) => "I/O error during upgrade",
-            BadInstallerType(_) => "invalid extension for installer",
             ComponentsUnsupported(_) => "toolchain does not support componentsn",
             UnknownComponent(_ ,_) => "toolchain does not contain component",
             AddingRequiredComponent(_, _) => "required component cannot be added",
-            RemovingRequiredComponent(_, _) => "required component cannot be removed",
+            RemovingRequiredToolchainComponent(_, _) => "required component cannot be removed",
             NoExeName => "couldn't determine self executable name",
             NotSelfInstalled(_) => "multirust is not installed",
             CantSpawnWindowsGcExe => "failed to spawn cleanup process",
             WindowsUninstallMadness(_) => "failure during windows uninstall",
             SelfUpdateFailed => "self-updater failed to replace multirust executable",
             ReadStdin => "unable to read from stdin for confirmation",
+            InvalidDuration(_) => "invalid duration",
+            WrongChecksumCount {..} => "number of checksums doesn't match number of installer URLs",
+            Cancelled => "cancelled, rolled back",
             Custom { ref desc, .. } => desc,
         }
     }
@@ -201,19 +225,24 @@ impl error::Error for Error {
             NoDefaultToolchain |
             PermissionDenied |
             ToolchainNotInstalled(_) |
+            ToolchainDamaged(_) |
+            ToolchainBroken(_, _) |
+            NoToolchainSource(_) |
             UnknownHostTriple |
             InfiniteRecursion |
             NeedMetadataUpgrade |
-            BadInstallerType(_) |
             ComponentsUnsupported(_) |
             UnknownComponent(_, _) |
             AddingRequiredComponent(_, _) |
-            RemovingRequiredComponent(_, _) |
+            RemovingRequiredToolchainComponent(_, _) |
             NoExeName |
             NotSelfInstalled(_) |
             CantSpawnWindowsGcExe |
             SelfUpdateFailed |
             ReadStdin |
+            InvalidDuration(_) |
+            WrongChecksumCount {..} |
+            Cancelled |
             Custom {..} => None,
         }
     }
@@ -232,6 +261,13 @@ impl Display for Error {
             NoDefaultToolchain => write!(f, "no default toolchain configured"),
             PermissionDenied => write!(f, "permission denied"),
             ToolchainNotInstalled(ref name) => write!(f, "toolchain '{}' is not installed", name),
+            ToolchainDamaged(ref name) => write!(f, "toolchain '{}' has damaged components", name),
+            ToolchainBroken(ref name, ref path) => {
+                write!(f, "toolchain '{}' is broken: '{}' is missing", name, path.display())
+            }
+            NoToolchainSource(ref name) => {
+                write!(f, "toolchain '{}' has no recorded source to update from", name)
+            }
             UnknownHostTriple => write!(f, "unknown host triple"),
             InfiniteRecursion => {
                 write!(f,
@@ -241,9 +277,6 @@ impl Display for Error {
             UpgradeIoError(ref e) => {
                 write!(f, "I/O error during upgrade: {}", e.description())
             }
-            BadInstallerType(ref s) => {
-                write!(f, "invalid extension for installer: '{}'", s)
-            }
             ComponentsUnsupported(ref t) => {
                 write!(f, "toolchain '{}' does not support components", t)
             }
@@ -254,7 +287,7 @@ impl Display for Error {
                 write!(f, "component '{}' for target '{}' is required for toolchain '{}' and cannot be re-added",
                        c.pkg, c.target, t)
             }
-            RemovingRequiredComponent(ref t, ref c) => {
+            RemovingRequiredToolchainComponent(ref t, ref c) => {
                 write!(f, "component '{}' for target '{}' is required for toolchain '{}' and cannot be removed",
                        c.pkg, c.target, t)
             }
@@ -266,6 +299,13 @@ impl Display for Error {
             WindowsUninstallMadness(ref e) => write!(f, "failure during windows uninstall: {}", e),
             SelfUpdateFailed => write!(f, "{}", self.description()),
             ReadStdin => write!(f, "{}", self.description()),
+            InvalidDuration(ref s) => {
+                write!(f, "invalid duration '{}', expected a number of s, m, h, d or w", s)
+            }
+            WrongChecksumCount { urls, checksums } => {
+                write!(f, "expected a checksum for each of {} installer URLs, found {}", urls, checksums)
+            }
+            Cancelled => write!(f, "cancelled, rolled back any changes"),
             Custom { ref desc, .. } => write!(f, "{}", desc),
         }
     }
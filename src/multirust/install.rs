@@ -6,25 +6,34 @@ use multirust_dist::prefix::InstallPrefix;
 use multirust_utils::utils;
 use multirust_dist::temp;
 use multirust_dist::dist;
-use multirust_dist::component::{Components, TarGzPackage, Transaction, Package};
+use multirust_dist::manifest::Profile;
+use multirust_dist::manifestation::{UpdateStatus, UpdateSummary};
+use multirust_dist::component::{Components, Transaction, Package, JOURNAL_FILE,
+                                open_package, recover_transaction};
+use multirust_dist;
 use errors::Result;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Records the rustc version of a toolchain built from custom
+/// installers, which have no channel manifest to read it from
+pub const INSTALLED_VERSION_FILE: &'static str = "multirust-version";
 
 #[derive(Debug, Copy, Clone)]
 pub enum InstallMethod<'a> {
     Copy(&'a Path),
     Link(&'a Path),
-    Installer(&'a Path, &'a temp::Cfg),
-    Dist(&'a str, Option<&'a Path>, dist::DownloadCfg<'a>),
+    Installers(&'a [PathBuf], &'a temp::Cfg),
+    Dist(&'a str, Option<&'a Path>, Option<Profile>,
+         &'a [String], &'a [String], dist::DownloadCfg<'a>),
 }
 
 impl<'a> InstallMethod<'a> {
-    pub fn run(self, path: &Path, notify_handler: NotifyHandler) -> Result<bool> {
+    pub fn run(self, path: &Path, notify_handler: NotifyHandler) -> Result<UpdateStatus> {
         if path.exists() {
             // Don't uninstall first for Dist method
             match self {
-                InstallMethod::Dist(_, _, _) |
-                InstallMethod::Installer(_, _) => {}
+                InstallMethod::Dist(_, _, _, _, _, _) |
+                InstallMethod::Installers(_, _) => {}
                 _ => {
                     try!(uninstall(path, notify_handler));
                 }
@@ -34,51 +43,94 @@ impl<'a> InstallMethod<'a> {
         match self {
             InstallMethod::Copy(src) => {
                 try!(utils::copy_dir(src, path, ntfy!(&notify_handler)));
-                Ok(true)
+                Ok(UpdateStatus::Changed(UpdateSummary::default()))
             }
             InstallMethod::Link(src) => {
                 try!(utils::symlink_dir(src, &path, ntfy!(&notify_handler)));
-                Ok(true)
+                Ok(UpdateStatus::Changed(UpdateSummary::default()))
             }
-            InstallMethod::Installer(src, temp_cfg) => {
-                try!(InstallMethod::tar_gz(src, path, &temp_cfg, notify_handler));
-                Ok(true)
+            InstallMethod::Installers(srcs, temp_cfg) => {
+                try!(InstallMethod::installers(srcs, path, &temp_cfg, notify_handler));
+                Ok(UpdateStatus::Changed(UpdateSummary::default()))
             }
-            InstallMethod::Dist(toolchain, update_hash, dl_cfg) => {
+            InstallMethod::Dist(toolchain, update_hash, profile, with, without, dl_cfg) => {
                 let ref prefix = InstallPrefix::from(path.to_owned());
-                let maybe_new_hash =
+                let maybe_update =
                     try!(dist::update_from_dist(
                         dl_cfg,
                         update_hash,
                         toolchain,
                         prefix,
-                        &[], &[]));
+                        with, without,
+                        profile));
 
-                if let Some(hash) = maybe_new_hash {
+                if let Some((hash, summary)) = maybe_update {
                     if let Some(hash_file) = update_hash {
                         try!(utils::write_file("update hash", hash_file, &hash));
                     }
 
-                    Ok(true)
+                    Ok(UpdateStatus::Changed(summary))
                 } else {
-                    Ok(false)
+                    Ok(UpdateStatus::Unchanged)
                 }
             }
         }
     }
 
-    fn tar_gz(src: &Path, path: &Path, temp_cfg: &temp::Cfg,
-              notify_handler: NotifyHandler) -> Result<()> {
-        notify_handler.call(Notification::Extracting(src, path));
+    // Replaces the installation with the components of all the
+    // installers in one transaction, so if any of them fails the old
+    // installation is restored
+    fn installers(srcs: &[PathBuf], path: &Path, temp_cfg: &temp::Cfg,
+                  notify_handler: NotifyHandler) -> Result<()> {
+        // Unpack everything before touching the old installation
+        let mut packages = Vec::new();
+        for src in srcs {
+            notify_handler.call(Notification::Extracting(src, path));
+            packages.push(try!(open_package(src, temp_cfg)));
+        }
 
         let prefix = InstallPrefix::from(path.to_owned());
-        let installation = try!(Components::open(prefix.clone()));
-        let package = try!(TarGzPackage::new_file(src, temp_cfg));
+        try!(recover_transaction(&prefix, notify_handler));
 
         let mut tx = Transaction::new(prefix.clone(), temp_cfg, notify_handler);
 
-        for component in package.components() {
-            tx = try!(package.install(&installation, &component, None, tx));
+        if utils::is_directory(path) {
+            for entry in try!(utils::read_dir("toolchain", path)) {
+                let entry = try!(entry.map_err(|e| {
+                    multirust_dist::Error::InspectingComponentFile(path.to_owned(), e)
+                }));
+                if entry.file_name().to_str() == Some(JOURNAL_FILE) {
+                    continue;
+                }
+                let is_dir = try!(entry.file_type().map_err(|e| {
+                    multirust_dist::Error::InspectingComponentFile(entry.path(), e)
+                })).is_dir();
+                let relpath = PathBuf::from(entry.file_name());
+                if is_dir {
+                    try!(tx.remove_dir("toolchain", relpath));
+                } else {
+                    try!(tx.remove_file("toolchain", relpath));
+                }
+            }
+        }
+
+        let installation = try!(Components::open(prefix.clone(), notify_handler));
+        for package in &packages {
+            for component in package.components() {
+                try!(utils::check_cancelled());
+                tx = try!(package.install(&installation, &component, None, tx));
+            }
+        }
+
+        // Prefer the version of the installer providing rustc
+        let version = packages.iter()
+                              .find(|p| p.contains("rustc", None) && p.version().is_some())
+                              .or_else(|| packages.iter().find(|p| p.version().is_some()))
+                              .and_then(|p| p.version());
+        if let Some(version) = version {
+            try!(tx.write_file("toolchain",
+                               prefix.rel_manifest_file(INSTALLED_VERSION_FILE),
+                               version.to_owned()));
         }
 
         tx.commit();
@@ -4,6 +4,7 @@ extern crate multirust_utils;
 extern crate hyper;
 extern crate regex;
 extern crate itertools;
+extern crate time;
 
 pub use errors::*;
 pub use config::*;
@@ -3,8 +3,12 @@ use multirust_dist::dist;
 use multirust_utils::utils;
 use multirust_dist::prefix::InstallPrefix;
 use multirust_dist::dist::ToolchainDesc;
-use multirust_dist::manifestation::{Manifestation, Changes};
-use multirust_dist::manifest::Component;
+use multirust_dist::manifestation::{Manifestation, Changes, UpdateStatus,
+                                    recover_staged_update};
+use multirust_dist::config::Config;
+use multirust_dist::manifest::{Component, Manifest, Profile};
+use multirust_dist::component::{Components, Verification, ContentStore, DedupStats,
+                                JOURNAL_FILE, recover_transaction};
 use config::Cfg;
 use env_var;
 use install::{self, InstallMethod};
@@ -13,8 +17,14 @@ use std::process::Command;
 use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 use std::env;
+use std::fs;
 
 use hyper;
+use time;
+
+// How stale a toolchain's last-use record gets before running the
+// toolchain again rewrites it
+const LAST_USED_INTERVAL: i64 = 60 * 60;
 
 #[derive(Debug)]
 pub struct Toolchain<'a> {
@@ -23,6 +33,151 @@ pub struct Toolchain<'a> {
     path: PathBuf,
 }
 
+/// A component of a toolchain's "rust" package, and whether it is
+/// part of the installed profile and currently installed.
+#[derive(Debug)]
+pub struct ComponentStatus {
+    pub component: Component,
+    pub required: bool,
+    pub installed: bool,
+    pub available: bool,
+}
+
+/// Where a custom toolchain was installed from, so that updating it
+/// can install it again from the same place
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToolchainSource {
+    Copy(PathBuf),
+    Link(PathBuf),
+    Installers {
+        /// Installer URLs and absolute paths
+        locations: Vec<String>,
+        /// The SHA-256 hashes the URLs were pinned to, in order, if
+        /// any were given
+        hashes: Vec<String>,
+        require_checksum: bool,
+    },
+}
+
+impl ToolchainSource {
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ToolchainSource::Copy(_) => "copy",
+            ToolchainSource::Link(_) => "link",
+            ToolchainSource::Installers { .. } => "installers",
+        }
+    }
+    /// The directory or installers the toolchain came from
+    pub fn locations(&self) -> Vec<String> {
+        match *self {
+            ToolchainSource::Copy(ref p) |
+            ToolchainSource::Link(ref p) => vec![p.to_string_lossy().into_owned()],
+            ToolchainSource::Installers { ref locations, .. } => locations.clone(),
+        }
+    }
+    // The kind of source on the first line, then its locations one
+    // per line. Installers are preceded by their options, which can't
+    // be mistaken for URLs or absolute paths.
+    fn encode(&self) -> String {
+        let mut s = format!("{}\n", self.kind());
+        if let ToolchainSource::Installers { ref hashes, require_checksum, .. } = *self {
+            if require_checksum {
+                s.push_str("require-checksum\n");
+            }
+            for hash in hashes {
+                s.push_str(&format!("sha256:{}\n", hash));
+            }
+        }
+        for location in self.locations() {
+            s.push_str(&location);
+            s.push('\n');
+        }
+        s
+    }
+    fn decode(s: &str) -> Option<Self> {
+        let mut lines = s.lines();
+        let kind = lines.next();
+        let locations: Vec<String> = lines.map(|l| l.to_owned()).collect();
+        match (kind, locations.len()) {
+            (Some("copy"), 1) => Some(ToolchainSource::Copy(PathBuf::from(&locations[0]))),
+            (Some("link"), 1) => Some(ToolchainSource::Link(PathBuf::from(&locations[0]))),
+            (Some("installers"), _) => {
+                let mut hashes = Vec::new();
+                let mut require_checksum = false;
+                let mut installers = Vec::new();
+                for line in locations {
+                    if line == "require-checksum" {
+                        require_checksum = true;
+                    } else if line.starts_with("sha256:") {
+                        hashes.push(line["sha256:".len()..].to_owned());
+                    } else {
+                        installers.push(line);
+                    }
+                }
+                if installers.is_empty() {
+                    return None;
+                }
+                Some(ToolchainSource::Installers {
+                    locations: installers,
+                    hashes: hashes,
+                    require_checksum: require_checksum,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a toolchain was installed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToolchainKind {
+    /// From a release channel
+    Dist,
+    /// Copied or installed from custom installers
+    Custom,
+    /// A link to a directory elsewhere
+    Linked,
+}
+
+impl ToolchainKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ToolchainKind::Dist => "dist",
+            ToolchainKind::Custom => "custom",
+            ToolchainKind::Linked => "linked",
+        }
+    }
+}
+
+/// A description of an installed toolchain, read from its metadata
+/// without running any of its binaries
+#[derive(Debug)]
+pub struct ToolchainInfo {
+    pub kind: ToolchainKind,
+    pub broken: bool,
+    pub source: Option<ToolchainSource>,
+    /// The date of the installed channel manifest
+    pub date: Option<String>,
+    /// The version of the installed "rust" package
+    pub version: Option<String>,
+    /// The targets that have components installed
+    pub targets: Vec<String>,
+    /// The disk space used, unless the toolchain is linked
+    pub size: Option<u64>,
+    /// Why the toolchain's metadata couldn't be read
+    pub error: Option<String>,
+}
+
+/// The disk space used by a toolchain
+#[derive(Debug)]
+pub struct ToolchainUsage {
+    pub size: utils::Size,
+    /// Linked toolchains live elsewhere, so they aren't measured
+    pub linked: bool,
+    /// The size of each installed component
+    pub components: Vec<(String, u64)>,
+}
+
 impl<'a> Toolchain<'a> {
     pub fn from(cfg: &'a Cfg, name: &str) -> Self {
         let path = cfg.toolchains_dir.join(name);
@@ -42,10 +197,114 @@ impl<'a> Toolchain<'a> {
         utils::is_directory(&self.path)
     }
     pub fn verify(&self) -> Result<()> {
+        if self.is_linked() {
+            // A link outlives the directory it points to, and that
+            // directory may not hold a toolchain any more
+            let target = fs::read_link(&self.path).unwrap_or(self.path.clone());
+            if !utils::is_directory(&target) {
+                return Err(Error::ToolchainBroken(self.name.clone(), target));
+            }
+            let ref rustc = target.join("bin").join(format!("rustc{}", env::consts::EXE_SUFFIX));
+            if !utils::is_file(rustc) {
+                return Err(Error::ToolchainBroken(self.name.clone(), rustc.clone()));
+            }
+            return Ok(());
+        }
         Ok(try!(utils::assert_is_directory(&self.path)))
     }
+    /// Whether the toolchain is a link to a directory elsewhere
+    pub fn is_linked(&self) -> bool {
+        fs::symlink_metadata(&self.path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false)
+    }
+    /// Takes the lock that serializes changes to this toolchain.
+    /// Anything that only reads the toolchain, like running its
+    /// binaries, doesn't need it, though running only tries to take
+    /// it to recover an interrupted update.
+    ///
+    /// The lock isn't reentrant, so it is taken once by each public
+    /// operation that changes the toolchain.
+    ///
+    /// Whoever takes the lock first puts back what an interrupted
+    /// update left aside.
+    fn lock(&self) -> Result<utils::FileLock> {
+        let lock = try!(utils::FileLock::acquire("toolchain lock", &self.lock_path(),
+                                                 ntfy!(&self.cfg.notify_handler)));
+        try!(self.recover());
+        Ok(lock)
+    }
+    fn lock_path(&self) -> PathBuf {
+        self.cfg.locks_dir.join(format!("toolchain-{}.lock", self.name))
+    }
+    // Recovers from an interrupted update before the toolchain is
+    // run, so that its binaries aren't run half-changed. Running
+    // doesn't wait for the lock: if it is held, whatever holds it is
+    // changing the toolchain and recovers it first.
+    fn recover_if_unlocked(&self) -> Result<()> {
+        let interrupted = fs::symlink_metadata(&self.path).is_err()
+            || utils::is_file(self.path.join(JOURNAL_FILE))
+            || fs::symlink_metadata(self.moved_link_path()).is_ok();
+        if !interrupted {
+            return Ok(());
+        }
+        if let Some(_lock) = try!(utils::FileLock::try_acquire("toolchain lock", &self.lock_path(),
+                                                               ntfy!(&self.cfg.notify_handler))) {
+            try!(self.recover());
+        }
+        Ok(())
+    }
+    // Finishes whatever a crashed process was doing to the toolchain.
+    // Only done under the toolchain lock, so that nothing else is
+    // changing it at the same time.
+    fn recover(&self) -> Result<()> {
+        let prefix = InstallPrefix::from(self.path.clone());
+        try!(recover_staged_update(&prefix, ntfy!(&self.cfg.notify_handler)));
+        try!(recover_transaction(&prefix, ntfy!(&self.cfg.notify_handler)));
+
+        // A link moved aside by `install_from_installers` is put back
+        // if the installation didn't finish, and removed if it did
+        let ref link = self.moved_link_path();
+        if fs::symlink_metadata(link).is_ok() {
+            if fs::symlink_metadata(&self.path).is_err() {
+                self.cfg.notify_handler.call(Notification::RestoringToolchainLink(&self.name));
+                try!(utils::rename_file("toolchain link", link, &self.path));
+            } else {
+                try!(utils::remove_dir("toolchain link", link, ntfy!(&self.cfg.notify_handler)));
+            }
+        }
+        Ok(())
+    }
+    fn moved_link_path(&self) -> PathBuf {
+        self.cfg.toolchains_dir.join(format!(".{}.link", self.name))
+    }
+    /// Recovers the toolchain from any update or installation that
+    /// was interrupted
+    pub fn recover_interrupted(&self) -> Result<()> {
+        let _lock = try!(self.lock());
+        Ok(())
+    }
     pub fn remove(&self) -> Result<()> {
-        if self.exists() {
+        let _lock = try!(self.lock());
+        self.uninstall()
+    }
+    /// Removes the toolchain if it still isn't in use and hasn't been
+    /// used for `secs` seconds, returning whether it was removed. That
+    /// is checked under the metadata lock, so the toolchain can't be
+    /// made the default or an override in the meantime.
+    pub fn remove_if_unused(&self, secs: i64) -> Result<bool> {
+        let _lock = try!(self.lock());
+        let _metadata_lock = try!(self.cfg.lock_metadata());
+        if try!(self.cfg.toolchains_in_use()).contains(&self.name)
+            || try!(self.unused_since(secs)).is_none() {
+            return Ok(false);
+        }
+        try!(self.uninstall());
+        Ok(true)
+    }
+    fn uninstall(&self) -> Result<()> {
+        // A broken link doesn't exist as a toolchain, but is removed
+        if self.exists() || self.is_linked() {
             self.cfg.notify_handler.call(Notification::UninstallingToolchain(&self.name));
         } else {
             self.cfg.notify_handler.call(Notification::ToolchainNotInstalled(&self.name));
@@ -54,6 +313,14 @@ impl<'a> Toolchain<'a> {
         if let Some(update_hash) = try!(self.update_hash()) {
             try!(utils::remove_file("update hash", &update_hash));
         }
+        let ref last_used = self.last_used_file();
+        if utils::is_file(last_used) {
+            try!(utils::remove_file("last use", last_used));
+        }
+        let ref source = self.source_file();
+        if utils::is_file(source) {
+            try!(utils::remove_file("toolchain source", source));
+        }
         let handler = self.cfg.notify_handler.as_ref();
         let result = install::uninstall(&self.path, ntfy!(&handler));
         if !self.exists() {
@@ -61,7 +328,7 @@ impl<'a> Toolchain<'a> {
         }
         Ok(try!(result))
     }
-    fn install(&self, install_method: InstallMethod) -> Result<()> {
+    fn install(&self, install_method: InstallMethod) -> Result<UpdateStatus> {
         assert!(self.is_valid_install_method(install_method));
         if self.exists() {
             self.cfg.notify_handler.call(Notification::UpdatingToolchain(&self.name));
@@ -72,21 +339,45 @@ impl<'a> Toolchain<'a> {
             .notify_handler
             .call(Notification::ToolchainDirectory(&self.path, &self.name));
         let handler = self.cfg.notify_handler.as_ref();
-        let updated = try!(install_method.run(&self.path, ntfy!(&handler)));
+        let status = try!(install_method.run(&self.path, ntfy!(&handler)));
 
-        if !updated {
+        if status == UpdateStatus::Unchanged {
             self.cfg.notify_handler.call(Notification::UpdateHashMatches);
         } else {
             self.cfg.notify_handler.call(Notification::InstalledToolchain(&self.name));
+            self.record_use();
+            if self.cfg.dedup_on_install {
+                try!(self.dedup_files());
+            }
         }
 
-        Ok(())
+        Ok(status)
+    }
+    /// Replaces the files of the toolchain with hard links into the
+    /// content store, so that files it shares with other toolchains
+    /// are only stored once
+    pub fn dedup(&self) -> Result<DedupStats> {
+        let _lock = try!(self.lock());
+        self.dedup_files()
+    }
+    fn dedup_files(&self) -> Result<DedupStats> {
+        // Linked toolchains belong to someone else
+        if !self.exists() || self.is_linked() {
+            return Ok(DedupStats::default());
+        }
+        self.cfg.notify_handler.call(Notification::DeduplicatingToolchain(&self.name));
+
+        let _lock = try!(self.cfg.lock_store());
+        let components = try!(Components::open(InstallPrefix::from(self.path.clone()),
+                                               ntfy!(&self.cfg.notify_handler)));
+        let store = ContentStore::new(self.cfg.store_dir.clone());
+        Ok(try!(store.dedup(&components)))
     }
     fn install_if_not_installed(&self, install_method: InstallMethod) -> Result<()> {
         assert!(self.is_valid_install_method(install_method));
         self.cfg.notify_handler.call(Notification::LookingForToolchain(&self.name));
         if !self.exists() {
-            self.install(install_method)
+            self.install(install_method).map(|_| ())
         } else {
             self.cfg.notify_handler.call(Notification::UsingExistingToolchain(&self.name));
             Ok(())
@@ -96,9 +387,43 @@ impl<'a> Toolchain<'a> {
         match install_method {
             InstallMethod::Copy(_) |
             InstallMethod::Link(_) |
-            InstallMethod::Installer(_, _) => self.is_custom(),
-            InstallMethod::Dist(_, _, _) => !self.is_custom(),
+            InstallMethod::Installers(_, _) => self.is_custom(),
+            InstallMethod::Dist(_, _, _, _, _, _) => !self.is_custom(),
+        }
+    }
+    fn last_used_file(&self) -> PathBuf {
+        self.cfg.last_used_dir.join(&self.name)
+    }
+    /// When the toolchain was last run, in seconds since the epoch,
+    /// if that has been recorded
+    pub fn last_used(&self) -> Result<Option<i64>> {
+        let ref path = self.last_used_file();
+        if !utils::is_file(path) {
+            return Ok(None);
         }
+        Ok(try!(utils::read_file("last use", path)).trim().parse().ok())
+    }
+    /// When the toolchain was last run, if that was at least `secs`
+    /// seconds ago
+    pub fn unused_since(&self, secs: i64) -> Result<Option<i64>> {
+        let now = time::get_time().sec;
+        Ok(try!(self.last_used()).and_then(|t| if now - t >= secs { Some(t) } else { None }))
+    }
+    /// Records that the toolchain is being used, for `multirust gc`.
+    /// This happens on every proxied command, so the record is only
+    /// rewritten once it's stale, and failing to write it is ignored.
+    pub fn record_use(&self) {
+        let now = time::get_time().sec;
+        if let Ok(Some(t)) = self.last_used() {
+            if t <= now && now - t < LAST_USED_INTERVAL {
+                return;
+            }
+        }
+        let _ = utils::ensure_dir_exists("last use", &self.cfg.last_used_dir,
+                                         ntfy!(&self.cfg.notify_handler))
+                    .and_then(|_| utils::write_file("last use",
+                                                    &self.last_used_file(),
+                                                    &now.to_string()));
     }
     fn update_hash(&self) -> Result<Option<PathBuf>> {
         if self.is_custom() {
@@ -116,17 +441,43 @@ impl<'a> Toolchain<'a> {
         }
     }
 
-    pub fn install_from_dist(&self) -> Result<()> {
+    /// Installs or updates the toolchain from its channel, returning
+    /// what changed
+    pub fn install_from_dist(&self, profile: Option<Profile>,
+                             with: &[String], without: &[String]) -> Result<UpdateStatus> {
+        let _lock = try!(self.lock());
         let update_hash = try!(self.update_hash());
         self.install(InstallMethod::Dist(&self.name,
                                          update_hash.as_ref().map(|p| &**p),
+                                         profile,
+                                         with, without,
                                          self.download_cfg()))
     }
-    pub fn install_from_dist_if_not_installed(&self) -> Result<()> {
+    /// Installs the toolchain if it isn't installed. An installed one
+    /// stays at its release, but still gets the components named in
+    /// `with`, loses those in `without` and switches to `profile`.
+    pub fn install_from_dist_if_not_installed(&self, profile: Option<Profile>,
+                                              with: &[String], without: &[String]) -> Result<()> {
+        let _lock = try!(self.lock());
+        let existed = self.exists();
         let update_hash = try!(self.update_hash());
-        self.install_if_not_installed(InstallMethod::Dist(&self.name,
-                                                          update_hash.as_ref().map(|p| &**p),
-                                                          self.download_cfg()))
+        try!(self.install_if_not_installed(InstallMethod::Dist(&self.name,
+                                                               update_hash.as_ref().map(|p| &**p),
+                                                               profile,
+                                                               with, without,
+                                                               self.download_cfg())));
+
+        if existed && (!with.is_empty() || !without.is_empty() || profile.is_some()) {
+            let (manifestation, manifest, trip) = try!(self.installed_manifest());
+            let changes = try!(dist::build_changes(&manifestation, &manifest, &trip,
+                                                   with, without, profile));
+            try!(manifestation.update(&manifest,
+                                      changes,
+                                      self.download_cfg().temp_cfg,
+                                      self.download_cfg().notify_handler.clone()));
+        }
+
+        Ok(())
     }
     pub fn is_custom(&self) -> bool {
         ToolchainDesc::from_str(&self.name).is_err()
@@ -143,69 +494,165 @@ impl<'a> Toolchain<'a> {
         }
     }
 
-    pub fn install_from_installers(&self, installers: &[&OsStr]) -> Result<()> {
+    /// Installs from custom installers, which may be local or URLs.
+    /// Downloads are verified against `hashes`, one for each URL in
+    /// order, or else against the hashes published next to them.
+    pub fn install_from_installers(&self,
+                                   installers: &[&OsStr],
+                                   hashes: &[&str],
+                                   require_checksum: bool)
+                                   -> Result<()> {
         try!(self.ensure_custom());
 
-        try!(self.remove());
+        // FIXME: Pretty hacky
+        let is_url = |installer: &str| {
+            let is_url = installer.starts_with("file://")
+                || installer.starts_with("http://")
+                || installer.starts_with("https://");
+            is_url && hyper::Url::parse(installer).is_ok()
+        };
+        let urls = installers.iter()
+                             .filter(|i| is_url(i.to_str().unwrap_or("bogus")))
+                             .count();
+        for hash in hashes {
+            if dist::parse_sha256(hash).is_none() {
+                let hash = hash.to_string();
+                return Err(Error::Install(::multirust_dist::Error::InvalidChecksum(hash)));
+            }
+        }
+        if !hashes.is_empty() && hashes.len() != urls {
+            return Err(Error::WrongChecksumCount {
+                urls: urls,
+                checksums: hashes.len(),
+            });
+        }
+        let pinned = hashes;
+        let mut hashes = hashes.iter();
 
-        // FIXME: This should do all downloads first, then do
-        // installs, and do it all in a single transaction.
+        let _lock = try!(self.lock());
+
+        // Everything is downloaded before the old toolchain is
+        // touched, so a bad URL leaves it as it was
+        let mut downloads = Vec::new();
+        let mut local_installers = Vec::new();
+        let mut sources = Vec::new();
         for installer in installers {
             let installer_str = installer.to_str().unwrap_or("bogus");
-            match installer_str.rfind(".") {
-                Some(i) => {
-                    let extension = &installer_str[i+1..];
-                    if extension != "gz" {
-                        return Err(Error::BadInstallerType(extension.to_string()));
-                    }
-                }
-                None => return Err(Error::BadInstallerType(String::from("(none)")))
-            }
 
-            // FIXME: Pretty hacky
-            let is_url = installer_str.starts_with("file://")
-                || installer_str.starts_with("http://")
-                || installer_str.starts_with("https://");
-            let url = hyper::Url::parse(installer_str).ok();
-            let url = if is_url { url } else { None };
-            if let Some(url) = url {
-
-                // Download to a local file
-                let local_installer = try!(self.cfg.temp_cfg.new_file_with_ext("", ".tar.gz"));
-                try!(utils::download_file(url,
-                                          &local_installer,
-                                          None,
-                                          ntfy!(&self.cfg.notify_handler)));
-                try!(self.install(InstallMethod::Installer(&local_installer, &self.cfg.temp_cfg)));
+            if is_url(installer_str) {
+                // Download to a local file. The package format is
+                // detected from its contents.
+                let local_installer = try!(dist::download_and_verify(installer_str,
+                                                                     hashes.next().map(|h| *h),
+                                                                     require_checksum,
+                                                                     self.download_cfg()));
+                local_installers.push(local_installer.to_path_buf());
+                downloads.push(local_installer);
+                sources.push(installer_str.to_owned());
             } else {
-                // If installer is a filename
+                // If installer is a file or an unpacked installer
+                // directory there's no need to download
+                let local_installer = try!(utils::to_absolute(installer));
+                sources.push(local_installer.to_string_lossy().into_owned());
+                local_installers.push(local_installer);
+            }
+        }
+
+        // Installing through a link would replace the files it points
+        // to, so the link is moved aside, and put back on failure
+        let ref link = self.moved_link_path();
+        let is_linked = self.is_linked();
+        if is_linked {
+            try!(utils::rename_file("toolchain link", &self.path, link));
+        }
 
-                // No need to download
-                let local_installer = Path::new(installer);
+        let result = self.install(InstallMethod::Installers(&local_installers,
+                                                            &self.cfg.temp_cfg));
 
-                // Install from file
-                try!(self.install(InstallMethod::Installer(&local_installer, &self.cfg.temp_cfg)));
+        if is_linked {
+            if result.is_ok() {
+                try!(utils::remove_dir("toolchain link", link, ntfy!(&self.cfg.notify_handler)));
+            } else {
+                try!(utils::rename_file("toolchain link", link, &self.path));
             }
         }
 
-        Ok(())
+        try!(result);
+        self.write_source(&ToolchainSource::Installers {
+            locations: sources,
+            hashes: pinned.iter().map(|h| h.to_string()).collect(),
+            require_checksum: require_checksum,
+        })
     }
 
     pub fn install_from_dir(&self, src: &Path, link: bool) -> Result<()> {
         try!(self.ensure_custom());
 
+        let _lock = try!(self.lock());
+
+        let ref src = try!(utils::to_absolute(src));
         if link {
-            self.install(InstallMethod::Link(&try!(utils::to_absolute(src))))
+            try!(self.install(InstallMethod::Link(src)));
+            self.write_source(&ToolchainSource::Link(src.clone()))
         } else {
-            self.install(InstallMethod::Copy(src))
+            try!(self.install(InstallMethod::Copy(src)));
+            self.write_source(&ToolchainSource::Copy(src.clone()))
         }
     }
 
+    /// Installs a custom toolchain again from where it was last
+    /// installed from
+    pub fn update_from_source(&self) -> Result<()> {
+        match try!(self.source()) {
+            // The old toolchain is removed before copying or linking,
+            // so make sure there's something to replace it with
+            Some(ToolchainSource::Copy(ref path)) => {
+                try!(utils::assert_is_directory(path));
+                self.install_from_dir(path, false)
+            }
+            Some(ToolchainSource::Link(ref path)) => {
+                try!(utils::assert_is_directory(path));
+                self.install_from_dir(path, true)
+            }
+            // Downloads are verified as they were when first installed,
+            // so a pinned installer that has changed since fails
+            Some(ToolchainSource::Installers { ref locations, ref hashes, require_checksum }) => {
+                let installers: Vec<_> = locations.iter().map(|i| OsStr::new(i)).collect();
+                let hashes: Vec<_> = hashes.iter().map(|h| &**h).collect();
+                self.install_from_installers(&installers, &hashes, require_checksum)
+            }
+            None => Err(Error::NoToolchainSource(self.name.clone())),
+        }
+    }
+
+    fn source_file(&self) -> PathBuf {
+        self.cfg.sources_dir.join(&self.name)
+    }
+    /// Where a custom toolchain was installed from, if that was
+    /// recorded
+    pub fn source(&self) -> Result<Option<ToolchainSource>> {
+        let ref path = self.source_file();
+        if !utils::is_file(path) {
+            return Ok(None);
+        }
+        Ok(ToolchainSource::decode(&try!(utils::read_file("toolchain source", path))))
+    }
+    fn write_source(&self, source: &ToolchainSource) -> Result<()> {
+        try!(utils::ensure_dir_exists("toolchain sources", &self.cfg.sources_dir,
+                                      ntfy!(&self.cfg.notify_handler)));
+        Ok(try!(utils::write_file("toolchain source", &self.source_file(), &source.encode())))
+    }
+
     pub fn create_command<T: AsRef<OsStr>>(&self, binary: T) -> Result<Command> {
-        if !self.exists() {
+        try!(self.recover_if_unlocked());
+        if self.is_linked() {
+            try!(self.verify());
+        } else if !self.exists() {
             return Err(Error::ToolchainNotInstalled(self.name.to_owned()));
         }
 
+        self.record_use();
+
         let mut cmd = Command::new(binary);
         self.set_env(&mut cmd);
         Ok(cmd)
@@ -262,132 +709,349 @@ impl<'a> Toolchain<'a> {
         self.cfg.set_default(&self.name)
     }
     pub fn make_override(&self, path: &Path) -> Result<()> {
+        let _lock = try!(self.cfg.lock_metadata());
         Ok(try!(self.cfg.override_db.set(path,
                                          &self.name,
                                          &self.cfg.temp_cfg,
                                          self.cfg.notify_handler.as_ref())))
     }
 
-    pub fn list_components(&self) -> Result<Vec<Component>> {
+    // Opens the v2 installation of this toolchain, returning it with
+    // its installed manifest and target triple.
+    //
+    // FIXME: This toolchain handling is a mess. Just do it once
+    // when the toolchain is created.
+    fn installed_manifest(&self) -> Result<(Manifestation, Manifest, String)> {
         if !self.exists() {
             return Err(Error::ToolchainNotInstalled(self.name.to_owned()));
         }
 
-        // FIXME: This toolchain handling is a mess. Just do it once
-        // when the toolchain is created.
         let ref toolchain = self.name;
         let ref toolchain = try!(ToolchainDesc::from_str(toolchain));
         let trip = toolchain.target_triple();
         let prefix = InstallPrefix::from(self.path.to_owned());
-        let manifestation = try!(Manifestation::open(prefix, &trip));
+        let manifestation = try!(Manifestation::open(prefix, &trip,
+                                                     ntfy!(&self.cfg.notify_handler)));
 
         if let Some(manifest) = try!(manifestation.load_manifest()) {
-            // Return all optional components of the "rust" package for the
-            // toolchain's target triple.
-            let mut res = Vec::new();
-
-            let rust_pkg = manifest.packages.get("rust")
-                .expect("manifest should cantain a rust package");
-            let targ_pkg = rust_pkg.targets.get(&trip)
-                .expect("installed manifest should have a known target");
-
-            for extension in &targ_pkg.extensions {
-                res.push(extension.clone())
-            }
-
-            Ok(res)
+            Ok((manifestation, manifest, trip))
         } else {
             Err(Error::ComponentsUnsupported(self.name.to_string()))
         }
     }
 
-    pub fn add_component(&self, component: Component) -> Result<()> {
-        if !self.exists() {
-            return Err(Error::ToolchainNotInstalled(self.name.to_owned()));
+    /// Lists the components of the toolchain's "rust" package. If a
+    /// channel toolchain isn't installed then its current manifest is
+    /// downloaded to list what would be available.
+    pub fn list_components(&self) -> Result<Vec<ComponentStatus>> {
+        if !self.exists() && !self.is_custom() {
+            let ref toolchain = try!(ToolchainDesc::from_str(&self.name));
+            let trip = toolchain.target_triple();
+            let manifest = try!(dist::download_manifest(self.download_cfg(), &self.name));
+            return component_statuses(&manifest, &trip, &None);
         }
 
-        let ref toolchain = self.name;
-        let ref toolchain = try!(ToolchainDesc::from_str(toolchain));
-        let trip = toolchain.target_triple();
-        let prefix = InstallPrefix::from(self.path.to_owned());
-        let manifestation = try!(Manifestation::open(prefix, &trip));
+        let (manifestation, manifest, trip) = try!(self.installed_manifest());
+        let config = try!(manifestation.read_config());
 
-        if let Some(manifest) = try!(manifestation.load_manifest()) {
+        component_statuses(&manifest, &trip, &config)
+    }
+
+    /// Finds a component of the installed "rust" package from its
+    /// name, either `pkg` for the toolchain's own target or
+    /// `pkg-target`.
+    pub fn find_component(&self, name: &str) -> Result<Component> {
+        let (_, manifest, trip) = try!(self.installed_manifest());
+        Ok(try!(manifest.find_rust_component(name, &trip)))
+    }
 
-            // Validate the component name
-            let rust_pkg = manifest.packages.get("rust")
-                .expect("manifest should cantain a rust package");
-            let targ_pkg = rust_pkg.targets.get(&trip)
-                .expect("installed manifest should have a known target");
+    pub fn add_component(&self, component: Component) -> Result<()> {
+        self.add_components(vec![component])
+    }
+
+    /// Installs several optional components in a single transaction
+    pub fn add_components(&self, mut components: Vec<Component>) -> Result<()> {
+        let _lock = try!(self.lock());
+        let (manifestation, manifest, trip) = try!(self.installed_manifest());
+        let config = try!(manifestation.read_config());
+
+        components.sort();
+        components.dedup();
 
-            if targ_pkg.components.contains(&component) {
-                return Err(Error::AddingRequiredComponent(self.name.to_string(), component));
+        // Validate the component names
+        let rust_pkg = manifest.packages.get("rust")
+            .expect("manifest should cantain a rust package");
+        let targ_pkg = rust_pkg.targets.get(&trip)
+            .expect("installed manifest should have a known target");
+        let required = try!(profile_components(&config, &manifest, &trip));
+
+        for component in &components {
+            if required.contains(component) {
+                return Err(Error::AddingRequiredComponent(self.name.to_string(), component.clone()));
             }
 
-            if !targ_pkg.extensions.contains(&component) {
-                return Err(Error::UnknownComponent(self.name.to_string(), component));
+            if !targ_pkg.extensions.contains(component) && !targ_pkg.components.contains(component) {
+                return Err(Error::UnknownComponent(self.name.to_string(), component.clone()));
             }
+        }
 
-            let changes = Changes {
-                add_extensions: vec![component],
-                remove_extensions: vec![]
-            };
+        let changes = Changes {
+            add_extensions: components,
+            remove_extensions: vec![],
+            profile: None,
+            remove_local: vec![],
+        };
 
-            try!(manifestation.update(&manifest,
-                                      changes,
-                                      self.download_cfg().temp_cfg,
-                                      self.download_cfg().notify_handler.clone()));
+        try!(manifestation.update(&manifest,
+                                  changes,
+                                  self.download_cfg().temp_cfg,
+                                  self.download_cfg().notify_handler.clone()));
 
-            Ok(())
-        } else {
-            Err(Error::ComponentsUnsupported(self.name.to_string()))
-        }
+        Ok(())
+    }
+
+    /// Installs the components of a locally built installer into a
+    /// channel toolchain. Later updates leave them in place.
+    pub fn add_local_components(&self, installer: &Path) -> Result<Vec<String>> {
+        let _lock = try!(self.lock());
+        let (manifestation, _, _) = try!(self.installed_manifest());
+        Ok(try!(manifestation.install_local(installer,
+                                            &self.cfg.temp_cfg,
+                                            self.download_cfg().notify_handler)))
+    }
+
+    /// The names of components installed from local installers
+    pub fn list_local_components(&self) -> Result<Vec<String>> {
+        let (manifestation, _, _) = try!(self.installed_manifest());
+        let config = try!(manifestation.read_config());
+        Ok(config.map(|c| c.local_components).unwrap_or(Vec::new()))
     }
 
     pub fn remove_component(&self, component: Component) -> Result<()> {
+        self.remove_components(vec![component], vec![])
+    }
+
+    /// Uninstalls several optional components, and components from
+    /// local installers named in `local`, in a single transaction
+    pub fn remove_components(&self, mut components: Vec<Component>, local: Vec<String>)
+                             -> Result<()> {
+        let _lock = try!(self.lock());
+        let (manifestation, manifest, trip) = try!(self.installed_manifest());
+        let config = try!(manifestation.read_config());
+        let installed = config.as_ref().map(|c| c.components.clone()).unwrap_or(Vec::new());
+
+        components.sort();
+        components.dedup();
+
+        // Validate the component names
+        let rust_pkg = manifest.packages.get("rust")
+            .expect("manifest should cantain a rust package");
+        let targ_pkg = rust_pkg.targets.get(&trip)
+            .expect("installed manifest should have a known target");
+        let required = try!(profile_components(&config, &manifest, &trip));
+
+        for component in &components {
+            if required.contains(component) {
+                return Err(Error::RemovingRequiredToolchainComponent(self.name.to_string(),
+                                                                     component.clone()));
+            }
+
+            let is_offered = targ_pkg.extensions.contains(component)
+                || targ_pkg.components.contains(component);
+            if !is_offered || !installed.contains(component) {
+                return Err(Error::UnknownComponent(self.name.to_string(), component.clone()));
+            }
+        }
+
+        let changes = Changes {
+            add_extensions: vec![],
+            remove_extensions: components,
+            profile: None,
+            remove_local: local,
+        };
+
+        try!(manifestation.update(&manifest,
+                                  changes,
+                                  self.download_cfg().temp_cfg,
+                                  self.download_cfg().notify_handler.clone()));
+
+        Ok(())
+    }
+
+    /// Checks the files of every installed component against the
+    /// hashes recorded when it was installed
+    pub fn verify_components(&self) -> Result<Vec<(String, Verification)>> {
         if !self.exists() {
             return Err(Error::ToolchainNotInstalled(self.name.to_owned()));
         }
 
-        let ref toolchain = self.name;
-        let ref toolchain = try!(ToolchainDesc::from_str(toolchain));
-        let trip = toolchain.target_triple();
         let prefix = InstallPrefix::from(self.path.to_owned());
-        let manifestation = try!(Manifestation::open(prefix, &trip));
+        let components = try!(Components::open(prefix, ntfy!(&self.cfg.notify_handler)));
+        let mut result = Vec::new();
+        for component in try!(components.list()) {
+            let verification = try!(component.verify());
+            result.push((component.name().to_owned(), verification));
+        }
 
-        if let Some(manifest) = try!(manifestation.load_manifest()) {
+        Ok(result)
+    }
 
-            // Validate the component name
-            let rust_pkg = manifest.packages.get("rust")
-                .expect("manifest should cantain a rust package");
-            let targ_pkg = rust_pkg.targets.get(&trip)
-                .expect("installed manifest should have a known target");
+    /// Measures the disk space used by the toolchain and by each of
+    /// its components. This only reads the toolchain, so it doesn't
+    /// recover an interrupted transaction. Links, including broken
+    /// ones, are reported with no size.
+    pub fn disk_usage(&self, du: &mut utils::DiskUsage) -> Result<ToolchainUsage> {
+        let mut usage = ToolchainUsage {
+            size: utils::Size::default(),
+            linked: self.is_linked(),
+            components: Vec::new(),
+        };
+        if usage.linked {
+            return Ok(usage);
+        }
+        if !self.exists() {
+            return Err(Error::ToolchainNotInstalled(self.name.to_owned()));
+        }
 
-            if targ_pkg.components.contains(&component) {
-                return Err(Error::RemovingRequiredComponent(self.name.to_string(), component));
+        usage.size = try!(du.measure(&self.path));
+
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        let components = try!(Components::open(prefix.clone(), ntfy!(&self.cfg.notify_handler)));
+        for component in try!(components.list()) {
+            let mut bytes = 0;
+            for path in try!(component.installed_files()) {
+                if let Ok(meta) = fs::symlink_metadata(prefix.abs_path(path)) {
+                    bytes += meta.len();
+                }
             }
+            usage.components.push((component.name().to_owned(), bytes));
+        }
+
+        Ok(usage)
+    }
+
+    /// Describes the toolchain from its metadata. Metadata that can't
+    /// be read marks the toolchain as broken, with the error, so that
+    /// one bad toolchain doesn't hide the others.
+    pub fn info(&self, du: &mut utils::DiskUsage) -> ToolchainInfo {
+        let mut info = ToolchainInfo {
+            kind: if self.is_linked() {
+                ToolchainKind::Linked
+            } else if self.is_custom() {
+                ToolchainKind::Custom
+            } else {
+                ToolchainKind::Dist
+            },
+            broken: self.verify().is_err(),
+            source: None,
+            date: None,
+            version: None,
+            targets: Vec::new(),
+            size: None,
+            error: None,
+        };
+        if let Err(e) = self.read_info(&mut info, du) {
+            info.broken = true;
+            info.error = Some(e.to_string());
+        }
+
+        info
+    }
+    fn read_info(&self, info: &mut ToolchainInfo, du: &mut utils::DiskUsage) -> Result<()> {
+        info.source = try!(self.source());
+        if info.broken {
+            return Ok(());
+        }
 
-            let dist_config = try!(manifestation.read_config()).unwrap();
-            if !dist_config.components.contains(&component) {
-                return Err(Error::UnknownComponent(self.name.to_string(), component));
+        if info.kind == ToolchainKind::Dist {
+            // Toolchains installed from v1 manifests don't keep one
+            match self.installed_manifest() {
+                Ok((manifestation, manifest, _)) => {
+                    info.date = Some(manifest.date.clone());
+                    info.version = manifest.packages.get("rust").map(|p| p.version.clone());
+                    if let Some(config) = try!(manifestation.read_config()) {
+                        let mut targets: Vec<_> = config.components.into_iter()
+                                                        .map(|c| c.target)
+                                                        .collect();
+                        targets.sort();
+                        targets.dedup();
+                        info.targets = targets;
+                    }
+                }
+                Err(Error::ComponentsUnsupported(_)) => {}
+                Err(e) => return Err(e),
             }
+        } else if info.kind == ToolchainKind::Custom {
+            info.version = try!(self.installed_version());
+        }
+
+        if info.kind != ToolchainKind::Linked {
+            info.size = Some(try!(self.disk_usage(du)).size.bytes);
+        }
+
+        Ok(())
+    }
 
-            let changes = Changes {
-                add_extensions: vec![],
-                remove_extensions: vec![component]
+    /// Reads the version of `rustc` or `cargo` from the toolchain's
+    /// metadata without running it. Returns `None` if the metadata
+    /// doesn't record one, e.g. for linked toolchains.
+    pub fn tool_version(&self, tool: &str) -> Result<Option<String>> {
+        if !self.exists() {
+            return Err(Error::ToolchainNotInstalled(self.name.to_owned()));
+        }
+        if self.is_linked() {
+            return Ok(None);
+        }
+        if self.is_custom() {
+            return if tool == "rustc" {
+                self.installed_version()
+            } else {
+                Ok(None)
             };
+        }
 
-            try!(manifestation.update(&manifest,
-                                      changes,
-                                      self.download_cfg().temp_cfg,
-                                      self.download_cfg().notify_handler.clone()));
+        let pkg = if tool == "rustc" { "rust" } else { tool };
+        match self.installed_manifest() {
+            Ok((manifestation, manifest, _)) => {
+                // Tools other than rustc are optional components
+                if pkg != "rust" {
+                    let config = try!(manifestation.read_config());
+                    let installed = config.map(|c| c.components.iter().any(|c| c.pkg == pkg))
+                                          .unwrap_or(false);
+                    if !installed {
+                        return Ok(None);
+                    }
+                }
+                Ok(manifest.packages.get(pkg).map(|p| p.version.clone()))
+            }
+            // Toolchains installed from v1 manifests
+            Err(Error::ComponentsUnsupported(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 
-            Ok(())
+    // The version recorded when installing from custom installers
+    fn installed_version(&self) -> Result<Option<String>> {
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        let ref path = prefix.manifest_file(install::INSTALLED_VERSION_FILE);
+        if utils::is_file(path) {
+            let version = try!(utils::read_file("toolchain version", path));
+            Ok(Some(version.trim().to_owned()))
         } else {
-            Err(Error::ComponentsUnsupported(self.name.to_string()))
+            Ok(None)
         }
     }
 
+    /// Reinstalls the components that are missing or damaged, using
+    /// the installed manifest. Returns the repaired components.
+    pub fn repair(&self) -> Result<Vec<Component>> {
+        let _lock = try!(self.lock());
+        let (manifestation, manifest, _) = try!(self.installed_manifest());
+        Ok(try!(manifestation.repair(&manifest,
+                                     &self.cfg.temp_cfg,
+                                     self.download_cfg().notify_handler)))
+    }
+
     pub fn binary_file(&self, name: &str) -> PathBuf {
         let mut path = self.path.clone();
         path.push("bin");
@@ -395,3 +1059,39 @@ impl<'a> Toolchain<'a> {
         path
     }
 }
+
+// All components of the "rust" package for a toolchain's target
+// triple, according to its dist config
+fn component_statuses(manifest: &Manifest, trip: &str,
+                      config: &Option<Config>) -> Result<Vec<ComponentStatus>> {
+    let installed = config.as_ref().map(|c| c.components.clone()).unwrap_or(Vec::new());
+    let required = try!(profile_components(config, manifest, trip));
+
+    let rust_pkg = try!(manifest.get_package("rust"));
+    let targ_pkg = try!(rust_pkg.get_target(trip));
+
+    let mut res = Vec::new();
+    for component in targ_pkg.components.iter().chain(targ_pkg.extensions.iter()) {
+        let available = manifest.get_package(&component.pkg).ok()
+            .and_then(|p| p.get_target(&component.target).ok())
+            .map(|tp| tp.available) == Some(true);
+        res.push(ComponentStatus {
+            component: component.clone(),
+            required: required.contains(component),
+            installed: installed.contains(component),
+            available: available,
+        });
+    }
+
+    Ok(res)
+}
+
+// The components required by the profile a toolchain was installed
+// with
+fn profile_components(config: &Option<Config>, manifest: &Manifest,
+                      trip: &str) -> Result<Vec<Component>> {
+    let profile = config.as_ref()
+        .and_then(|c| c.profile)
+        .unwrap_or(Profile::Default);
+    Ok(try!(manifest.get_profile_components(profile, trip)))
+}
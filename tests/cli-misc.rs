@@ -4,11 +4,21 @@
 extern crate multirust_dist;
 extern crate multirust_utils;
 extern crate multirust_mock;
+extern crate flate2;
+extern crate tar;
+
+use std::env::consts::EXE_SUFFIX;
+use std::fs::{self, File};
+use std::iter::repeat;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use multirust_mock::clitools::{self, Config, Scenario,
                                expect_stdout_ok, expect_stderr_ok,
                                expect_ok, expect_err, run,
                                this_host_triple};
+use multirust_utils::{raw, utils};
+use multirust_mock::dist::calc_hash;
 
 pub fn setup(f: &Fn(&Config)) {
     clitools::setup(Scenario::SimpleV2, f);
@@ -149,16 +159,466 @@ fn install_toolchain_from_custom() {
     });
 }
 
+fn custom_installer(config: &Config) -> PathBuf {
+    config.distdir.path().join(format!("dist/rust-nightly-{}.tar.gz", this_host_triple()))
+}
+
 #[test]
-fn install_toolchain_from_custom_wrong_extension() {
+fn install_toolchain_from_custom_tar() {
     setup(&|config| {
-        let trip = this_host_triple();
-        let custom_installer = config.distdir.path().join(
-            format!("dist/rust-nightly-{}.msi", trip));
-        let custom_installer = custom_installer.to_string_lossy();
+        let ref tarball = config.customdir.path().join("rust-nightly.tar");
+        let installer = File::open(custom_installer(config)).unwrap();
+        let mut decoder = flate2::read::GzDecoder::new(installer).unwrap();
+        io::copy(&mut decoder, &mut File::create(tarball).unwrap()).unwrap();
+
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", &tarball.to_string_lossy()]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_dir() {
+    setup(&|config| {
+        let installer = File::open(custom_installer(config)).unwrap();
+        let decoder = flate2::read::GzDecoder::new(installer).unwrap();
+        tar::Archive::new(decoder).unpack(config.customdir.path()).unwrap();
+        let ref dir = config.customdir.path().join(
+            format!("rust-nightly-{}", this_host_triple()));
+
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", &dir.to_string_lossy()]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_dir_records_version() {
+    setup(&|config| {
+        let installer = File::open(custom_installer(config)).unwrap();
+        let decoder = flate2::read::GzDecoder::new(installer).unwrap();
+        tar::Archive::new(decoder).unpack(config.customdir.path()).unwrap();
+        let ref dir = config.customdir.path().join(
+            format!("rust-nightly-{}", this_host_triple()));
+        raw::write_file(&dir.join("version"), "1.3.0 (hash-v-1 2015-01-02)\n").unwrap();
+
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", &dir.to_string_lossy()]);
+        expect_stdout_ok(config, &["multirust", "show-default"],
+                         "rustc 1.3.0 (hash-v-1 2015-01-02)");
+        // Running cargo is the fallback
+        expect_stdout_ok(config, &["multirust", "show-default"],
+                         "1.3.0 (hash-n-2)");
+    });
+}
+
+#[test]
+fn show_default_runs_linked_rustc() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        expect_stdout_ok(config, &["multirust", "show-default"],
+                         "hash-c-1");
+    });
+}
+
+// Installers are recognized by their contents, not their extension
+#[test]
+fn install_toolchain_from_custom_unrecognized_installer() {
+    setup(&|config| {
+        let ref installer = config.customdir.path().join("rust-nightly.tar.gz");
+        raw::write_file(installer, "not an installer").unwrap();
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", &installer.to_string_lossy()],
+                   "is not a rust-installer directory or tarball");
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_xz() {
+    setup(&|config| {
+        let ref installer = config.customdir.path().join("rust-nightly.tar.xz");
+        File::create(installer).unwrap().write_all(b"\xfd7zXZ\x00").unwrap();
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", &installer.to_string_lossy()],
+                   "is compressed with xz, which is not supported");
+    });
+}
+
+fn old_custom_installer(config: &Config) -> PathBuf {
+    config.distdir.path().join(format!("dist/2015-01-01/rust-nightly-{}.tar.gz", this_host_triple()))
+}
+
+#[test]
+fn install_toolchain_from_missing_custom_installer_keeps_old_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", &custom_installer(config).to_string_lossy()]);
+        let missing = format!("file://{}", config.customdir.path().join("missing.tar.gz").display());
+        expect_err(config, &["multirust", "update", "custom",
+                             "--installer", &old_custom_installer(config).to_string_lossy(),
+                             &missing],
+                   "could not download file");
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+// The second installer conflicts with the first, which fails after
+// the old toolchain has been removed in the transaction
+#[test]
+fn install_toolchain_from_conflicting_custom_installers_keeps_old_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", &custom_installer(config).to_string_lossy()]);
+        let ref old = old_custom_installer(config).to_string_lossy().into_owned();
+        expect_err(config, &["multirust", "update", "custom",
+                             "--installer", old, old],
+                   "rolling back changes");
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+        let ref journal = config.homedir.path().join("toolchains/custom/.multirust-journal");
+        assert!(!journal.exists());
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_replaces_link() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        expect_ok(config, &["multirust", "update", "custom",
+                            "--installer", &custom_installer(config).to_string_lossy()]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+        // The linked directory is left alone
+        assert!(path.join("bin").exists());
+        assert!(!path.join("lib/rustlib/components").exists());
+    });
+}
+
+// A process killed while installing through a link leaves the link
+// moved aside, and the next command to lock the toolchain puts it back
+#[test]
+fn interrupted_install_from_custom_restores_link() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        let ref toolchain = config.homedir.path().join("toolchains/custom");
+        let ref moved = config.homedir.path().join("toolchains/.custom.link");
+        fs::rename(toolchain, moved).unwrap();
+
+        expect_stderr_ok(config, &["multirust", "update", "custom"],
+                         "restoring the link of toolchain 'custom'");
+        assert!(!moved.exists());
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-c-1");
+    });
+}
+
+#[test]
+fn install_toolchain_from_bad_custom_installer_keeps_link() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        let ref installer = config.customdir.path().join("rust-nightly.tar.gz");
+        raw::write_file(installer, "not an installer").unwrap();
+        expect_err(config, &["multirust", "update", "custom",
+                             "--installer", &installer.to_string_lossy()],
+                   "is not a rust-installer directory or tarball");
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-c-1");
+    });
+}
+
+// Copies the custom installer to a URL of its own, with no checksum
+// published next to it
+fn custom_installer_url(config: &Config) -> String {
+    let ref installer = config.customdir.path().join("rust-nightly.tar.gz");
+    fs::copy(custom_installer(config), installer).unwrap();
+    format!("file://{}", installer.display())
+}
+
+#[test]
+fn install_toolchain_from_custom_url_checks_published_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        let ref sidecar = config.customdir.path().join("rust-nightly.tar.gz.sha256");
+        raw::write_file(sidecar, &format!("{} *rust-nightly.tar.gz\n", repeat("0").take(64).collect::<String>())).unwrap();
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", url],
+                   "checksum failed");
+
+        let hash = calc_hash(&custom_installer(config));
+        raw::write_file(sidecar, &format!("{} *rust-nightly.tar.gz\n", hash)).unwrap();
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", url, "--require-checksum"]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_url_without_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        expect_stderr_ok(config, &["multirust", "default", "custom",
+                                   "--installer", url],
+                         "no checksum is published for");
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_url_requiring_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", url, "--require-checksum"],
+                   "no checksum is published for");
+        let out = run(config, "rustc", &["--version"], &[]);
+        assert!(!out.ok);
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_url_with_explicit_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        let ref zeros = repeat("0").take(64).collect::<String>();
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", url, "--sha256", zeros],
+                   "checksum failed");
+
+        let ref hash = calc_hash(&custom_installer(config)).to_uppercase();
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", url, "--sha256", hash, "--require-checksum"]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_url_with_invalid_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", url, "--sha256", "abc"],
+                   "'abc' is not a SHA-256 checksum");
+    });
+}
+
+// A short or garbled published checksum is an error, not a panic
+#[test]
+fn install_toolchain_from_custom_url_with_invalid_published_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        let ref sidecar = config.customdir.path().join("rust-nightly.tar.gz.sha256");
+        raw::write_file(sidecar, "abc\n").unwrap();
+        expect_err(config, &["multirust", "default", "custom",
+                             "--installer", url],
+                   "rust-nightly.tar.gz.sha256' does not contain a SHA-256 checksum");
+        let out = run(config, "rustc", &["--version"], &[]);
+        assert!(!out.ok);
+    });
+}
+
+#[test]
+fn install_toolchain_from_custom_url_wrong_checksum_count() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        let ref local = custom_installer(config).to_string_lossy().into_owned();
+        let ref hash = calc_hash(&custom_installer(config));
         expect_err(config, &["multirust", "default", "custom",
-                             "--installer", &custom_installer],
-                   "invalid extension for installer: 'msi'");
+                             "--installer", url, local,
+                             "--sha256", hash, "--sha256", hash],
+                   "expected a checksum for each of 1 installer URLs, found 2");
+    });
+}
+
+// Copies one of the custom toolchains to a directory that can be
+// changed by the test
+fn copy_custom_toolchain(config: &Config, name: &str, dest: &Path) {
+    if dest.exists() {
+        fs::remove_dir_all(dest).unwrap();
+    }
+    utils::copy_dir(&config.customdir.path().join(name), dest,
+                    multirust_utils::NotifyHandler::none()).unwrap();
+}
+
+#[test]
+fn update_copied_toolchain_copies_source_again() {
+    setup(&|config| {
+        let ref src = config.customdir.path().join("source");
+        copy_custom_toolchain(config, "custom-1", src);
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--copy-local", &src.to_string_lossy()]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-c-1");
+        copy_custom_toolchain(config, "custom-2", src);
+        expect_ok(config, &["multirust", "update", "custom"]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-c-2");
+    });
+}
+
+#[test]
+fn update_installed_toolchain_installs_again() {
+    setup(&|config| {
+        let ref installer = config.customdir.path().join("rust-nightly.tar.gz");
+        fs::copy(custom_installer(config), installer).unwrap();
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", &installer.to_string_lossy()]);
+        fs::copy(old_custom_installer(config), installer).unwrap();
+        expect_ok(config, &["multirust", "update", "custom"]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-1");
+    });
+}
+
+#[test]
+fn update_all_updates_custom_toolchains() {
+    setup(&|config| {
+        let ref src = config.customdir.path().join("source");
+        copy_custom_toolchain(config, "custom-1", src);
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--copy-local", &src.to_string_lossy()]);
+        copy_custom_toolchain(config, "custom-2", src);
+        expect_ok(config, &["multirust", "update"]);
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-c-2");
+    });
+}
+
+#[test]
+fn update_copied_toolchain_with_missing_source() {
+    setup(&|config| {
+        let ref src = config.customdir.path().join("source");
+        copy_custom_toolchain(config, "custom-1", src);
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--copy-local", &src.to_string_lossy()]);
+        fs::remove_dir_all(src).unwrap();
+        expect_err(config, &["multirust", "update", "custom"],
+                   "not a directory");
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-c-1");
+    });
+}
+
+// The installer was pinned to a hash when first installed, so it is
+// still checked against it
+#[test]
+fn update_custom_toolchain_keeps_pinned_checksum() {
+    setup(&|config| {
+        let ref url = custom_installer_url(config);
+        let ref hash = calc_hash(&custom_installer(config));
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--installer", url, "--sha256", hash, "--require-checksum"]);
+        expect_ok(config, &["multirust", "update", "custom"]);
+
+        fs::copy(old_custom_installer(config),
+                 config.customdir.path().join("rust-nightly.tar.gz")).unwrap();
+        expect_err(config, &["multirust", "update", "custom"],
+                   "checksum failed");
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn update_custom_toolchain_without_source() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        fs::remove_file(config.homedir.path().join("sources/custom")).unwrap();
+        expect_err(config, &["multirust", "update", "custom"],
+                   "toolchain 'custom' has no recorded source to update from");
+    });
+}
+
+#[test]
+fn list_toolchains_verbose_shows_sources() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "update", "linked",
+                            "--link-local", &path.to_string_lossy()]);
+        expect_ok(config, &["multirust", "update", "copied",
+                            "--copy-local", &path.to_string_lossy()]);
+        let ref installer = custom_installer(config).to_string_lossy().into_owned();
+        expect_ok(config, &["multirust", "update", "installed",
+                            "--installer", installer]);
+        expect_stdout_ok(config, &["multirust", "list-toolchains", "-v"],
+                         &format!("source: copied from '{}'", path.display()));
+        expect_stdout_ok(config, &["multirust", "list-toolchains", "-v"],
+                         &format!("source: linked to '{}'", path.display()));
+        expect_stdout_ok(config, &["multirust", "list-toolchains", "-v"],
+                         &format!("source: installed from '{}'", installer));
+    });
+}
+
+#[test]
+fn remove_toolchain_forgets_source() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "update", "custom",
+                            "--copy-local", &path.to_string_lossy()]);
+        assert!(config.homedir.path().join("sources/custom").exists());
+        expect_ok(config, &["multirust", "remove-toolchain", "custom"]);
+        assert!(!config.homedir.path().join("sources/custom").exists());
+    });
+}
+
+#[test]
+fn linked_toolchain_with_missing_target_is_broken() {
+    setup(&|config| {
+        let ref src = config.customdir.path().join("source");
+        copy_custom_toolchain(config, "custom-1", src);
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &src.to_string_lossy()]);
+        fs::remove_dir_all(src).unwrap();
+        expect_err(config, &["rustc", "--version"],
+                   &format!("toolchain 'custom' is broken: '{}' is missing", src.display()));
+        expect_stdout_ok(config, &["multirust", "list-toolchains"],
+                         "custom (broken)");
+        expect_ok(config, &["multirust", "remove-toolchain", "custom"]);
+        assert!(!config.homedir.path().join("toolchains/custom").exists());
+        assert!(fs::symlink_metadata(config.homedir.path().join("toolchains/custom")).is_err());
+    });
+}
+
+#[test]
+fn linked_toolchain_without_rustc_is_broken() {
+    setup(&|config| {
+        let ref src = config.customdir.path().join("source");
+        copy_custom_toolchain(config, "custom-1", src);
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &src.to_string_lossy()]);
+        let ref rustc = src.join("bin").join(format!("rustc{}", EXE_SUFFIX));
+        fs::remove_file(rustc).unwrap();
+        expect_err(config, &["cargo", "--version"],
+                   &format!("toolchain 'custom' is broken: '{}' is missing", rustc.display()));
+        expect_stdout_ok(config, &["multirust", "list-toolchains"],
+                         "custom (broken)");
+    });
+}
+
+#[test]
+fn list_toolchains_doesnt_flag_working_link() {
+    setup(&|config| {
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        let out = run(config, "multirust", &["list-toolchains"], &[]);
+        assert!(out.ok);
+        assert!(!out.stdout.contains("broken"));
     });
 }
 
@@ -5,14 +5,20 @@ extern crate multirust_dist;
 extern crate multirust_utils;
 extern crate multirust_mock;
 extern crate tempdir;
+extern crate time;
 
 use std::fs;
+use std::env::consts::EXE_SUFFIX;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use tempdir::TempDir;
 use multirust_mock::clitools::{self, Config, Scenario,
                                this_host_triple,
                                expect_ok, expect_stdout_ok, expect_err,
                                expect_stderr_ok, set_current_dist_date,
-                               change_dir, run};
+                               add_dist_profiles, change_dir, run, cmd};
+use multirust_utils::{utils, raw};
 
 pub fn setup(f: &Fn(&Config)) {
     clitools::setup(Scenario::SimpleV2, f);
@@ -43,6 +49,19 @@ fn default_toolchain() {
     });
 }
 
+// The mock binaries don't print their names, so these versions can
+// only have come from the installed manifest
+#[test]
+fn show_default_reads_versions_from_manifest() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stdout_ok(config, &["multirust", "show-default"],
+                         "rustc 1.3.0 (hash-n-2)");
+        expect_stdout_ok(config, &["multirust", "show-default"],
+                         "cargo 1.3.0 (hash-n-2)");
+    });
+}
+
 #[test]
 fn expected_bins_exist() {
     setup(&|config| {
@@ -106,6 +125,83 @@ fn update_channel() {
     });
 }
 
+// As left by an update killed between moving the old installation
+// aside and moving the new one into place
+#[test]
+fn update_restores_toolchain_moved_aside_by_crashed_update() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref path = config.homedir.path().join("toolchains/nightly");
+        let ref old_path = config.homedir.path().join("toolchains/.nightly.old");
+        fs::rename(path, old_path).unwrap();
+
+        set_current_dist_date(config, "2015-01-02");
+        expect_stderr_ok(config, &["multirust", "update", "nightly"],
+                         "which an interrupted update had moved aside");
+        assert!(!old_path.exists());
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn cancelled_update_keeps_toolchain() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        let out = run(config, "multirust", &["update", "nightly"],
+                      &[("MULTIRUST_TEST_CANCEL_AFTER_DOWNLOADS", "3")]);
+        assert!(!out.ok);
+        assert!(out.stderr.contains("cancelled, rolled back any changes"));
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-1");
+        expect_ok(config, &["multirust", "verify", "nightly"]);
+    });
+}
+
+#[test]
+fn cancelled_update_all_stops() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        let out = run(config, "multirust", &["update"],
+                      &[("MULTIRUST_TEST_CANCEL_AFTER_DOWNLOADS", "3")]);
+        assert!(!out.ok);
+        assert!(out.stderr.contains("cancelled, rolled back any changes"));
+        assert!(!out.stdout.contains("FAILED"));
+        expect_stdout_ok(config, &["rustc", "--version"],
+                         "hash-n-1");
+    });
+}
+
+#[test]
+fn update_channel_shows_summary() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        let out = run(config, "multirust", &["update", "nightly"], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains("nightly updated (2015-01-01 → 2015-01-02, "));
+        assert!(out.stdout.contains("    rustc 1.2.0 (hash-n-1) → 1.3.0 (hash-n-2)"));
+        assert!(out.stdout.contains("    cargo 1.2.0 (hash-n-1) → 1.3.0 (hash-n-2)"));
+    });
+}
+
+#[test]
+fn update_channel_summary_lists_added_components() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref component = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        let out = run(config, "multirust", &["update", "nightly", "--with", component], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains(&format!("    added {}", component)));
+    });
+}
+
 #[test]
 fn list_toolchains() {
     clitools::setup(Scenario::ArchivesV2, &|config| {
@@ -507,8 +603,31 @@ fn upgrade_v2_to_v1() {
 #[test]
 fn list_targets_no_toolchain() {
     setup(&|config| {
-        expect_err(config, &["multirust", "list-targets", "nightly"],
-                   "toolchain 'nightly' is not installed");
+        expect_stdout_ok(config, &["multirust", "list-targets", "nightly"],
+                         clitools::CROSS_ARCH1);
+        assert!(!config.homedir.path().join("toolchains/nightly").exists());
+    });
+}
+
+#[test]
+fn list_targets_bogus_channel() {
+    setup(&|config| {
+        expect_err(config, &["multirust", "list-targets", "nightly-2016-01-01"],
+                   "no release found for 'nightly-2016-01-01'");
+    });
+}
+
+#[test]
+fn list_targets_marks_installed() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "add-target", "nightly", clitools::CROSS_ARCH1]);
+        expect_stdout_ok(config, &["multirust", "list-targets", "nightly"],
+                         &format!("{} (installed)", clitools::CROSS_ARCH1));
+        expect_stdout_ok(config, &["multirust", "list-targets", "nightly"],
+                         &format!("{} (installed)", this_host_triple()));
+        expect_stdout_ok(config, &["multirust", "list-targets", "nightly"],
+                         &format!("{}\n", clitools::CROSS_ARCH2));
     });
 }
 
@@ -533,6 +652,159 @@ fn list_targets_custom_toolchain() {
     });
 }
 
+#[test]
+fn update_with_minimal_profile() {
+    setup(&|config| {
+        add_dist_profiles(config, &[("minimal", &["rustc", "rust-std"]),
+                                    ("default", &["rustc", "rust-std", "rust-docs"])]);
+        expect_ok(config, &["multirust", "update", "nightly", "--profile", "minimal"]);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        let path = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        assert!(!path.exists());
+    });
+}
+
+#[test]
+fn update_keeps_profile() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        let profiles: &[(&str, &[&str])] = &[("minimal", &["rustc", "rust-std"]),
+                                             ("default", &["rustc", "rust-std", "rust-docs"])];
+        set_current_dist_date(config, "2015-01-01");
+        add_dist_profiles(config, profiles);
+        expect_ok(config, &["multirust", "update", "nightly", "--profile", "minimal"]);
+        set_current_dist_date(config, "2015-01-02");
+        add_dist_profiles(config, profiles);
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        let path = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        assert!(!path.exists());
+    });
+}
+
+#[test]
+fn update_after_profile_dropped_from_manifest() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        add_dist_profiles(config, &[("minimal", &["rustc", "rust-std"]),
+                                    ("default", &["rustc", "rust-std", "rust-docs"])]);
+        expect_ok(config, &["multirust", "update", "nightly", "--profile", "minimal"]);
+        set_current_dist_date(config, "2015-01-02");
+        add_dist_profiles(config, &[("default", &["rustc", "rust-std", "rust-docs"])]);
+        expect_ok(config, &["multirust", "update", "nightly", "--profile", "default"]);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        let path = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        assert!(path.exists());
+    });
+}
+
+#[test]
+fn update_bogus_profile() {
+    setup(&|config| {
+        expect_err(config, &["multirust", "update", "nightly", "--profile", "bogus"],
+                   "unknown profile: 'bogus'");
+    });
+}
+
+#[test]
+fn update_with_extension() {
+    setup(&|config| {
+        let ref component = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        expect_ok(config, &["multirust", "update", "nightly", "--with", component]);
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH1);
+        assert!(config.homedir.path().join(path).exists());
+    });
+}
+
+#[test]
+fn default_with_multiple_extensions() {
+    setup(&|config| {
+        let ref component1 = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        let ref component2 = format!("rust-std-{}", clitools::CROSS_ARCH2);
+        expect_ok(config, &["multirust", "default", "nightly",
+                            "--with", component1, "--with", component2]);
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH1);
+        assert!(config.homedir.path().join(path).exists());
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH2);
+        assert!(config.homedir.path().join(path).exists());
+    });
+}
+
+// An installed toolchain keeps its release, but still gets the
+// requested components
+#[test]
+fn default_and_override_change_components_of_installed_toolchain() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        let ref component = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        let ref path = config.homedir.path()
+                             .join(format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                                           clitools::CROSS_ARCH1));
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+
+        expect_ok(config, &["multirust", "default", "nightly", "--with", component]);
+        assert!(path.exists());
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-1");
+
+        let tempdir = TempDir::new("multirust").unwrap();
+        change_dir(tempdir.path(), &|| {
+            expect_ok(config, &["multirust", "override", "nightly", "--without", component]);
+        });
+        assert!(!path.exists());
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-1");
+    });
+}
+
+#[test]
+fn default_changes_profile_of_installed_toolchain() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        let ref docs = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        set_current_dist_date(config, "2015-01-01");
+        add_dist_profiles(config, &[("minimal", &["rustc", "rust-std"]),
+                                    ("default", &["rustc", "rust-std", "rust-docs"])]);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        assert!(docs.exists());
+        set_current_dist_date(config, "2015-01-02");
+
+        expect_ok(config, &["multirust", "default", "nightly", "--profile", "minimal"]);
+        assert!(!docs.exists());
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-1");
+    });
+}
+
+#[test]
+fn update_without_extension() {
+    setup(&|config| {
+        let ref component = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        expect_ok(config, &["multirust", "update", "nightly", "--with", component]);
+        expect_ok(config, &["multirust", "update", "nightly", "--without", component]);
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH1);
+        assert!(!config.homedir.path().join(path).exists());
+    });
+}
+
+#[test]
+fn update_with_bogus_component() {
+    setup(&|config| {
+        expect_err(config, &["multirust", "update", "nightly", "--with", "rust-bogus"],
+                   "toolchain does not contain component 'rust-bogus'");
+    });
+}
+
+#[test]
+fn update_without_required_component() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_err(config, &["multirust", "update", "nightly", "--without", "rustc"],
+                   "is required by the 'default' profile and cannot be removed");
+    });
+}
+
 #[test]
 fn list_targets() {
     setup(&|config| {
@@ -555,6 +827,63 @@ fn add_target() {
     });
 }
 
+#[test]
+fn add_several_targets() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "add-target", "nightly",
+                            clitools::CROSS_ARCH1, clitools::CROSS_ARCH2]);
+        for target in &[clitools::CROSS_ARCH1, clitools::CROSS_ARCH2] {
+            let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib", target);
+            assert!(config.homedir.path().join(path).exists());
+        }
+    });
+}
+
+#[test]
+fn add_all_targets() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "add-target", "nightly", "--all"]);
+        for target in &[clitools::CROSS_ARCH1, clitools::CROSS_ARCH2] {
+            let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib", target);
+            assert!(config.homedir.path().join(path).exists());
+        }
+    });
+}
+
+#[test]
+fn add_several_targets_with_one_unavailable() {
+    setup(&|config| {
+        make_component_unavailable(config, "rust-std", clitools::CROSS_ARCH2);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_err(config, &["multirust", "add-target", "nightly",
+                             clitools::CROSS_ARCH1, clitools::CROSS_ARCH2],
+                   &format!("component 'rust-std' for '{}' is unavailable for download",
+                            clitools::CROSS_ARCH2));
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH1);
+        assert!(!config.homedir.path().join(path).exists());
+    });
+}
+
+#[test]
+fn add_all_targets_with_one_unavailable() {
+    setup(&|config| {
+        make_component_unavailable(config, "rust-std", clitools::CROSS_ARCH2);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stderr_ok(config, &["multirust", "add-target", "nightly", "--all"],
+                         &format!("skipping target '{}', which is unavailable for download",
+                                  clitools::CROSS_ARCH2));
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH1);
+        assert!(config.homedir.path().join(path).exists());
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH2);
+        assert!(!config.homedir.path().join(path).exists());
+    });
+}
+
 #[test]
 fn add_target_no_toolchain() {
     setup(&|config| {
@@ -628,6 +957,34 @@ fn remove_target() {
     });
 }
 
+#[test]
+fn remove_several_targets() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "add-target", "nightly", "--all"]);
+        expect_ok(config, &["multirust", "remove-target", "nightly",
+                            clitools::CROSS_ARCH1, clitools::CROSS_ARCH2]);
+        for target in &[clitools::CROSS_ARCH1, clitools::CROSS_ARCH2] {
+            let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib", target);
+            assert!(!config.homedir.path().join(path).exists());
+        }
+    });
+}
+
+#[test]
+fn remove_all_targets() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "add-target", "nightly", "--all"]);
+        expect_ok(config, &["multirust", "remove-target", "nightly", "--all"]);
+        for target in &[clitools::CROSS_ARCH1, clitools::CROSS_ARCH2] {
+            let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib", target);
+            assert!(!config.homedir.path().join(path).exists());
+        }
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+    });
+}
+
 #[test]
 fn remove_target_not_installed() {
     setup(&|config| {
@@ -728,3 +1085,764 @@ fn update_unavailable_std() {
                    &format!("component 'rust-std' for '{}' is unavailable for download", trip));
     });
 }
+
+#[test]
+fn component_without_subcommand() {
+    setup(&|config| {
+        let out = run(config, "multirust", &["component"], &[]);
+        assert!(!out.ok);
+    });
+}
+
+#[test]
+fn component_list() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stdout_ok(config, &["multirust", "component", "list", "nightly"],
+                         &format!("rustc-{} (required)", this_host_triple()));
+        expect_stdout_ok(config, &["multirust", "component", "list", "nightly"],
+                         &format!("rust-std-{}\n", clitools::CROSS_ARCH1));
+    });
+}
+
+#[test]
+fn component_add_and_remove_several() {
+    setup(&|config| {
+        let ref component1 = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        let ref component2 = format!("rust-std-{}", clitools::CROSS_ARCH2);
+        let path1 = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                            clitools::CROSS_ARCH1);
+        let path2 = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                            clitools::CROSS_ARCH2);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", component1, component2]);
+        assert!(config.homedir.path().join(&path1).exists());
+        assert!(config.homedir.path().join(&path2).exists());
+        expect_stdout_ok(config, &["multirust", "component", "list", "nightly"],
+                         &format!("{} (installed)", component1));
+        expect_ok(config, &["multirust", "component", "remove", "nightly", component1, component2]);
+        assert!(!config.homedir.path().join(&path1).exists());
+        assert!(!config.homedir.path().join(&path2).exists());
+    });
+}
+
+#[test]
+fn component_add_left_out_by_profile() {
+    setup(&|config| {
+        add_dist_profiles(config, &[("minimal", &["rustc", "rust-std"]),
+                                    ("default", &["rustc", "rust-std", "rust-docs"])]);
+        expect_ok(config, &["multirust", "update", "nightly", "--profile", "minimal"]);
+        let path = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        assert!(!path.exists());
+        expect_ok(config, &["multirust", "component", "add", "nightly", "rust-docs"]);
+        assert!(path.exists());
+    });
+}
+
+#[test]
+fn component_add_bogus() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_err(config, &["multirust", "component", "add", "nightly", "rust-bogus"],
+                   "toolchain does not contain component 'rust-bogus'");
+    });
+}
+
+#[test]
+fn component_remove_required() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_err(config, &["multirust", "component", "remove", "nightly", "rustc"],
+                   "is required for toolchain 'nightly' and cannot be removed");
+    });
+}
+
+fn local_installer(config: &Config, name: &str) -> String {
+    clitools::create_local_installer(config, name).to_string_lossy().to_string()
+}
+
+#[test]
+fn component_add_local_installer() {
+    setup(&|config| {
+        let ref installer = local_installer(config, "rust-local");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", "--installer", installer]);
+        let path = "toolchains/nightly/lib/rust-local/rust-local.rlib";
+        assert!(config.homedir.path().join(path).exists());
+        expect_stdout_ok(config, &["multirust", "component", "list", "nightly"],
+                         "rust-local (local)");
+    });
+}
+
+#[test]
+fn component_add_local_installer_dir() {
+    setup(&|config| {
+        let installer = clitools::create_local_installer_dir(config, "rust-local");
+        let ref installer = installer.to_string_lossy().to_string();
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", "--installer", installer]);
+        let path = "toolchains/nightly/lib/rust-local/rust-local.rlib";
+        assert!(config.homedir.path().join(path).exists());
+    });
+}
+
+// Components of the release can't be replaced by local ones, even
+// ones that aren't installed
+#[test]
+fn component_add_local_installer_of_release_component() {
+    setup(&|config| {
+        let path = config.distdir.path()
+                         .join(format!("dist/2015-01-02/rust-std-nightly-{}.tar.gz",
+                                       clitools::CROSS_ARCH1));
+        let ref installer = path.to_string_lossy().to_string();
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_err(config, &["multirust", "component", "add", "nightly", "--installer", installer],
+                   &format!("component 'rust-std-{}' is part of the installed release",
+                            clitools::CROSS_ARCH1));
+        let path = format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                           clitools::CROSS_ARCH1);
+        assert!(!config.homedir.path().join(path).exists());
+    });
+}
+
+#[test]
+fn local_component_kept_by_update() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        let ref installer = local_installer(config, "rust-local");
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", "--installer", installer]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stderr_ok(config, &["multirust", "update", "nightly"],
+                         "keeping locally installed component 'rust-local'");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        let path = "toolchains/nightly/lib/rust-local/rust-local.rlib";
+        assert!(config.homedir.path().join(path).exists());
+    });
+}
+
+#[test]
+fn component_remove_local() {
+    setup(&|config| {
+        let ref installer = local_installer(config, "rust-local");
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", "--installer", installer]);
+        expect_ok(config, &["multirust", "component", "remove", "nightly", "rust-local"]);
+        let path = "toolchains/nightly/lib/rust-local/rust-local.rlib";
+        assert!(!config.homedir.path().join(path).exists());
+        let out = run(config, "multirust", &["component", "list", "nightly"], &[]);
+        assert!(!out.stdout.contains("rust-local"));
+    });
+}
+
+// Local components are removed in the same transaction as the others,
+// so a failure partway through keeps them too
+#[test]
+fn component_remove_local_with_others_is_one_transaction() {
+    setup(&|config| {
+        let ref installer = local_installer(config, "rust-local");
+        let ref std = format!("rust-std-{}", clitools::CROSS_ARCH1);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", std]);
+        expect_ok(config, &["multirust", "component", "add", "nightly", "--installer", installer]);
+        let local_path = config.homedir.path()
+                               .join("toolchains/nightly/lib/rust-local/rust-local.rlib");
+        let std_path = config.homedir.path()
+                             .join(format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib",
+                                           clitools::CROSS_ARCH1));
+
+        // The local component is removed first, and then removing
+        // rust-std fails on its missing file
+        fs::remove_file(&std_path).unwrap();
+        expect_err(config, &["multirust", "component", "remove", "nightly", "rust-local", std],
+                   "file does not exist");
+        assert!(local_path.exists());
+        expect_stdout_ok(config, &["multirust", "component", "list", "nightly"],
+                         "rust-local (local)");
+        expect_stdout_ok(config, &["multirust", "component", "list", "nightly"],
+                         &format!("{} (installed)", std));
+    });
+}
+
+#[test]
+fn verify_intact_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stdout_ok(config, &["multirust", "verify", "nightly"],
+                         &format!("rustc-{}: ok", this_host_triple()));
+    });
+}
+
+#[test]
+fn verify_damaged_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref docs = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        fs::remove_file(docs).unwrap();
+        let out = run(config, "multirust", &["verify", "nightly"], &[]);
+        assert!(!out.ok);
+        assert!(out.stdout.contains(&format!("rust-docs-{}: damaged", this_host_triple())));
+        assert!(out.stdout.contains("missing: share/doc/rust/html/index.html"));
+        expect_err(config, &["multirust", "verify", "nightly"],
+                   "toolchain 'nightly' has damaged components");
+    });
+}
+
+#[test]
+fn verify_toolchain_not_installed() {
+    setup(&|config| {
+        expect_err(config, &["multirust", "verify", "nightly"],
+                   "toolchain 'nightly' is not installed");
+    });
+}
+
+#[test]
+fn repair_damaged_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref rustc = config.homedir.path().join(format!("toolchains/nightly/bin/rustc{}",
+                                                           EXE_SUFFIX));
+        let ref docs = config.homedir.path().join("toolchains/nightly/share/doc/rust/html/index.html");
+        fs::remove_file(rustc).unwrap();
+        fs::remove_file(docs).unwrap();
+        expect_stderr_ok(config, &["multirust", "repair", "nightly"],
+                         &format!("repaired component 'rustc-{}'", this_host_triple()));
+        assert!(docs.exists());
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        expect_ok(config, &["multirust", "verify", "nightly"]);
+    });
+}
+
+#[test]
+fn repair_intact_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_stderr_ok(config, &["multirust", "repair", "nightly"],
+                         "toolchain 'nightly' has no damaged components");
+    });
+}
+
+#[test]
+fn repair_reinstalls_unlisted_component() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref components = config.homedir.path().join("toolchains/nightly/lib/rustlib/components");
+        let ref docs_name = format!("rust-docs-{}", this_host_triple());
+        let content = multirust_utils::raw::read_file(components).unwrap();
+        let content: String = content.lines()
+                                     .filter(|l| l != docs_name)
+                                     .map(|l| format!("{}\n", l))
+                                     .collect();
+        multirust_utils::raw::write_file(components, &content).unwrap();
+        expect_stderr_ok(config, &["multirust", "repair", "nightly"],
+                         &format!("repaired component '{}'", docs_name));
+        expect_ok(config, &["multirust", "verify", "nightly"]);
+    });
+}
+
+#[test]
+fn repair_without_config_keeps_added_targets() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "add-target", "nightly", clitools::CROSS_ARCH1]);
+        let ref std = config.homedir.path().join(
+            format!("toolchains/nightly/lib/rustlib/{}/lib/libstd.rlib", clitools::CROSS_ARCH1));
+        let ref rustc = config.homedir.path().join(format!("toolchains/nightly/bin/rustc{}",
+                                                           EXE_SUFFIX));
+        fs::remove_file(config.homedir.path().join(
+            "toolchains/nightly/lib/rustlib/multirust-config.toml")).unwrap();
+        fs::remove_file(rustc).unwrap();
+
+        expect_stderr_ok(config, &["multirust", "repair", "nightly"],
+                         &format!("repaired component 'rustc-{}'", this_host_triple()));
+        assert!(std.exists());
+        assert!(rustc.exists());
+        expect_stdout_ok(config, &["multirust", "list-targets", "nightly"],
+                         &format!("{} (installed)", clitools::CROSS_ARCH1));
+    });
+}
+
+#[test]
+fn repair_without_config_with_profiles_lacking_complete() {
+    setup(&|config| {
+        add_dist_profiles(config, &[("minimal", &["rustc", "rust-std"]),
+                                    ("default", &["rustc", "rust-std", "rust-docs"])]);
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref rustc = config.homedir.path().join(format!("toolchains/nightly/bin/rustc{}",
+                                                           EXE_SUFFIX));
+        fs::remove_file(config.homedir.path().join(
+            "toolchains/nightly/lib/rustlib/multirust-config.toml")).unwrap();
+        fs::remove_file(rustc).unwrap();
+
+        expect_stderr_ok(config, &["multirust", "repair", "nightly"],
+                         &format!("repaired component 'rustc-{}'", this_host_triple()));
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        expect_ok(config, &["multirust", "verify", "nightly"]);
+    });
+}
+
+#[test]
+fn update_waits_for_toolchain_lock() {
+    setup(&|config| {
+        let ref lock_path = config.homedir.path().join("locks/toolchain-nightly.lock");
+        let lock = utils::FileLock::acquire("toolchain lock", lock_path,
+                                            multirust_utils::NotifyHandler::none()).unwrap();
+
+        let mut cmd = cmd(config, "multirust", &["update", "nightly"]);
+        let mut child = cmd.stderr(Stdio::piped()).spawn().unwrap();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+
+        // Release the lock once the update says it's waiting for it
+        let ref expected = format!("held by pid {}", utils::process_id());
+        let mut line = String::new();
+        while !line.contains("waiting for lock") {
+            line.clear();
+            assert!(stderr.read_line(&mut line).unwrap() > 0);
+        }
+        assert!(line.contains(expected));
+        drop(lock);
+
+        let mut rest = String::new();
+        while stderr.read_line(&mut rest).unwrap() > 0 { }
+        assert!(child.wait().unwrap().success());
+        expect_stdout_ok(config, &["multirust", "run", "nightly", "rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    let a = raw::file_identity(a).unwrap();
+    let b = raw::file_identity(b).unwrap();
+    a.device == b.device && a.index == b.index
+}
+
+#[test]
+fn dedup_links_identical_files() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_ok(config, &["multirust", "update", "stable"]);
+        expect_stdout_ok(config, &["multirust", "dedup"], "linked ");
+
+        let ref toolchains = config.homedir.path().join("toolchains");
+        let ref doc = Path::new("share/doc/rust/html/index.html");
+        let ref rustc = PathBuf::from(format!("bin/rustc{}", EXE_SUFFIX));
+        assert!(same_file(&toolchains.join("nightly").join(doc),
+                          &toolchains.join("stable").join(doc)));
+        assert!(!same_file(&toolchains.join("nightly").join(rustc),
+                           &toolchains.join("stable").join(rustc)));
+
+        // Everything is already shared the second time
+        expect_stdout_ok(config, &["multirust", "dedup"], "linked 0 of");
+        expect_stdout_ok(config, &["multirust", "run", "stable", "rustc", "--version"],
+                         "hash-s-2");
+    });
+}
+
+#[test]
+fn dedup_removes_unused_files_from_store() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_ok(config, &["multirust", "update", "stable"]);
+        expect_ok(config, &["multirust", "dedup"]);
+        expect_ok(config, &["multirust", "remove-toolchain", "stable"]);
+        expect_stdout_ok(config, &["multirust", "dedup"],
+                         "unused files from the store");
+        expect_stdout_ok(config, &["multirust", "run", "nightly", "rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn update_keeps_files_of_deduplicated_toolchain() {
+    setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_ok(config, &["multirust", "update", "stable"]);
+        expect_ok(config, &["multirust", "dedup"]);
+
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_stdout_ok(config, &["multirust", "run", "nightly", "rustc", "--version"],
+                         "hash-n-2");
+        expect_stdout_ok(config, &["multirust", "run", "stable", "rustc", "--version"],
+                         "hash-s-1");
+        expect_ok(config, &["multirust", "verify", "stable"]);
+    });
+}
+
+#[test]
+fn dedup_on_install() {
+    setup(&|config| {
+        for toolchain in &["nightly", "stable"] {
+            let status = cmd(config, "multirust", &["update", toolchain])
+                             .env("MULTIRUST_DEDUP", "1")
+                             .status().unwrap();
+            assert!(status.success());
+        }
+
+        let ref toolchains = config.homedir.path().join("toolchains");
+        let ref doc = Path::new("share/doc/rust/html/index.html");
+        assert!(same_file(&toolchains.join("nightly").join(doc),
+                          &toolchains.join("stable").join(doc)));
+    });
+}
+
+#[test]
+fn du_shows_toolchains_and_components() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_stdout_ok(config, &["multirust", "du"], "nightly: ");
+        expect_stdout_ok(config, &["multirust", "du"], "    rustc: ");
+        expect_stdout_ok(config, &["multirust", "du"],
+                         &format!("    rust-std-{}: ", this_host_triple()));
+        expect_stdout_ok(config, &["multirust", "du"], "update-hashes: ");
+        expect_stdout_ok(config, &["multirust", "du"], "total: ");
+    });
+}
+
+#[test]
+fn du_json() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_stdout_ok(config, &["multirust", "du", "--json"],
+                         r#""name":"nightly""#);
+        expect_stdout_ok(config, &["multirust", "du", "--json"],
+                         r#""name":"rustc""#);
+        expect_stdout_ok(config, &["multirust", "du", "--json"],
+                         r#""name":"store""#);
+        expect_stdout_ok(config, &["multirust", "du", "--json"],
+                         r#""total_bytes":"#);
+    });
+}
+
+// Measuring only reads the toolchain, so it leaves an interrupted
+// transaction for a command that locks it
+#[test]
+fn du_leaves_interrupted_transaction() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        let ref journal = config.homedir.path().join("toolchains/nightly/.multirust-journal");
+        raw::write_file(journal, "added-file\tbin/bogus\n").unwrap();
+        expect_stderr_ok(config, &["multirust", "du"],
+                         "run `multirust update` or `multirust repair` to recover it");
+        assert!(journal.exists());
+    });
+}
+
+#[test]
+fn du_doesnt_measure_linked_toolchains() {
+    setup(&|config| {
+        let path = config.customdir.path().join("custom-1");
+        let path = path.to_string_lossy();
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &path]);
+        expect_stdout_ok(config, &["multirust", "du"], "custom: linked");
+        expect_stdout_ok(config, &["multirust", "du", "--json"],
+                         r#""linked":true"#);
+    });
+}
+
+#[test]
+fn du_reports_broken_links() {
+    setup(&|config| {
+        let ref src = config.customdir.path().join("source");
+        utils::copy_dir(&config.customdir.path().join("custom-1"), src,
+                        multirust_utils::NotifyHandler::none()).unwrap();
+        expect_ok(config, &["multirust", "default", "custom",
+                            "--link-local", &src.to_string_lossy()]);
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        fs::remove_dir_all(src).unwrap();
+        expect_stdout_ok(config, &["multirust", "du"], "custom: linked");
+        expect_stdout_ok(config, &["multirust", "du"], "nightly: ");
+    });
+}
+
+fn set_last_used(config: &Config, toolchain: &str, time: &str) {
+    let ref path = config.homedir.path().join("last-used").join(toolchain);
+    raw::write_file(path, time).unwrap();
+}
+
+#[test]
+fn gc_removes_unused_toolchains() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "update", "stable"]);
+        let tempdir = TempDir::new("multirust").unwrap();
+        change_dir(tempdir.path(), &|| {
+            expect_ok(config, &["multirust", "override", "beta"]);
+        });
+        for toolchain in &["nightly", "stable", "beta"] {
+            set_last_used(config, toolchain, "0");
+        }
+
+        expect_ok(config, &["multirust", "gc", "--older-than", "30d", "-y"]);
+        let out = run(config, "multirust", &["list-toolchains"], &[]);
+        assert!(out.stdout.contains("nightly"));
+        assert!(out.stdout.contains("beta"));
+        assert!(!out.stdout.contains("stable"));
+    });
+}
+
+#[test]
+fn gc_keeps_recently_used_toolchains() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "stable"]);
+        expect_ok(config, &["multirust", "gc", "--older-than", "30d", "-y"]);
+        expect_stdout_ok(config, &["multirust", "list-toolchains"], "stable");
+
+        set_last_used(config, "stable", "0");
+        expect_ok(config, &["multirust", "run", "stable", "rustc", "--version"]);
+        expect_ok(config, &["multirust", "gc", "--older-than", "30d", "-y"]);
+        expect_stdout_ok(config, &["multirust", "list-toolchains"], "stable");
+    });
+}
+
+#[test]
+fn gc_tracks_toolchains_without_a_record() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "stable"]);
+        let ref record = config.homedir.path().join("last-used/stable");
+        fs::remove_file(record).unwrap();
+
+        expect_ok(config, &["multirust", "gc", "--older-than", "0s", "-y"]);
+        expect_stdout_ok(config, &["multirust", "list-toolchains"], "stable");
+        assert!(record.exists());
+
+        expect_ok(config, &["multirust", "gc", "--older-than", "0s", "-y"]);
+        expect_stdout_ok(config, &["multirust", "list-toolchains"],
+                         "no installed toolchains");
+        assert!(!record.exists());
+    });
+}
+
+// Declining leaves everything as it was, including toolchains whose
+// use isn't recorded yet
+#[test]
+fn gc_declined_changes_nothing() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "stable"]);
+        expect_ok(config, &["multirust", "update", "beta"]);
+        set_last_used(config, "stable", "0");
+        let ref record = config.homedir.path().join("last-used/beta");
+        fs::remove_file(record).unwrap();
+
+        let mut cmd = clitools::cmd(config, "multirust", &["gc", "--older-than", "30d"]);
+        let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+        child.stdin.as_mut().unwrap().write_all(b"n\n").unwrap();
+        assert!(child.wait_with_output().unwrap().status.success());
+
+        expect_stdout_ok(config, &["multirust", "list-toolchains"], "stable");
+        assert!(!record.exists());
+    });
+}
+
+#[test]
+fn gc_bad_duration() {
+    setup(&|config| {
+        expect_err(config, &["multirust", "gc", "--older-than", "30x"],
+                   "invalid duration '30x'");
+    });
+}
+
+// Larger than any pid the test platforms hand out
+const DEAD_PID: u32 = 999999999;
+
+#[test]
+fn clean_tmp_removes_abandoned_entries() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        let ref tmp = config.homedir.path().join("tmp");
+        let now = time::get_time().sec;
+        let pid = utils::process_id();
+
+        let ref dead = tmp.join(format!("{}-{}-dead_file", DEAD_PID, now));
+        let ref live = tmp.join(format!("{}-{}-live_file", pid, now));
+        let ref old = tmp.join(format!("{}-0-old_dir", pid));
+        let ref unowned = tmp.join("unowned_dir");
+        raw::write_file(dead, "").unwrap();
+        raw::write_file(live, "").unwrap();
+        fs::create_dir_all(old.join("nested")).unwrap();
+        fs::create_dir(unowned).unwrap();
+
+        expect_stderr_ok(config, &["multirust", "clean-tmp"],
+                         "removed 3 temporary files and directories");
+        assert!(!dead.exists());
+        assert!(live.exists());
+        assert!(!old.exists());
+        assert!(!unowned.exists());
+    });
+}
+
+#[test]
+fn changing_metadata_cleans_tmp() {
+    setup(&|config| {
+        let ref tmp = config.homedir.path().join("tmp");
+        fs::create_dir_all(tmp).unwrap();
+        let ref dead = tmp.join(format!("{}-{}-dead_file", DEAD_PID, time::get_time().sec));
+        raw::write_file(dead, "").unwrap();
+
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        assert!(!dead.exists());
+    });
+}
+
+// The backups of an interrupted transaction are in the temp
+// directory, and are needed to roll it back
+#[test]
+fn clean_tmp_recovers_interrupted_transactions_first() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        let ref prefix = config.homedir.path().join("toolchains/nightly");
+        let ref rustc = format!("bin/rustc{}", EXE_SUFFIX);
+        let ref backup = config.homedir.path().join("tmp")
+                               .join(format!("{}-0-backup_file", DEAD_PID));
+        fs::rename(prefix.join(rustc), backup).unwrap();
+        raw::write_file(&prefix.join(".multirust-journal"),
+                        &format!("removed-file\t{}\t{}\n", rustc, backup.display())).unwrap();
+
+        expect_ok(config, &["multirust", "clean-tmp"]);
+        assert!(!backup.exists());
+        expect_stdout_ok(config, &["multirust", "run", "nightly", "rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+// Commands that only read a toolchain leave an interrupted
+// transaction for one that takes the toolchain lock
+#[test]
+fn only_locked_commands_recover_interrupted_transactions() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        let ref prefix = config.homedir.path().join("toolchains/nightly");
+        let ref rustc = format!("bin/rustc{}", EXE_SUFFIX);
+        let ref backup = config.homedir.path().join("tmp")
+                               .join(format!("{}-0-backup_file", DEAD_PID));
+        fs::rename(prefix.join(rustc), backup).unwrap();
+        let ref journal = prefix.join(".multirust-journal");
+        raw::write_file(journal,
+                        &format!("removed-file\t{}\t{}\n", rustc, backup.display())).unwrap();
+
+        expect_stderr_ok(config, &["multirust", "list-toolchains", "-v"],
+                         "run `multirust update` or `multirust repair` to recover it");
+        assert!(journal.exists());
+        assert!(backup.exists());
+
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        assert!(!journal.exists());
+        expect_stdout_ok(config, &["multirust", "run", "nightly", "rustc", "--version"],
+                         "hash-n-2");
+    });
+}
+
+#[test]
+fn running_toolchain_recovers_interrupted_transaction() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref prefix = config.homedir.path().join("toolchains/nightly");
+        let ref rustc = format!("bin/rustc{}", EXE_SUFFIX);
+        let ref backup = config.homedir.path().join("tmp")
+                               .join(format!("{}-0-backup_file", DEAD_PID));
+        fs::rename(prefix.join(rustc), backup).unwrap();
+        let ref journal = prefix.join(".multirust-journal");
+        raw::write_file(journal,
+                        &format!("removed-file\t{}\t{}\n", rustc, backup.display())).unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        assert!(!journal.exists());
+        assert!(!backup.exists());
+    });
+}
+
+// Running a toolchain doesn't wait for the toolchain lock, and
+// leaves recovery to whatever holds it
+#[test]
+fn running_locked_toolchain_leaves_interrupted_transaction() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref prefix = config.homedir.path().join("toolchains/nightly");
+        let ref bogus = prefix.join("bin/bogus");
+        raw::write_file(bogus, "").unwrap();
+        let ref journal = prefix.join(".multirust-journal");
+        raw::write_file(journal, "added-file\tbin/bogus\n").unwrap();
+
+        let ref lock_path = config.homedir.path().join("locks/toolchain-nightly.lock");
+        let lock = utils::FileLock::acquire("toolchain lock", lock_path,
+                                            multirust_utils::NotifyHandler::none()).unwrap();
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        assert!(journal.exists());
+        assert!(bogus.exists());
+        drop(lock);
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        assert!(!journal.exists());
+        assert!(!bogus.exists());
+    });
+}
+
+#[test]
+fn list_toolchains_verbose() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let out = run(config, "multirust", &["list-toolchains", "-v"], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains("nightly (default)"));
+        assert!(out.stdout.contains("    kind: dist"));
+        assert!(out.stdout.contains("    date: 2015-01-02"));
+        assert!(out.stdout.contains("    version: 1.3.0"));
+        assert!(out.stdout.contains(&format!("    targets: {}", this_host_triple())));
+        assert!(out.stdout.contains("    size: "));
+    });
+}
+
+// A toolchain whose metadata can't be read is listed as broken, and
+// doesn't stop the others being listed
+#[test]
+fn list_toolchains_verbose_with_unreadable_metadata() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "update", "nightly"]);
+        expect_ok(config, &["multirust", "update", "stable"]);
+        let ref dist_config = config.homedir.path()
+                                    .join("toolchains/nightly/lib/rustlib/multirust-config.toml");
+        raw::write_file(dist_config, "bogus").unwrap();
+
+        let out = run(config, "multirust", &["list-toolchains", "-v"], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains("nightly (broken)"));
+        assert!(out.stdout.contains("    error: "));
+        assert!(out.stdout.contains("stable\n"));
+        assert!(out.stdout.contains("    version: 1.1.0"));
+    });
+}
+
+#[test]
+fn list_toolchains_verbose_marks_override() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        expect_ok(config, &["multirust", "update", "stable"]);
+        let out = run(config, "multirust", &["list-toolchains", "-v"],
+                      &[("MULTIRUST_TOOLCHAIN", "stable")]);
+        assert!(out.ok);
+        assert!(out.stdout.contains("nightly (default)"));
+        assert!(out.stdout.contains("stable (override)"));
+    });
+}
+
+#[test]
+fn list_toolchains_json() {
+    setup(&|config| {
+        expect_ok(config, &["multirust", "default", "nightly"]);
+        let ref path = config.customdir.path().join("custom-1");
+        expect_ok(config, &["multirust", "update", "custom",
+                            "--link-local", &path.to_string_lossy()]);
+        let out = run(config, "multirust", &["list-toolchains", "--json"], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains(r#""name":"nightly""#));
+        assert!(out.stdout.contains(r#""date":"2015-01-02""#));
+        assert!(out.stdout.contains(r#""version":"1.3.0 (hash-n-2)""#));
+        assert!(out.stdout.contains(&format!(r#""targets":["{}"]"#, this_host_triple())));
+        assert!(out.stdout.contains(r#""default":true"#));
+        assert!(out.stdout.contains(r#""kind":"linked""#));
+        assert!(out.stdout.contains(r#""bytes":null"#));
+        assert!(out.stdout.contains(r#""source":{"kind":"link""#));
+    });
+}